@@ -0,0 +1,246 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+
+use bytes::Bytes;
+use futures::future::{self, Either};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Chunk, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio::io::AsyncWrite;
+use typed_headers::mime::{self, Mime};
+use typed_headers::{ContentType, HeaderMapExt};
+
+/// The most the helpers read into memory unless told otherwise, 1 MiB.
+pub const DEFAULT_LIMIT: u64 = 1024 * 1024;
+
+/// Deserializes a JSON request body of up to [`DEFAULT_LIMIT`] bytes.
+///
+/// Like the other helpers here, it fails with the response the request
+/// should be answered with, so a handler can pass it on as is: a `415
+/// Unsupported Media Type` if the body isn't declared to be JSON, a `413
+/// Payload Too Large` if it's over the limit and a `400 Bad Request`
+/// describing the problem if it can't be deserialized.
+pub fn json<T>(req: Request<Body>) -> impl Future<Item = T, Error = Response<Body>> + Send
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    json_limited(req, DEFAULT_LIMIT)
+}
+
+/// Like [`json`], but for bodies of up to `limit` bytes.
+pub fn json_limited<T>(
+    req: Request<Body>,
+    limit: u64,
+) -> impl Future<Item = T, Error = Response<Body>> + Send
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let is_json = content_type(&req).map_or(false, |content_type| {
+        content_type.type_() == mime::APPLICATION
+            && (content_type.subtype() == mime::JSON || content_type.suffix() == Some(mime::JSON))
+    });
+    if !is_json {
+        return Either::A(future::err(rejection(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            None,
+        )));
+    }
+
+    Either::B(aggregate(req, limit).and_then(|bytes| {
+        serde_json::from_slice(&bytes)
+            .map_err(|err| rejection(StatusCode::BAD_REQUEST, Some(err.to_string())))
+    }))
+}
+
+/// Deserializes an `application/x-www-form-urlencoded` request body of up
+/// to [`DEFAULT_LIMIT`] bytes, failing like [`json`] does.
+pub fn form<T>(req: Request<Body>) -> impl Future<Item = T, Error = Response<Body>> + Send
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    form_limited(req, DEFAULT_LIMIT)
+}
+
+/// Like [`form`], but for bodies of up to `limit` bytes.
+pub fn form_limited<T>(
+    req: Request<Body>,
+    limit: u64,
+) -> impl Future<Item = T, Error = Response<Body>> + Send
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let is_form = content_type(&req).map_or(false, |content_type| {
+        content_type.type_() == mime::APPLICATION
+            && content_type.subtype() == mime::WWW_FORM_URLENCODED
+    });
+    if !is_form {
+        return Either::A(future::err(rejection(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            None,
+        )));
+    }
+
+    Either::B(aggregate(req, limit).and_then(|bytes| {
+        serde_urlencoded::from_bytes(&bytes)
+            .map_err(|err| rejection(StatusCode::BAD_REQUEST, Some(err.to_string())))
+    }))
+}
+
+/// The body of `req` as a stream of chunks, read from the connection only
+/// as they're polled for, so that large bodies can be handled without
+/// holding them in memory.
+pub fn chunks(req: Request<Body>) -> impl Stream<Item = Bytes, Error = hyper::Error> + Send {
+    req.into_body().map(Chunk::into_bytes)
+}
+
+/// Writes the body of `req` to `writer` as it's read, resolving to the
+/// number of bytes written along with the writer. Each chunk is only read
+/// once the one before it has been written, so a slow writer slows down
+/// reading rather than letting chunks pile up.
+pub fn copy_to<W>(
+    req: Request<Body>,
+    writer: W,
+) -> impl Future<Item = (u64, W), Error = io::Error> + Send
+where
+    W: AsyncWrite + Send + 'static,
+{
+    req.into_body()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        .fold((0, writer), |(written, writer), chunk| {
+            let length = chunk.len() as u64;
+            tokio::io::write_all(writer, chunk).map(move |(writer, _)| (written + length, writer))
+        })
+}
+
+pub(crate) fn content_type(req: &Request<Body>) -> Option<Mime> {
+    match req.headers().typed_get::<ContentType>() {
+        Ok(Some(ContentType(content_type))) => Some(content_type),
+        _ => None,
+    }
+}
+
+/// Reads a whole body into memory, failing with
+/// [`ToBytesError::TooLarge`] as soon as it's known to be longer than
+/// `limit`, without reading any more of it.
+pub fn to_bytes_limited(
+    body: Body,
+    limit: u64,
+) -> impl Future<Item = Bytes, Error = ToBytesError> + Send {
+    if body.content_length().map_or(false, |length| length > limit) {
+        return Either::A(future::err(ToBytesError::TooLarge { limit }));
+    }
+
+    Either::B(
+        body.map_err(ToBytesError::Body)
+            .fold(Vec::new(), move |mut bytes, chunk| {
+                if (bytes.len() + chunk.len()) as u64 > limit {
+                    return Err(ToBytesError::TooLarge { limit });
+                }
+                bytes.extend_from_slice(&chunk);
+                Ok(bytes)
+            })
+            .map(Bytes::from),
+    )
+}
+
+/// Like [`to_bytes_limited`], but also resolves to the trailers sent after
+/// the body, if there are any.
+///
+/// Note that trailers are only received over HTTP/2: hyper reads those of
+/// chunked HTTP/1.1 requests but doesn't pass them on. Responses are given
+/// trailers with [`reply::stream_with_trailers`](crate::reply::stream_with_trailers),
+/// which likewise only sends them over HTTP/2.
+pub fn to_bytes_with_trailers(
+    body: Body,
+    limit: u64,
+) -> impl Future<Item = (Bytes, Option<HeaderMap>), Error = ToBytesError> + Send {
+    WithTrailers {
+        body,
+        bytes: Vec::new(),
+        limit,
+    }
+}
+
+struct WithTrailers {
+    body: Body,
+    bytes: Vec<u8>,
+    limit: u64,
+}
+
+impl Future for WithTrailers {
+    type Item = (Bytes, Option<HeaderMap>);
+    type Error = ToBytesError;
+
+    fn poll(&mut self) -> Poll<Self::Item, ToBytesError> {
+        while let Some(chunk) = try_ready!(self.body.poll_data().map_err(ToBytesError::Body)) {
+            if (self.bytes.len() + chunk.len()) as u64 > self.limit {
+                return Err(ToBytesError::TooLarge { limit: self.limit });
+            }
+            self.bytes.extend_from_slice(&chunk);
+        }
+
+        let trailers = try_ready!(self.body.poll_trailers().map_err(ToBytesError::Body));
+        let bytes = mem::replace(&mut self.bytes, Vec::new());
+        Ok(Async::Ready((Bytes::from(bytes), trailers)))
+    }
+}
+
+/// The error [`to_bytes_limited`] fails with.
+#[derive(Debug)]
+pub enum ToBytesError {
+    /// The body is longer than the limit.
+    TooLarge { limit: u64 },
+    /// The body couldn't be read.
+    Body(hyper::Error),
+}
+
+impl fmt::Display for ToBytesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToBytesError::TooLarge { limit } => write!(f, "body is longer than {} bytes", limit),
+            ToBytesError::Body(err) => write!(f, "failed to read body: {}", err),
+        }
+    }
+}
+
+impl Error for ToBytesError {}
+
+/// A `413 Payload Too Large` for bodies over the limit, or a `400 Bad
+/// Request` for those that couldn't be read.
+impl From<ToBytesError> for Response<Body> {
+    fn from(err: ToBytesError) -> Self {
+        match err {
+            ToBytesError::TooLarge { .. } => rejection(StatusCode::PAYLOAD_TOO_LARGE, None),
+            ToBytesError::Body(_) => rejection(StatusCode::BAD_REQUEST, Some(err.to_string())),
+        }
+    }
+}
+
+fn aggregate(
+    req: Request<Body>,
+    limit: u64,
+) -> impl Future<Item = Bytes, Error = Response<Body>> + Send {
+    to_bytes_limited(req.into_body(), limit).map_err(Response::from)
+}
+
+/// A response with `status`, explaining the problem in plain text if given
+/// a `message`.
+pub(crate) fn rejection(status: StatusCode, message: Option<String>) -> Response<Body> {
+    let mut response = match message {
+        Some(message) => {
+            let mut response = Response::new(Body::from(message));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            response
+        }
+        None => Response::new(Body::empty()),
+    };
+    *response.status_mut() = status;
+    response
+}
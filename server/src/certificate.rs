@@ -0,0 +1,474 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use sha2::{Digest, Sha256};
+
+/// The certificate chain a client authenticated with over TLS, starting
+/// with the client's own certificate, as found in the request extensions
+/// of requests received over connections that presented one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertificates(pub Vec<Certificate>);
+
+impl ClientCertificates {
+    /// The client's own certificate.
+    pub fn leaf(&self) -> Option<&Certificate> {
+        self.0.first()
+    }
+}
+
+/// A name a certificate is valid for, besides its subject.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AltName {
+    Dns(String),
+    Email(String),
+    Uri(String),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for AltName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AltName::Dns(name) => write!(f, "DNS:{}", name),
+            AltName::Email(email) => write!(f, "email:{}", email),
+            AltName::Uri(uri) => write!(f, "URI:{}", uri),
+            AltName::Ip(ip) => write!(f, "IP:{}", ip),
+        }
+    }
+}
+
+/// An X.509 certificate, with the details needed to tell who it belongs
+/// to read from it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Certificate {
+    der: Vec<u8>,
+    subject: String,
+    alt_names: Vec<AltName>,
+}
+
+impl Certificate {
+    /// Reads a DER encoded certificate, returning `None` if it's malformed.
+    pub fn from_der(der: Vec<u8>) -> Option<Self> {
+        let (subject, alt_names) = parse(&der)?;
+        Some(Certificate {
+            der,
+            subject,
+            alt_names,
+        })
+    }
+
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// The subject's distinguished name, with its attributes in the order
+    /// they appear in the certificate, e.g. `CN=device-1, O=Contoso`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// The certificate's subject alternative names.
+    pub fn alt_names(&self) -> &[AltName] {
+        &self.alt_names
+    }
+
+    /// The SHA-256 fingerprint of the certificate, as colon separated
+    /// pairs of upper case hex digits.
+    pub fn fingerprint(&self) -> String {
+        Sha256::digest(&self.der)
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const VERSION: u8 = 0xa0;
+const EXTENSIONS: u8 = 0xa3;
+const BOOLEAN: u8 = 0x01;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const BMP_STRING: u8 = 0x1e;
+
+const EMAIL_NAME: u8 = 0x81;
+const DNS_NAME: u8 = 0x82;
+const URI_NAME: u8 = 0x86;
+const IP_ADDRESS: u8 = 0x87;
+
+/// `2.5.29.17`, the subject alternative name extension.
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// A reader of DER encoded values.
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    /// Reads the next value's tag and contents.
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let tag = *self.0.get(0)?;
+        let first = *self.0.get(1)?;
+        let (length, header) = if first < 0x80 {
+            (usize::from(first), 2)
+        } else {
+            let count = usize::from(first & 0x7f);
+            if count == 0 || count > 4 {
+                return None;
+            }
+            let bytes = self.0.get(2..2 + count)?;
+            let length = bytes
+                .iter()
+                .fold(0, |length, &byte| (length << 8) | usize::from(byte));
+            (length, 2 + count)
+        };
+
+        let contents = self.0.get(header..header + length)?;
+        self.0 = &self.0[header + length..];
+        Some((tag, contents))
+    }
+
+    /// Reads the next value, which has to have `tag`.
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.next()? {
+            (actual, contents) if actual == tag => Some(contents),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Reads the subject and alternative names of a certificate.
+fn parse(der: &[u8]) -> Option<(String, Vec<AltName>)> {
+    let certificate = Der(der).expect(SEQUENCE)?;
+    let mut tbs = Der(Der(certificate).expect(SEQUENCE)?);
+
+    let (tag, _) = tbs.next()?;
+    if tag == VERSION {
+        // That was the version, which comes before the serial number.
+        tbs.next()?;
+    }
+    let _signature = tbs.expect(SEQUENCE)?;
+    let _issuer = tbs.expect(SEQUENCE)?;
+    let _validity = tbs.expect(SEQUENCE)?;
+    let subject = name(tbs.expect(SEQUENCE)?)?;
+    let _public_key = tbs.expect(SEQUENCE)?;
+
+    let mut alt_names = vec![];
+    while !tbs.is_empty() {
+        let (tag, contents) = tbs.next()?;
+        if tag == EXTENSIONS {
+            alt_names = extensions(contents)?;
+        }
+    }
+    Some((subject, alt_names))
+}
+
+/// Formats a distinguished name.
+fn name(der: &[u8]) -> Option<String> {
+    let mut names = Der(der);
+    let mut formatted = vec![];
+    while !names.is_empty() {
+        let mut set = Der(names.expect(SET)?);
+        let mut attributes = vec![];
+        while !set.is_empty() {
+            let mut attribute = Der(set.expect(SEQUENCE)?);
+            let oid = attribute.expect(OBJECT_IDENTIFIER)?;
+            let (tag, value) = attribute.next()?;
+            attributes.push(format!("{}={}", attribute_name(oid), string(tag, value)));
+        }
+        formatted.push(attributes.join("+"));
+    }
+    Some(formatted.join(", "))
+}
+
+fn attribute_name(oid: &[u8]) -> String {
+    let name = match oid {
+        [0x55, 0x04, 0x03] => "CN",
+        [0x55, 0x04, 0x05] => "serialNumber",
+        [0x55, 0x04, 0x06] => "C",
+        [0x55, 0x04, 0x07] => "L",
+        [0x55, 0x04, 0x08] => "ST",
+        [0x55, 0x04, 0x09] => "STREET",
+        [0x55, 0x04, 0x0a] => "O",
+        [0x55, 0x04, 0x0b] => "OU",
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19] => "DC",
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress",
+        _ => return dotted(oid),
+    };
+    name.to_string()
+}
+
+/// Formats an object identifier in dotted decimal.
+fn dotted(oid: &[u8]) -> String {
+    let mut arcs = vec![];
+    let mut arc: u64 = 0;
+    for &byte in oid {
+        arc = (arc << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Decodes a string value, which is UTF-16 for BMP strings and treated as
+/// UTF-8 otherwise, as UTF-8 is a superset of the other string types
+/// certificates use.
+fn string(tag: u8, value: &[u8]) -> String {
+    if tag == BMP_STRING {
+        let units: Vec<u16> = value
+            .chunks(2)
+            .map(|pair| (u16::from(pair[0]) << 8) | u16::from(*pair.get(1).unwrap_or(&0)))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(value).into_owned()
+    }
+}
+
+/// Reads the subject alternative names out of a certificate's extensions.
+fn extensions(der: &[u8]) -> Option<Vec<AltName>> {
+    let mut extensions = Der(Der(der).expect(SEQUENCE)?);
+    while !extensions.is_empty() {
+        let mut extension = Der(extensions.expect(SEQUENCE)?);
+        if extension.expect(OBJECT_IDENTIFIER)? != SUBJECT_ALT_NAME {
+            continue;
+        }
+        let (mut tag, mut value) = extension.next()?;
+        if tag == BOOLEAN {
+            // That was whether the extension is critical.
+            let (next_tag, next_value) = extension.next()?;
+            tag = next_tag;
+            value = next_value;
+        }
+        if tag != OCTET_STRING {
+            return None;
+        }
+        return alt_names(value);
+    }
+    Some(vec![])
+}
+
+fn alt_names(der: &[u8]) -> Option<Vec<AltName>> {
+    let mut names = Der(Der(der).expect(SEQUENCE)?);
+    let mut alt_names = vec![];
+    while !names.is_empty() {
+        let (tag, value) = names.next()?;
+        let text = || String::from_utf8_lossy(value).into_owned();
+        let alt_name = match tag {
+            EMAIL_NAME => AltName::Email(text()),
+            DNS_NAME => AltName::Dns(text()),
+            URI_NAME => AltName::Uri(text()),
+            IP_ADDRESS if value.len() == 4 => {
+                AltName::Ip(Ipv4Addr::new(value[0], value[1], value[2], value[3]).into())
+            }
+            IP_ADDRESS if value.len() == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(value);
+                AltName::Ip(Ipv6Addr::from(octets).into())
+            }
+            // Other kinds of names, such as directory names, are skipped.
+            _ => continue,
+        };
+        alt_names.push(alt_name);
+    }
+    Some(alt_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UTF8_STRING: u8 = 0x0c;
+    const PRINTABLE_STRING: u8 = 0x13;
+    const INTEGER: u8 = 0x02;
+    const BIT_STRING: u8 = 0x03;
+
+    const CN: &[u8] = &[0x55, 0x04, 0x03];
+    const O: &[u8] = &[0x55, 0x04, 0x0a];
+    const OU: &[u8] = &[0x55, 0x04, 0x0b];
+    const GIVEN_NAME: &[u8] = &[0x55, 0x04, 0x2a];
+
+    /// Encodes a value with `tag` and `contents`.
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut der = vec![tag];
+        let length = contents.len();
+        if length < 0x80 {
+            der.push(length as u8);
+        } else if length <= 0xff {
+            der.extend_from_slice(&[0x81, length as u8]);
+        } else {
+            der.extend_from_slice(&[0x82, (length >> 8) as u8, length as u8]);
+        }
+        der.extend_from_slice(contents);
+        der
+    }
+
+    fn seq(values: &[Vec<u8>]) -> Vec<u8> {
+        tlv(SEQUENCE, &values.concat())
+    }
+
+    fn attribute(oid: &[u8], tag: u8, value: &[u8]) -> Vec<u8> {
+        seq(&[tlv(OBJECT_IDENTIFIER, oid), tlv(tag, value)])
+    }
+
+    fn certificate(version: bool, subject: Vec<u8>, extensions: Option<Vec<u8>>) -> Vec<u8> {
+        let mut tbs = vec![];
+        if version {
+            tbs.push(tlv(VERSION, &tlv(INTEGER, &[2])));
+        }
+        tbs.push(tlv(INTEGER, &[1]));
+        tbs.push(seq(&[]));
+        tbs.push(seq(&[]));
+        tbs.push(seq(&[]));
+        tbs.push(subject);
+        tbs.push(seq(&[]));
+        if let Some(extensions) = extensions {
+            tbs.push(tlv(EXTENSIONS, &extensions));
+        }
+        seq(&[seq(&tbs), seq(&[]), tlv(BIT_STRING, &[0])])
+    }
+
+    fn subject() -> Vec<u8> {
+        seq(&[
+            tlv(SET, &attribute(CN, UTF8_STRING, b"device-1")),
+            tlv(SET, &attribute(O, PRINTABLE_STRING, b"Contoso")),
+        ])
+    }
+
+    fn alt_name_extension(critical: bool, names: &[Vec<u8>]) -> Vec<u8> {
+        let mut extension = vec![tlv(OBJECT_IDENTIFIER, SUBJECT_ALT_NAME)];
+        if critical {
+            extension.push(tlv(BOOLEAN, &[0xff]));
+        }
+        extension.push(tlv(OCTET_STRING, &seq(names)));
+        seq(&[seq(&extension)])
+    }
+
+    #[test]
+    fn subjects_are_formatted_in_order() {
+        let certificate = Certificate::from_der(certificate(true, subject(), None)).expect("valid");
+        assert_eq!(certificate.subject(), "CN=device-1, O=Contoso");
+        assert!(certificate.alt_names().is_empty());
+    }
+
+    #[test]
+    fn the_version_is_optional() {
+        let certificate =
+            Certificate::from_der(certificate(false, subject(), None)).expect("valid");
+        assert_eq!(certificate.subject(), "CN=device-1, O=Contoso");
+    }
+
+    #[test]
+    fn multi_valued_and_unknown_attributes_are_formatted() {
+        let subject = seq(&[
+            tlv(
+                SET,
+                &[
+                    attribute(CN, UTF8_STRING, b"a"),
+                    attribute(OU, UTF8_STRING, b"b"),
+                ]
+                .concat(),
+            ),
+            tlv(
+                SET,
+                &attribute(GIVEN_NAME, BMP_STRING, &[0x00, 0x68, 0x00, 0x69]),
+            ),
+        ]);
+        let certificate = Certificate::from_der(certificate(true, subject, None)).expect("valid");
+        assert_eq!(certificate.subject(), "CN=a+OU=b, 2.5.4.42=hi");
+    }
+
+    #[test]
+    fn alt_names_are_read() {
+        let long_name = "a".repeat(300);
+        let names = [
+            tlv(DNS_NAME, b"example.com"),
+            tlv(EMAIL_NAME, b"a@example.com"),
+            tlv(URI_NAME, b"urn:device:1"),
+            tlv(IP_ADDRESS, &[127, 0, 0, 1]),
+            tlv(IP_ADDRESS, &Ipv6Addr::LOCALHOST.octets()),
+            tlv(0xa4, &seq(&[])),
+            tlv(DNS_NAME, long_name.as_bytes()),
+        ];
+
+        for &critical in &[false, true] {
+            let der = certificate(true, subject(), Some(alt_name_extension(critical, &names)));
+            let certificate = Certificate::from_der(der).expect("valid");
+            assert_eq!(
+                certificate.alt_names(),
+                &[
+                    AltName::Dns("example.com".to_string()),
+                    AltName::Email("a@example.com".to_string()),
+                    AltName::Uri("urn:device:1".to_string()),
+                    AltName::Ip(Ipv4Addr::LOCALHOST.into()),
+                    AltName::Ip(Ipv6Addr::LOCALHOST.into()),
+                    AltName::Dns(long_name.clone()),
+                ][..]
+            );
+        }
+    }
+
+    #[test]
+    fn other_extensions_are_skipped() {
+        let basic_constraints = seq(&[
+            tlv(OBJECT_IDENTIFIER, &[0x55, 0x1d, 0x13]),
+            tlv(OCTET_STRING, &seq(&[])),
+        ]);
+        let alt_names = alt_name_extension(false, &[tlv(DNS_NAME, b"example.com")]);
+        // Both extensions in one list, the unrelated one first.
+        let mut alt_names = Der(&alt_names);
+        let alt_name = alt_names.expect(SEQUENCE).expect("a list");
+        let extensions = seq(&[basic_constraints, alt_name.to_vec()]);
+
+        let der = certificate(true, subject(), Some(extensions));
+        let certificate = Certificate::from_der(der).expect("valid");
+        assert_eq!(
+            certificate.alt_names(),
+            &[AltName::Dns("example.com".to_string())][..]
+        );
+    }
+
+    #[test]
+    fn malformed_certificates_are_rejected() {
+        let der = certificate(true, subject(), None);
+        assert_eq!(Certificate::from_der(der[..der.len() - 1].to_vec()), None);
+        assert_eq!(Certificate::from_der(vec![]), None);
+        assert_eq!(Certificate::from_der(vec![SET, 0]), None);
+        // Indefinite lengths aren't allowed in DER.
+        assert_eq!(Certificate::from_der(vec![SEQUENCE, 0x80, 0, 0]), None);
+    }
+
+    #[test]
+    fn fingerprints_are_colon_separated_hex() {
+        let certificate = Certificate::from_der(certificate(true, subject(), None)).expect("valid");
+        let fingerprint = certificate.fingerprint();
+        let pairs: Vec<_> = fingerprint.split(':').collect();
+        assert_eq!(pairs.len(), 32);
+        assert!(pairs.iter().all(|pair| pair.len() == 2
+            && pair
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_lowercase())));
+    }
+
+    #[test]
+    fn alt_names_display_with_their_kind() {
+        assert_eq!(AltName::Dns("a.com".to_string()).to_string(), "DNS:a.com");
+        assert_eq!(
+            AltName::Ip(Ipv4Addr::LOCALHOST.into()).to_string(),
+            "IP:127.0.0.1"
+        );
+    }
+}
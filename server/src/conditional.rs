@@ -0,0 +1,450 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::header::{
+    HeaderName, HeaderValue, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+    IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+/// An entity tag, identifying a version of a resource.
+///
+/// Strong tags change whenever the resource's representation does, while
+/// weak ones only promise that versions sharing a tag are equivalent.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+impl ETag {
+    /// Panics if `tag` contains a double quote or characters not allowed
+    /// in a header.
+    pub fn strong(tag: &str) -> Self {
+        ETag::new(tag, false)
+    }
+
+    pub fn weak(tag: &str) -> Self {
+        ETag::new(tag, true)
+    }
+
+    fn new(tag: &str, weak: bool) -> Self {
+        if !tag.bytes().all(is_etag_char) {
+            panic!("invalid entity tag {:?}", tag);
+        }
+        ETag {
+            tag: tag.to_string(),
+            weak,
+        }
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Whether both tags are strong and the same.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Whether the tags are the same, ignoring whether they're weak.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// The error returned when parsing a malformed entity tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidETag;
+
+impl fmt::Display for InvalidETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid entity tag")
+    }
+}
+
+impl Error for InvalidETag {}
+
+impl FromStr for ETag {
+    type Err = InvalidETag;
+
+    /// Parses a tag as it appears in headers, e.g. `W/"abc"`.
+    fn from_str(s: &str) -> Result<Self, InvalidETag> {
+        let s = s.trim();
+        let (weak, quoted) = if s.starts_with("W/") {
+            (true, &s[2..])
+        } else {
+            (false, s)
+        };
+        if quoted.len() < 2 || !quoted.starts_with('"') || !quoted.ends_with('"') {
+            return Err(InvalidETag);
+        }
+
+        let tag = &quoted[1..quoted.len() - 1];
+        if tag.bytes().all(is_etag_char) {
+            Ok(ETag {
+                tag: tag.to_string(),
+                weak,
+            })
+        } else {
+            Err(InvalidETag)
+        }
+    }
+}
+
+fn is_etag_char(byte: u8) -> bool {
+    byte == 0x21 || (byte >= 0x23 && byte != 0x7f)
+}
+
+/// What the client asked for in an `If-Match` or `If-None-Match` header.
+enum Condition {
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl Condition {
+    fn parse(value: &str) -> Condition {
+        if value.trim() == "*" {
+            Condition::Any
+        } else {
+            Condition::Tags(
+                value
+                    .split(',')
+                    .filter_map(|tag| tag.parse().ok())
+                    .collect(),
+            )
+        }
+    }
+
+    fn matches(&self, etag: Option<&ETag>, eq: fn(&ETag, &ETag) -> bool) -> bool {
+        match (self, etag) {
+            (Condition::Any, _) => true,
+            (Condition::Tags(tags), Some(etag)) => tags.iter().any(|tag| eq(tag, etag)),
+            (Condition::Tags(_), None) => false,
+        }
+    }
+}
+
+/// Evaluates the conditional headers of `req` against the current version
+/// of the resource it's for, with the precedence RFC 7232 gives them.
+///
+/// Returns a `412 Precondition Failed` when `If-Match` or
+/// `If-Unmodified-Since` doesn't hold, and a `304 Not Modified` when the
+/// client's copy of the resource is still fresh according to
+/// `If-None-Match` or `If-Modified-Since`, or `None` if the request should
+/// be handled as usual. `If-Match` uses the strong comparison and
+/// `If-None-Match` the weak one, and dates are compared to the second.
+pub fn evaluate(
+    req: &Request<Body>,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> Option<Response<Body>> {
+    let is_read = *req.method() == Method::GET || *req.method() == Method::HEAD;
+
+    if let Some(condition) = header(req, IF_MATCH).map(Condition::parse) {
+        if !condition.matches(etag, ETag::strong_eq) {
+            return Some(precondition_failed());
+        }
+    } else if let (Some(since), Some(modified)) = (date(req, IF_UNMODIFIED_SINCE), last_modified) {
+        if truncate(modified) > since {
+            return Some(precondition_failed());
+        }
+    }
+
+    if let Some(condition) = header(req, IF_NONE_MATCH).map(Condition::parse) {
+        if condition.matches(etag, ETag::weak_eq) {
+            return Some(if is_read {
+                not_modified(etag, last_modified)
+            } else {
+                precondition_failed()
+            });
+        }
+    } else if let (true, Some(since), Some(modified)) =
+        (is_read, date(req, IF_MODIFIED_SINCE), last_modified)
+    {
+        if truncate(modified) <= since {
+            return Some(not_modified(etag, last_modified));
+        }
+    }
+
+    None
+}
+
+/// Whether the `Range` header of `req` should be honored, which its
+/// `If-Range` header makes conditional on the client's partial copy being
+/// of the current version of the resource. The tag is compared with the
+/// strong comparison, and a date has to match `last_modified` exactly.
+/// Requests without an `If-Range` header always pass.
+pub fn if_range(
+    req: &Request<Body>,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    let value = match header(req, IF_RANGE) {
+        Some(value) => value,
+        None => return true,
+    };
+
+    if let Ok(tag) = value.parse::<ETag>() {
+        return etag.map_or(false, |etag| tag.strong_eq(etag));
+    }
+    match (httpdate::parse_http_date(value), last_modified) {
+        (Ok(date), Some(modified)) => truncate(modified) == date,
+        _ => false,
+    }
+}
+
+/// A `304 Not Modified` response, carrying the validators of the current
+/// version of the resource.
+pub fn not_modified(etag: Option<&ETag>, last_modified: Option<SystemTime>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+    set_validators(&mut response, etag, last_modified);
+    response
+}
+
+/// Sets the `ETag` and `Last-Modified` headers of `response`.
+pub fn set_validators(
+    response: &mut Response<Body>,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) {
+    if let Some(etag) = etag {
+        let value = HeaderValue::from_str(&etag.to_string()).expect("checked when created");
+        response.headers_mut().insert(ETAG, value);
+    }
+    if let Some(last_modified) = last_modified {
+        let value = HeaderValue::from_str(&httpdate::fmt_http_date(last_modified))
+            .expect("dates are valid header values");
+        response.headers_mut().insert(LAST_MODIFIED, value);
+    }
+}
+
+fn precondition_failed() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::PRECONDITION_FAILED;
+    response
+}
+
+fn header(req: &Request<Body>, name: HeaderName) -> Option<&str> {
+    req.headers().get(name)?.to_str().ok()
+}
+
+fn date(req: &Request<Body>, name: HeaderName) -> Option<SystemTime> {
+    httpdate::parse_http_date(header(req, name)?).ok()
+}
+
+/// Drops the fractions of a second HTTP dates can't represent.
+fn truncate(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs()),
+        Err(_) => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, headers: &[(HeaderName, &str)]) -> Request<Body> {
+        let mut req = Request::new(Body::empty());
+        *req.method_mut() = method;
+        for (name, value) in headers {
+            req.headers_mut()
+                .insert(name.clone(), HeaderValue::from_str(value).expect("valid"));
+        }
+        req
+    }
+
+    fn status(response: Option<Response<Body>>) -> Option<StatusCode> {
+        response.map(|response| response.status())
+    }
+
+    fn modified() -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(1_000_000_000_500)
+    }
+
+    fn http_date(seconds: u64) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn etags_parse_as_they_appear_in_headers() {
+        assert_eq!("\"abc\"".parse(), Ok(ETag::strong("abc")));
+        assert_eq!(" W/\"abc\" ".parse(), Ok(ETag::weak("abc")));
+        assert_eq!("\"\"".parse(), Ok(ETag::strong("")));
+        assert_eq!("abc".parse::<ETag>(), Err(InvalidETag));
+        assert_eq!("\"a\"b\"".parse::<ETag>(), Err(InvalidETag));
+        assert_eq!("w/\"abc\"".parse::<ETag>(), Err(InvalidETag));
+        assert_eq!(ETag::weak("abc").to_string(), "W/\"abc\"");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid entity tag")]
+    fn etags_with_quotes_panic() {
+        ETag::strong("a\"b");
+    }
+
+    #[test]
+    fn strong_comparison_rejects_weak_tags() {
+        let strong = ETag::strong("v1");
+        let weak = ETag::weak("v1");
+        assert!(strong.strong_eq(&strong));
+        assert!(!strong.strong_eq(&weak));
+        assert!(strong.weak_eq(&weak));
+        assert!(!strong.weak_eq(&ETag::strong("v2")));
+    }
+
+    #[test]
+    fn requests_without_conditions_pass() {
+        let req = request(Method::GET, &[]);
+        assert_eq!(
+            status(evaluate(&req, Some(&ETag::strong("v1")), None)),
+            None
+        );
+    }
+
+    #[test]
+    fn if_match_uses_the_strong_comparison() {
+        let etag = ETag::strong("v1");
+        let req = request(Method::PUT, &[(IF_MATCH, "\"v0\", \"v1\"")]);
+        assert_eq!(status(evaluate(&req, Some(&etag), None)), None);
+
+        let req = request(Method::PUT, &[(IF_MATCH, "W/\"v1\"")]);
+        assert_eq!(
+            status(evaluate(&req, Some(&etag), None)),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+
+        let req = request(Method::PUT, &[(IF_MATCH, "*")]);
+        assert_eq!(status(evaluate(&req, None, None)), None);
+
+        let req = request(Method::PUT, &[(IF_MATCH, "\"v1\"")]);
+        assert_eq!(
+            status(evaluate(&req, None, None)),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+    }
+
+    #[test]
+    fn if_none_match_uses_the_weak_comparison() {
+        let etag = ETag::strong("v1");
+        let req = request(Method::GET, &[(IF_NONE_MATCH, "W/\"v1\"")]);
+        assert_eq!(
+            status(evaluate(&req, Some(&etag), None)),
+            Some(StatusCode::NOT_MODIFIED)
+        );
+
+        let req = request(Method::GET, &[(IF_NONE_MATCH, "\"v0\"")]);
+        assert_eq!(status(evaluate(&req, Some(&etag), None)), None);
+
+        let req = request(Method::PUT, &[(IF_NONE_MATCH, "*")]);
+        assert_eq!(
+            status(evaluate(&req, Some(&etag), None)),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+    }
+
+    #[test]
+    fn not_modified_carries_the_validators() {
+        let etag = ETag::strong("v1");
+        let req = request(Method::HEAD, &[(IF_NONE_MATCH, "\"v1\"")]);
+        let response = evaluate(&req, Some(&etag), Some(modified())).expect("not modified");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[ETAG], "\"v1\"");
+        assert_eq!(
+            response.headers()[LAST_MODIFIED],
+            http_date(1_000_000_000).as_str()
+        );
+    }
+
+    #[test]
+    fn dates_are_compared_to_the_second() {
+        let since = http_date(1_000_000_000);
+        let req = request(Method::GET, &[(IF_MODIFIED_SINCE, since.as_str())]);
+        assert_eq!(
+            status(evaluate(&req, None, Some(modified()))),
+            Some(StatusCode::NOT_MODIFIED)
+        );
+
+        let earlier = http_date(999_999_999);
+        let req = request(Method::GET, &[(IF_MODIFIED_SINCE, earlier.as_str())]);
+        assert_eq!(status(evaluate(&req, None, Some(modified()))), None);
+
+        let req = request(Method::PUT, &[(IF_UNMODIFIED_SINCE, since.as_str())]);
+        assert_eq!(status(evaluate(&req, None, Some(modified()))), None);
+
+        let req = request(Method::PUT, &[(IF_UNMODIFIED_SINCE, earlier.as_str())]);
+        assert_eq!(
+            status(evaluate(&req, None, Some(modified()))),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+    }
+
+    #[test]
+    fn if_modified_since_only_applies_to_reads() {
+        let since = http_date(1_000_000_000);
+        let req = request(Method::POST, &[(IF_MODIFIED_SINCE, since.as_str())]);
+        assert_eq!(status(evaluate(&req, None, Some(modified()))), None);
+    }
+
+    #[test]
+    fn tags_take_precedence_over_dates() {
+        let etag = ETag::strong("v1");
+        let earlier = http_date(999_999_999);
+        let req = request(
+            Method::PUT,
+            &[
+                (IF_MATCH, "\"v1\""),
+                (IF_UNMODIFIED_SINCE, earlier.as_str()),
+            ],
+        );
+        assert_eq!(status(evaluate(&req, Some(&etag), Some(modified()))), None);
+
+        let since = http_date(1_000_000_000);
+        let req = request(
+            Method::GET,
+            &[
+                (IF_NONE_MATCH, "\"v0\""),
+                (IF_MODIFIED_SINCE, since.as_str()),
+            ],
+        );
+        assert_eq!(status(evaluate(&req, Some(&etag), Some(modified()))), None);
+    }
+
+    #[test]
+    fn if_range_needs_an_exact_strong_match() {
+        let etag = ETag::strong("v1");
+        let req = request(Method::GET, &[]);
+        assert!(if_range(&req, Some(&etag), None));
+
+        let req = request(Method::GET, &[(IF_RANGE, "\"v1\"")]);
+        assert!(if_range(&req, Some(&etag), None));
+
+        let req = request(Method::GET, &[(IF_RANGE, "W/\"v1\"")]);
+        assert!(!if_range(&req, Some(&ETag::weak("v1")), None));
+
+        let date = http_date(1_000_000_000);
+        let req = request(Method::GET, &[(IF_RANGE, date.as_str())]);
+        assert!(if_range(&req, None, Some(modified())));
+
+        let date = http_date(999_999_999);
+        let req = request(Method::GET, &[(IF_RANGE, date.as_str())]);
+        assert!(!if_range(&req, None, Some(modified())));
+    }
+}
@@ -0,0 +1,104 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use hyper::Request;
+
+use crate::certificate::{Certificate, ClientCertificates};
+use crate::middleware::{ClientIp, Deadline, RequestId};
+use crate::router::{MatchedRoute, PathParams};
+use crate::server::{AlpnProtocol, PeerAddr};
+use crate::state::State;
+
+/// Typed access to what middleware and the router record about a request
+/// in its extensions, such as the identity of the client, the request's
+/// identifier or the parameters of the route it matched.
+///
+/// Anything `Send + Sync + 'static` can be stored, one value per type, so
+/// middleware of its own can pass values on to handlers by inserting them
+/// under a type of its own with [`set_context`](RequestContext::set_context).
+pub trait RequestContext {
+    /// The value of type `T` recorded for the request.
+    fn context<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static;
+
+    /// Records `value` for the request, returning the value of the same
+    /// type it replaces, if any.
+    fn set_context<T>(&mut self, value: T) -> Option<T>
+    where
+        T: Send + Sync + 'static;
+
+    /// The parameters captured from the path by the route the request
+    /// matched.
+    fn params(&self) -> Option<&PathParams> {
+        self.context()
+    }
+
+    /// The parameter `name` captured from the path.
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params().and_then(|params| params.get(name))
+    }
+
+    /// The route the request was dispatched to.
+    fn route(&self) -> Option<&MatchedRoute> {
+        self.context()
+    }
+
+    /// The state of type `T` registered with `Router::state`.
+    fn state<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.context::<State<T>>().map(|state| &**state)
+    }
+
+    /// How long is left to answer the request, if a timeout applies to it.
+    fn remaining_time(&self) -> Option<Duration> {
+        self.context().map(Deadline::remaining)
+    }
+
+    /// The address of the client that sent the request.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.context::<PeerAddr>().map(|peer| peer.0)
+    }
+
+    /// The address of the client the request originates from, as worked
+    /// out by `ClientIpLayer`.
+    fn client_ip(&self) -> Option<IpAddr> {
+        self.context::<ClientIp>().map(|client| client.0)
+    }
+
+    /// The application protocol negotiated when the client connected over
+    /// TLS, e.g. `h2`.
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.context::<AlpnProtocol>()
+            .map(|protocol| &protocol.0[..])
+    }
+
+    /// The certificate the client authenticated with over TLS.
+    fn client_certificate(&self) -> Option<&Certificate> {
+        self.context::<ClientCertificates>()
+            .and_then(ClientCertificates::leaf)
+    }
+
+    /// The identifier given to the request by `RequestIdLayer`.
+    fn request_id(&self) -> Option<&str> {
+        self.context().map(RequestId::as_str)
+    }
+}
+
+impl<B> RequestContext for Request<B> {
+    fn context<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.extensions().get()
+    }
+
+    fn set_context<T>(&mut self, value: T) -> Option<T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.extensions_mut().insert(value)
+    }
+}
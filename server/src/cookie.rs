@@ -0,0 +1,193 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use hyper::header::{HeaderValue, COOKIE, SET_COOKIE};
+use hyper::{Body, Request, Response};
+
+/// The cookies a request was sent with, parsed from its `Cookie` headers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cookies {
+    cookies: Vec<(String, String)>,
+}
+
+impl Cookies {
+    /// Parses the cookies of `req`, skipping any that are malformed.
+    pub fn from_request(req: &Request<Body>) -> Self {
+        let cookies = req
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(';'))
+            .filter_map(|pair| {
+                let mut pair = pair.splitn(2, '=');
+                let name = pair.next()?.trim();
+                let value = pair.next()?.trim();
+                if name.is_empty() {
+                    return None;
+                }
+                // Values may be quoted, which isn't part of the value.
+                let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    &value[1..value.len() - 1]
+                } else {
+                    value
+                };
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        Cookies { cookies }
+    }
+
+    /// The value of the first cookie named `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every cookie's name and value, in the order they were sent.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+/// Whether a cookie is sent along with cross-site requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+/// A cookie for a response to set, built up from its name and value with
+/// the attributes it should have.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    same_site: Option<SameSite>,
+    secure: bool,
+    http_only: bool,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            same_site: None,
+            secure: false,
+            http_only: false,
+        }
+    }
+
+    /// A cookie that removes the one named `name` from the client, by
+    /// having it expire immediately.
+    pub fn removal(name: &str) -> Self {
+        SetCookie::new(name, "")
+            .max_age(Duration::from_secs(0))
+            .expires(SystemTime::UNIX_EPOCH)
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// Adds the cookie to `response`, alongside any others it sets.
+    ///
+    /// Panics if the cookie's name, value or attributes contain characters
+    /// that aren't allowed in a header.
+    pub fn set(&self, response: &mut Response<Body>) {
+        let value = HeaderValue::from_str(&self.to_string())
+            .unwrap_or_else(|err| panic!("invalid cookie {:?}: {}", self.name, err));
+        response.headers_mut().append(SET_COOKIE, value);
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use hyper::{Body, Response, StatusCode};
+use log::error;
+use serde_json::json;
+
+use crate::reply::{self, IntoResponse};
+
+/// An error a handler can fail with, carrying the status to answer the
+/// request with and optionally a message meant for the client.
+///
+/// Any error type converts into one with `?`, as a `500 Internal Server
+/// Error` that keeps the original error as its source but doesn't reveal
+/// it to the client. Responses are rendered as `application/problem+json`
+/// documents (RFC 7807) unless the [`MapError`](crate::middleware::MapError)
+/// middleware renders them differently.
+#[derive(Debug)]
+pub struct Error {
+    status: StatusCode,
+    message: Option<String>,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+}
+
+impl Error {
+    /// An error answered with `status`, telling the client `message`.
+    pub fn new<S>(status: StatusCode, message: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Error {
+            status,
+            message: Some(message.into()),
+            source: None,
+        }
+    }
+
+    /// An error answered with `status` alone.
+    pub fn from_status(status: StatusCode) -> Self {
+        Error {
+            status,
+            message: None,
+            source: None,
+        }
+    }
+
+    /// A `500 Internal Server Error` caused by `source`.
+    pub fn internal<E>(source: E) -> Self
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Error::from_status(StatusCode::INTERNAL_SERVER_ERROR).with_source(source)
+    }
+
+    /// Records `source` as the cause of the error, for logging. It isn't
+    /// shown to the client.
+    pub fn with_source<E>(mut self, source: E) -> Self
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The message meant for the client, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(String::as_str)
+    }
+
+    pub fn source(&self) -> Option<&(dyn StdError + Send + Sync + 'static)> {
+        self.source.as_ref().map(|source| &**source)
+    }
+}
+
+impl<E> From<E> for Error
+where
+    E: StdError + Send + Sync + 'static,
+{
+    fn from(err: E) -> Self {
+        Error::internal(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.status)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, " (caused by: {})", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error rendered by [`problem_json`]. It's also inserted into the
+/// response extensions, where `MapError` finds it.
+impl IntoResponse for Error {
+    fn into_response(self) -> Response<Body> {
+        if self.status.is_server_error() {
+            error!("handler failed: {}", self);
+        }
+
+        let mut response = problem_json(&self);
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Renders `err` as an `application/problem+json` document describing its
+/// status, along with its message if it has one.
+pub fn problem_json(err: &Error) -> Response<Body> {
+    let mut problem = json!({
+        "type": "about:blank",
+        "title": err.status.canonical_reason().unwrap_or_default(),
+        "status": err.status.as_u16(),
+    });
+    if let Some(message) = &err.message {
+        problem["detail"] = json!(message);
+    }
+
+    let mut response = match serde_json::to_vec(&problem) {
+        Ok(body) => reply::with_body("application/problem+json", body),
+        Err(_) => Response::new(Body::empty()),
+    };
+    *response.status_mut() = err.status;
+    response
+}
+
+/// Renders `err` as plain text, its message or else the reason phrase of
+/// its status.
+pub fn plain_text(err: &Error) -> Response<Body> {
+    let text = err
+        .message()
+        .or_else(|| err.status.canonical_reason())
+        .unwrap_or_default();
+    let mut response = reply::text(text);
+    *response.status_mut() = err.status;
+    response
+}
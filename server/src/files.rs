@@ -0,0 +1,690 @@
+use std::fs::Metadata;
+use std::io::{self, Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::BytesMut;
+use futures::future::{self, Either, Loop};
+use futures::{Future, Stream};
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, LOCATION, VARY,
+};
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::json;
+use tokio::codec::{BytesCodec, FramedRead};
+use tokio::fs::File;
+use tokio::io::AsyncRead;
+
+use crate::conditional::{self, ETag};
+use crate::error::Error;
+use crate::handler::{self, ResponseFuture};
+use crate::negotiate;
+use crate::range::{self, Ranges};
+use crate::reply;
+use crate::router::{normalize, EncodedSlashes};
+
+/// A handler serving the files under the directory `root` for requests
+/// whose path starts with `prefix`, with the default [`StaticDir`]
+/// settings. It's meant to be registered for a wildcard route covering
+/// the prefix:
+///
+/// ```ignore
+/// routes!(
+///     GET "/assets/*" => static_dir("/assets", "./public"),
+/// )
+/// ```
+pub fn static_dir<P>(
+    prefix: &str,
+    root: P,
+) -> impl Fn(Request<Body>) -> ResponseFuture + Clone + Send
+where
+    P: Into<PathBuf>,
+{
+    StaticDir::new(prefix, root).handler()
+}
+
+/// Serves the files under a directory, mapping the part of the request
+/// path after a prefix onto it.
+///
+/// The decoded path is resolved before it's mapped, so it can't climb out
+/// of the directory with `..` segments, and requests for paths with
+/// segments that could be read as something else by the file system, such
+/// as backslashes, get a `404 Not Found` like those for missing files.
+/// Symbolic links under the directory are followed. A request for a
+/// directory is redirected to the same path with a trailing slash, so
+/// that relative links in its index file work, and answered with the
+/// first of its index files that exists, or with a listing of its
+/// contents if it has none and listings are enabled.
+///
+/// Files are served with an `ETag` made of their size and modification
+/// time and with their `Last-Modified` time, and conditional requests are
+/// answered with a `304 Not Modified` while the client's copy is fresh.
+/// Requests for a range of a file get just that range, so that interrupted
+/// downloads can be resumed.
+///
+/// When a file has a precompressed variant next to it, named after it
+/// with a `.br` or `.gz` extension added, clients that accept the coding
+/// get the variant instead, with the matching `Content-Encoding`.
+#[derive(Clone, Debug)]
+pub struct StaticDir {
+    prefix: String,
+    root: PathBuf,
+    index_files: Vec<String>,
+    listing: bool,
+    precompressed: bool,
+}
+
+impl StaticDir {
+    pub fn new<P>(prefix: &str, root: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        StaticDir {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root: root.into(),
+            index_files: vec!["index.html".to_string()],
+            listing: false,
+            precompressed: true,
+        }
+    }
+
+    /// Sets the names of the files tried in turn when a directory is
+    /// requested. Defaults to `index.html` alone.
+    pub fn index_files<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.index_files = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Answers requests for directories without an index file with a
+    /// listing of their contents, as an HTML page or, for clients that
+    /// prefer it, as a JSON array describing each entry. Off by default.
+    pub fn listing(mut self, listing: bool) -> Self {
+        self.listing = listing;
+        self
+    }
+
+    /// Whether to look for precompressed variants of files. On by default.
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// The handler serving the files.
+    pub fn handler(self) -> impl Fn(Request<Body>) -> ResponseFuture + Clone + Send {
+        let config = Arc::new(self);
+        move |req| config.serve(req)
+    }
+
+    fn serve(&self, req: Request<Body>) -> ResponseFuture {
+        let path = match self.file_path(req.uri().path()) {
+            Some(path) => path,
+            None => return not_found(),
+        };
+
+        let location = if req.uri().path().ends_with('/') {
+            None
+        } else {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{}/?{}", req.uri().path(), query),
+                None => format!("{}/", req.uri().path()),
+            };
+            HeaderValue::from_str(&path_and_query).ok()
+        };
+        let index_files = self.index_files.clone();
+        let listing = if self.listing {
+            let title = normalize(req.uri().path(), EncodedSlashes::Decode)
+                .map_or_else(String::new, |path| path.into_owned());
+            match negotiate::best_match(&req, &["text/html", "application/json"]) {
+                Some("application/json") => Some(Listing::Json),
+                _ => Some(Listing::Html(title)),
+            }
+        } else {
+            None
+        };
+        let variants = if self.precompressed {
+            let accept_encoding = req
+                .headers()
+                .get(ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            Some(Variant::accepted(accept_encoding))
+        } else {
+            None
+        };
+
+        handler::from_future(
+            open(path.clone())
+                .and_then(move |(file, metadata)| {
+                    if !metadata.is_dir() {
+                        return Either::A(serve_variant(req, path, file, metadata, variants));
+                    }
+
+                    match location {
+                        Some(location) => {
+                            let mut response = Response::new(Body::empty());
+                            *response.status_mut() = StatusCode::MOVED_PERMANENTLY;
+                            response.headers_mut().insert(LOCATION, location);
+                            Either::B(Either::A(future::ok(response)))
+                        }
+                        None => Either::B(Either::B(serve_dir(
+                            req,
+                            path,
+                            index_files,
+                            listing,
+                            variants,
+                        ))),
+                    }
+                })
+                .map_err(error),
+        )
+    }
+
+    /// Where the file for the request path `path` would be, or `None` if
+    /// the path isn't under the prefix or can't be safely mapped.
+    fn file_path(&self, path: &str) -> Option<PathBuf> {
+        let path = normalize(path, EncodedSlashes::Reject)?;
+        if !path.starts_with(&self.prefix) {
+            return None;
+        }
+
+        let relative = &path[self.prefix.len()..];
+        if !relative.is_empty() && !relative.starts_with('/') {
+            return None;
+        }
+
+        let mut file_path = self.root.clone();
+        for segment in relative.split('/').filter(|segment| !segment.is_empty()) {
+            if segment == ".." || segment.contains(|c: char| c == '\\' || c == '\0' || c == ':') {
+                return None;
+            }
+            file_path.push(segment);
+        }
+        Some(file_path)
+    }
+}
+
+/// Answers `req` with the file at `path`, or with a `304 Not Modified` or
+/// `412 Precondition Failed` if its conditional headers call for one.
+///
+/// A request for a single range gets a `206 Partial Content` with just
+/// that range read from the file, and one for ranges that are all outside
+/// the file a `416 Range Not Satisfiable`. Requests for several ranges are
+/// answered with the whole file, as RFC 7233 allows, rather than with a
+/// multipart body.
+fn serve_file(
+    req: &Request<Body>,
+    path: &Path,
+    file: File,
+    metadata: &Metadata,
+    coding: Option<&'static str>,
+) -> impl Future<Item = Response<Body>, Error = io::Error> + Send {
+    let etag = etag(metadata);
+    let modified = metadata.modified().ok();
+    if let Some(response) = conditional::evaluate(req, Some(&etag), modified) {
+        return Either::A(future::ok(response));
+    }
+
+    let length = metadata.len();
+    let ranges = if conditional::if_range(req, Some(&etag), modified) {
+        range::ranges(req, length)
+    } else {
+        Ranges::Full
+    };
+
+    let response = match ranges {
+        Ranges::Partial(ref ranges) if ranges.len() == 1 => {
+            let range = ranges[0].clone();
+            let content_type = content_type(path);
+            Either::A(
+                file.seek(SeekFrom::Start(range.start))
+                    .map(move |(file, _)| {
+                        let part = file.take(range.end - range.start);
+                        let mut response = Response::new(stream_body(part));
+                        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+                        let headers = response.headers_mut();
+                        headers.insert(CONTENT_TYPE, content_type);
+                        headers.insert(CONTENT_LENGTH, HeaderValue::from(range.end - range.start));
+                        headers.insert(CONTENT_RANGE, range::content_range(&range, length));
+                        conditional::set_validators(&mut response, Some(&etag), modified);
+                        response
+                    }),
+            )
+        }
+        Ranges::Unsatisfiable => Either::B(future::ok(range::unsatisfiable(length))),
+        _ => Either::B(future::ok(response(path, file, metadata))),
+    };
+
+    Either::B(response.map(move |mut response| {
+        let success = response.status().is_success();
+        let headers = response.headers_mut();
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let (true, Some(coding)) = (success, coding) {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(coding));
+        }
+        response
+    }))
+}
+
+/// A precompressed variant of a file.
+#[derive(Clone, Copy, Debug)]
+struct Variant {
+    /// What's added to the name of the file to get the variant's.
+    extension: &'static str,
+    coding: &'static str,
+}
+
+/// The variants looked for, in order of preference.
+const VARIANTS: &[Variant] = &[
+    Variant {
+        extension: "br",
+        coding: "br",
+    },
+    Variant {
+        extension: "gz",
+        coding: "gzip",
+    },
+];
+
+impl Variant {
+    /// The variants a client with `accept_encoding` accepts, those it
+    /// prefers first.
+    fn accepted(accept_encoding: &str) -> Vec<Variant> {
+        let codings = negotiate::parse_tokens(accept_encoding);
+        let quality = |name: &str| {
+            codings
+                .iter()
+                .find(|(coding, _)| coding == name)
+                .or_else(|| codings.iter().find(|(coding, _)| coding == "*"))
+                .map_or(0, |(_, quality)| *quality)
+        };
+
+        let mut accepted: Vec<_> = VARIANTS
+            .iter()
+            .map(|&variant| (variant, quality(variant.coding)))
+            .filter(|(_, quality)| *quality > 0)
+            .collect();
+        // The sort is stable, so variants the client likes equally keep
+        // their order of preference.
+        accepted.sort_by(|(_, a), (_, b)| b.cmp(a));
+        accepted.into_iter().map(|(variant, _)| variant).collect()
+    }
+
+    fn path(self, path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(self.extension);
+        PathBuf::from(name)
+    }
+}
+
+/// Answers `req` with the first of `variants` of the file at `path` that
+/// exists, or else with `file` itself. The response varies by
+/// `Accept-Encoding` unless `variants` is `None`, which is how serving
+/// precompressed variants is turned off.
+fn serve_variant(
+    req: Request<Body>,
+    path: PathBuf,
+    file: File,
+    metadata: Metadata,
+    variants: Option<Vec<Variant>>,
+) -> impl Future<Item = Response<Body>, Error = io::Error> + Send {
+    let vary = variants.is_some();
+    let variants = variants.unwrap_or_default();
+    let paths = variants.iter().map(|variant| variant.path(&path)).collect();
+
+    open_first(paths)
+        .then(move |result| match result {
+            Ok((index, variant, variant_metadata)) => serve_file(
+                &req,
+                &path,
+                variant,
+                &variant_metadata,
+                Some(variants[index].coding),
+            ),
+            Err(_) => serve_file(&req, &path, file, &metadata, None),
+        })
+        .map(move |mut response| {
+            if vary {
+                response
+                    .headers_mut()
+                    .append(VARY, HeaderValue::from_static("accept-encoding"));
+            }
+            response
+        })
+}
+
+/// Answers with the first of `index_files` in the directory `dir`, or else
+/// with a `listing` of it.
+fn serve_dir(
+    req: Request<Body>,
+    dir: PathBuf,
+    index_files: Vec<String>,
+    listing: Option<Listing>,
+    variants: Option<Vec<Variant>>,
+) -> impl Future<Item = Response<Body>, Error = io::Error> + Send {
+    let paths: Vec<_> = index_files.iter().map(|name| dir.join(name)).collect();
+    open_first(paths.clone())
+        .and_then(move |(index, file, metadata)| {
+            serve_variant(req, paths[index].clone(), file, metadata, variants)
+        })
+        .or_else(move |err| match listing {
+            Some(listing) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Either::A(future::err(err));
+                }
+                Either::B(list(dir).map(move |entries| listing.render(&entries)))
+            }
+            None => Either::A(future::err(err)),
+        })
+}
+
+/// Opens the first of `paths` that's a file, resolving to its index among
+/// them, or fails with `NotFound` if none are.
+fn open_first(
+    paths: Vec<PathBuf>,
+) -> impl Future<Item = (usize, File, Metadata), Error = io::Error> + Send {
+    future::loop_fn(0, move |index| {
+        let path = match paths.get(index) {
+            Some(path) => path.clone(),
+            None => return Either::A(future::err(io::Error::from(io::ErrorKind::NotFound))),
+        };
+
+        Either::B(open(path).then(move |result| match result {
+            Ok((file, metadata)) => {
+                if metadata.is_dir() {
+                    Ok(Loop::Continue(index + 1))
+                } else {
+                    Ok(Loop::Break((index, file, metadata)))
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Loop::Continue(index + 1)),
+            Err(err) => Err(err),
+        }))
+    })
+}
+
+/// How a directory listing is rendered.
+enum Listing {
+    /// As an HTML page titled after the directory's path.
+    Html(String),
+    Json,
+}
+
+impl Listing {
+    fn render(&self, entries: &[Entry]) -> Response<Body> {
+        match self {
+            Listing::Html(path) => {
+                let mut rows = String::new();
+                if path != "/" {
+                    rows.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+                }
+                for entry in entries {
+                    let suffix = if entry.is_dir { "/" } else { "" };
+                    let size = if entry.is_dir {
+                        "-".to_string()
+                    } else {
+                        entry.size.to_string()
+                    };
+                    rows.push_str(&format!(
+                        "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                        encode_segment(&entry.name),
+                        suffix,
+                        escape_html(&entry.name),
+                        suffix,
+                        size,
+                        entry
+                            .modified
+                            .map(httpdate::fmt_http_date)
+                            .unwrap_or_default(),
+                    ));
+                }
+
+                let title = escape_html(path);
+                reply::html(format!(
+                    "<!DOCTYPE html>\n\
+                     <html>\n\
+                     <head><meta charset=\"utf-8\"><title>Index of {}</title></head>\n\
+                     <body>\n\
+                     <h1>Index of {}</h1>\n\
+                     <table>\n\
+                     <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n\
+                     {}\
+                     </table>\n\
+                     </body>\n\
+                     </html>\n",
+                    title, title, rows,
+                ))
+            }
+            Listing::Json => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|entry| {
+                        json!({
+                            "name": entry.name,
+                            "type": if entry.is_dir { "directory" } else { "file" },
+                            "size": entry.size,
+                            "modified": entry.modified.map(httpdate::fmt_http_date),
+                        })
+                    })
+                    .collect();
+                reply::json(&entries)
+            }
+        }
+    }
+}
+
+/// An entry of a directory listing.
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// The entries of the directory `dir`, subdirectories first and each
+/// group sorted by name. Entries whose metadata can't be read, such as
+/// broken symbolic links, are left out.
+fn list(dir: PathBuf) -> impl Future<Item = Vec<Entry>, Error = io::Error> + Send {
+    tokio::fs::read_dir(dir)
+        .flatten_stream()
+        .and_then(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            tokio::fs::metadata(entry.path()).then(move |metadata| {
+                Ok(metadata.ok().map(|metadata| Entry {
+                    name,
+                    is_dir: metadata.is_dir(),
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                }))
+            })
+        })
+        .filter_map(|entry| entry)
+        .collect()
+        .map(|mut entries| {
+            entries.sort_by(|a: &Entry, b: &Entry| {
+                b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name))
+            });
+            entries
+        })
+}
+
+/// Percent-encodes everything but unreserved characters in `name`, so it
+/// can be used as a relative link.
+fn encode_segment(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn not_found() -> ResponseFuture {
+    Box::new(future::ok(reply::status(StatusCode::NOT_FOUND)))
+}
+
+/// Opens the file at `path` along with its metadata.
+pub(crate) fn open(
+    path: PathBuf,
+) -> impl Future<Item = (File, Metadata), Error = io::Error> + Send {
+    File::open(path).and_then(File::metadata)
+}
+
+/// The media type of the file at `path`, going by its extension, or
+/// `application/octet-stream` if it's unknown.
+pub(crate) fn content_type(path: &Path) -> HeaderValue {
+    let media_type = mime_guess::from_path(path).first_or_octet_stream();
+    HeaderValue::from_str(media_type.as_ref()).expect("media types are valid header values")
+}
+
+/// A `200 OK` response streaming `file`, with the headers describing it.
+pub(crate) fn response(path: &Path, file: File, metadata: &Metadata) -> Response<Body> {
+    let mut response = Response::new(stream_body(file));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, content_type(path));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(metadata.len()));
+    conditional::set_validators(
+        &mut response,
+        Some(&etag(metadata)),
+        metadata.modified().ok(),
+    );
+    response
+}
+
+/// A body streaming what `reader` reads.
+fn stream_body<R>(reader: R) -> Body
+where
+    R: AsyncRead + Send + 'static,
+{
+    Body::wrap_stream(FramedRead::new(reader, BytesCodec::new()).map(BytesMut::freeze))
+}
+
+/// A strong entity tag for the file described by `metadata`, made of its
+/// modification time and size, so that it changes whenever the file is
+/// rewritten without the file having to be read to compute it.
+pub(crate) fn etag(metadata: &Metadata) -> ETag {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    ETag::strong(&format!(
+        "{:x}.{:x}-{:x}",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    ))
+}
+
+/// The error to answer with when a file can't be served: a `404 Not
+/// Found` if it doesn't exist, a `403 Forbidden` if it can't be read and
+/// a `500 Internal Server Error` otherwise.
+pub(crate) fn error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => Error::from_status(StatusCode::NOT_FOUND),
+        io::ErrorKind::PermissionDenied => Error::from_status(StatusCode::FORBIDDEN),
+        _ => Error::internal(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assets() -> StaticDir {
+        StaticDir::new("/assets/", "/srv/public")
+    }
+
+    fn root() -> PathBuf {
+        PathBuf::from("/srv/public")
+    }
+
+    #[test]
+    fn paths_under_the_prefix_map_under_the_root() {
+        let dir = assets();
+        assert_eq!(dir.file_path("/assets"), Some(root()));
+        assert_eq!(dir.file_path("/assets/"), Some(root()));
+        assert_eq!(
+            dir.file_path("/assets/site.css"),
+            Some(root().join("site.css"))
+        );
+        assert_eq!(
+            dir.file_path("/assets/css/themes/dark.css"),
+            Some(root().join("css").join("themes").join("dark.css"))
+        );
+        assert_eq!(
+            dir.file_path("/assets//css/./site.css"),
+            Some(root().join("css").join("site.css"))
+        );
+        assert_eq!(
+            dir.file_path("/assets/css/../js/app.js"),
+            Some(root().join("js").join("app.js"))
+        );
+        assert_eq!(
+            dir.file_path("/assets/a%20b.txt"),
+            Some(root().join("a b.txt"))
+        );
+    }
+
+    #[test]
+    fn paths_outside_the_prefix_are_rejected() {
+        let dir = assets();
+        assert_eq!(dir.file_path("/"), None);
+        assert_eq!(dir.file_path("/asset"), None);
+        assert_eq!(dir.file_path("/assetsx"), None);
+        assert_eq!(dir.file_path("/assetsx/site.css"), None);
+        assert_eq!(dir.file_path("/other/assets/site.css"), None);
+        assert_eq!(dir.file_path("assets/site.css"), None);
+    }
+
+    #[test]
+    fn paths_climbing_out_of_the_root_are_rejected() {
+        let dir = assets();
+        assert_eq!(dir.file_path("/assets/.."), None);
+        assert_eq!(dir.file_path("/assets/../secret"), None);
+        assert_eq!(dir.file_path("/assets/css/../../secret"), None);
+        assert_eq!(dir.file_path("/assets/%2e%2e/secret"), None);
+        assert_eq!(dir.file_path("/assets/%2E%2E/secret"), None);
+        assert_eq!(dir.file_path("/assets/.%2e/secret"), None);
+    }
+
+    #[test]
+    fn segments_the_file_system_could_misread_are_rejected() {
+        let dir = assets();
+        assert_eq!(dir.file_path("/assets/..%2fsecret"), None);
+        assert_eq!(dir.file_path("/assets/css%2Fsite.css"), None);
+        assert_eq!(dir.file_path("/assets/..\\secret"), None);
+        assert_eq!(dir.file_path("/assets/%5c..%5csecret"), None);
+        assert_eq!(dir.file_path("/assets/site.css%00.txt"), None);
+        assert_eq!(dir.file_path("/assets/C:/Windows/win.ini"), None);
+        assert_eq!(dir.file_path("/assets/site.css:stream"), None);
+        assert_eq!(dir.file_path("/assets/site.css%3astream"), None);
+        assert_eq!(dir.file_path("/assets/%ff"), None);
+        assert_eq!(dir.file_path("/assets/%zz"), None);
+    }
+}
@@ -0,0 +1,109 @@
+use std::error::Error as StdError;
+
+use futures::{future, Future, IntoFuture};
+use hyper::body::Payload;
+use hyper::{Body, Error as HyperError, Request, Response, StatusCode};
+
+use crate::reply::{self, IntoResponse};
+
+pub trait CloneableService: objekt::Clone {
+    type ReqBody: Payload;
+    type ResBody: Payload;
+    type Error: Into<Box<StdError + Send + Sync>>;
+    type Future: Future<Item = Response<Self::ResBody>, Error = Self::Error>;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future;
+}
+
+objekt::clone_trait_object!(CloneableService<ReqBody = Body, ResBody = Body, Error = HyperError, Future = ResponseFuture> + Send);
+
+pub type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = HyperError> + Send>;
+pub type RequestHandler = Box<
+    dyn CloneableService<
+            ReqBody = Body,
+            ResBody = Body,
+            Error = HyperError,
+            Future = ResponseFuture,
+        > + Send,
+>;
+
+impl<T, F> CloneableService for T
+where
+    T: Fn(Request<Body>) -> F + Clone,
+    F: IntoFuture<Item = Response<Body>, Error = HyperError>,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = F::Error;
+    type Future = F::Future;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        (self)(req).into_future()
+    }
+}
+
+/// What a handler can return: either a value that can be converted into a
+/// response right away, or a future resolving to one.
+pub trait IntoResponseFuture {
+    fn into_response_future(self) -> ResponseFuture;
+}
+
+impl<T> IntoResponseFuture for T
+where
+    T: IntoResponse,
+{
+    fn into_response_future(self) -> ResponseFuture {
+        Box::new(future::ok(self.into_response()))
+    }
+}
+
+impl IntoResponseFuture for ResponseFuture {
+    fn into_response_future(self) -> ResponseFuture {
+        self
+    }
+}
+
+/// Turns a function returning anything that implements
+/// [`IntoResponseFuture`] into a [`RequestHandler`]. This is how `routes!`
+/// registers handlers, so they can return `String`s, `Json` values,
+/// `(StatusCode, T)` pairs or `Result`s of them instead of building a
+/// response and boxing a future themselves.
+pub fn into_handler<F, R>(handler: F) -> RequestHandler
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + 'static,
+    R: IntoResponseFuture,
+{
+    Box::new(move |req| handler(req).into_response_future())
+}
+
+/// Converts a handler's future into a [`ResponseFuture`], turning whatever
+/// it resolves to, or fails with, into a response. This lets asynchronous
+/// handlers fail with an [`Error`](crate::error::Error) rather than only
+/// with a `hyper::Error`.
+pub fn from_future<F>(future: F) -> ResponseFuture
+where
+    F: Future + Send + 'static,
+    F::Item: IntoResponse,
+    F::Error: IntoResponse,
+{
+    Box::new(future.then(|result| Ok::<_, HyperError>(result.into_response())))
+}
+
+/// Creates a handler that redirects every request to `location` with the
+/// given 3xx `status`, answering with [`reply::redirect`].
+///
+/// Panics if `status` isn't a redirection or `location` isn't a valid
+/// header value.
+pub fn redirect(
+    location: &str,
+    status: u16,
+) -> impl Fn(Request<Body>) -> ResponseFuture + Clone + Send {
+    let status = StatusCode::from_u16(status)
+        .unwrap_or_else(|_| panic!("invalid redirect status {}", status));
+    // Built once up front so that an invalid redirect panics as the route
+    // is set up rather than on its first request.
+    reply::redirect(location, status);
+
+    let location = location.to_string();
+    move |_| Box::new(future::ok(reply::redirect(&location, status))) as ResponseFuture
+}
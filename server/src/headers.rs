@@ -0,0 +1,56 @@
+use hyper::header::HeaderMap;
+use hyper::{Request, Response};
+use typed_headers::{Error, Header, HeaderMapExt};
+
+/// Typed access to the headers of requests and responses, for any header
+/// implementing `typed_headers::Header`, including custom ones.
+pub trait TypedHeaders {
+    fn headers(&self) -> &HeaderMap;
+
+    fn headers_mut(&mut self) -> &mut HeaderMap;
+
+    /// The header `H`, or `None` if it's missing or malformed.
+    fn header<H>(&self) -> Option<H>
+    where
+        H: Header,
+    {
+        self.try_header().ok().and_then(|header| header)
+    }
+
+    /// The header `H`, or `None` if it's missing, failing if it's
+    /// malformed.
+    fn try_header<H>(&self) -> Result<Option<H>, Error>
+    where
+        H: Header,
+    {
+        TypedHeaders::headers(self).typed_get()
+    }
+
+    /// Sets the header `H`, replacing any values it had.
+    fn set_header<H>(&mut self, header: H)
+    where
+        H: Header,
+    {
+        TypedHeaders::headers_mut(self).typed_insert(&header);
+    }
+}
+
+impl<B> TypedHeaders for Request<B> {
+    fn headers(&self) -> &HeaderMap {
+        Request::headers(self)
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        Request::headers_mut(self)
+    }
+}
+
+impl<B> TypedHeaders for Response<B> {
+    fn headers(&self) -> &HeaderMap {
+        Response::headers(self)
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        Response::headers_mut(self)
+    }
+}
@@ -0,0 +1,40 @@
+//! A minimalistic HTTP framework built on hyper, mainly useful for writing
+//! integration tests against mock servers.
+//!
+//! Routes are declared with the [`routes!`] macro and served by a
+//! [`Server`]; `src/main.rs` is a small example.
+
+pub mod body;
+pub mod certificate;
+pub mod conditional;
+pub mod context;
+pub mod cookie;
+pub mod error;
+pub mod files;
+pub mod handler;
+pub mod headers;
+pub mod middleware;
+pub mod multipart;
+pub mod negotiate;
+#[cfg(windows)]
+mod pipe;
+pub mod query;
+pub mod range;
+pub mod reply;
+pub mod router;
+pub mod server;
+pub mod shutdown;
+pub mod sse;
+pub mod state;
+#[cfg(unix)]
+pub mod systemd;
+#[cfg(feature = "templates")]
+pub mod template;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub mod tls;
+
+pub use crate::context::RequestContext;
+pub use crate::error::Error;
+pub use crate::handler::{RequestHandler, ResponseFuture};
+pub use crate::router::{Route, Router};
+pub use crate::server::Server;
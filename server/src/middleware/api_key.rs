@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future;
+use hyper::header::HeaderName;
+use hyper::{Body, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// The label of the key a request was authenticated with, as found in the
+/// request extensions once the [`ApiKey`] middleware has let it through.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ApiKeyLabel(pub String);
+
+impl ApiKeyLabel {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Middleware that only lets requests through whose `X-Api-Key` header
+/// holds one of the allowed keys, answering any others with a `401
+/// Unauthorized`. Each key is given a label, such as the name of the
+/// client it was issued to, that is passed on to handlers as an
+/// [`ApiKeyLabel`].
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    header: HeaderName,
+    keys: Arc<HashMap<String, String>>,
+}
+
+impl ApiKey {
+    pub fn new() -> Self {
+        ApiKey::default()
+    }
+
+    /// Reads the key from the header `name` instead.
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = HeaderName::from_bytes(name.as_bytes())
+            .unwrap_or_else(|err| panic!("invalid header name {:?}: {}", name, err));
+        self
+    }
+
+    /// Allows `key`, labelling the requests made with it `label`.
+    pub fn key(mut self, key: &str, label: &str) -> Self {
+        Arc::make_mut(&mut self.keys).insert(key.to_string(), label.to_string());
+        self
+    }
+}
+
+impl Default for ApiKey {
+    fn default() -> Self {
+        ApiKey {
+            header: HeaderName::from_static("x-api-key"),
+            keys: Arc::default(),
+        }
+    }
+}
+
+impl Middleware for ApiKey {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let api_key = self.clone();
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                let label = req
+                    .headers()
+                    .get(&api_key.header)
+                    .and_then(|key| key.to_str().ok())
+                    .and_then(|key| api_key.keys.get(key))
+                    .cloned();
+                match label {
+                    Some(label) => {
+                        req.extensions_mut().insert(ApiKeyLabel(label));
+                        handler.call(req)
+                    }
+                    None => {
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = StatusCode::UNAUTHORIZED;
+                        Box::new(future::ok(response)) as ResponseFuture
+                    }
+                }
+            },
+        )
+        .wrap(handler)
+    }
+}
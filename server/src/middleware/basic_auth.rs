@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future;
+use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{Body, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Decides whether a user name and password are valid.
+pub type Verifier = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// The user a request was authenticated as, as found in the request
+/// extensions once the [`BasicAuth`] middleware has let it through.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BasicUser(pub String);
+
+/// Middleware that only lets requests through with `Authorization: Basic`
+/// credentials the verifier accepts, answering any others with a `401
+/// Unauthorized` and a `WWW-Authenticate` challenge.
+#[derive(Clone)]
+pub struct BasicAuth {
+    verifier: Verifier,
+    challenge: HeaderValue,
+}
+
+impl BasicAuth {
+    /// Accepts the credentials `verifier` returns `true` for.
+    pub fn new<F>(verifier: F) -> Self
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        BasicAuth {
+            verifier: Arc::new(verifier),
+            challenge: HeaderValue::from_static("Basic realm=\"Restricted\""),
+        }
+    }
+
+    /// Accepts the given pairs of user names and passwords.
+    pub fn users<I, U, P>(users: I) -> Self
+    where
+        I: IntoIterator<Item = (U, P)>,
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let users: HashMap<String, String> = users
+            .into_iter()
+            .map(|(user, password)| (user.into(), password.into()))
+            .collect();
+        BasicAuth::new(move |user, password| {
+            users
+                .get(user)
+                .map_or(false, |expected| expected == password)
+        })
+    }
+
+    /// Names the realm the challenge is for, `Restricted` by default.
+    pub fn realm(mut self, realm: &str) -> Self {
+        self.challenge = HeaderValue::from_str(&format!("Basic realm=\"{}\"", realm))
+            .unwrap_or_else(|err| panic!("invalid realm {:?}: {}", realm, err));
+        self
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let auth = self.clone();
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                let user = credentials(&req)
+                    .filter(|(user, password)| (auth.verifier)(user, password))
+                    .map(|(user, _)| user);
+                match user {
+                    Some(user) => {
+                        req.extensions_mut().insert(BasicUser(user));
+                        handler.call(req)
+                    }
+                    None => {
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = StatusCode::UNAUTHORIZED;
+                        response
+                            .headers_mut()
+                            .insert(WWW_AUTHENTICATE, auth.challenge.clone());
+                        Box::new(future::ok(response)) as ResponseFuture
+                    }
+                }
+            },
+        )
+        .wrap(handler)
+    }
+}
+
+/// The user name and password of a request's `Authorization: Basic`
+/// header, if it has a well formed one.
+fn credentials(req: &Request<Body>) -> Option<(String, String)> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = header.splitn(2, ' ');
+    if !parts.next()?.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = base64::decode(parts.next()?.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+}
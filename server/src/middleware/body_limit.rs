@@ -0,0 +1,167 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::{future, Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::CONTENT_LENGTH;
+use hyper::{Body, Chunk, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// A request body that fails once more than `remaining` bytes have been
+/// read from it, flagging `exceeded` so the middleware can tell why.
+struct Limited {
+    body: Body,
+    remaining: u64,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl Stream for Limited {
+    type Item = Chunk;
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Self::Error> {
+        match self.body.poll()? {
+            Async::Ready(Some(chunk)) => {
+                let length = chunk.len() as u64;
+                if length > self.remaining {
+                    self.exceeded.store(true, Ordering::SeqCst);
+                    return Err("request body too large".into());
+                }
+                self.remaining -= length;
+                Ok(Async::Ready(Some(chunk)))
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+/// Middleware that answers requests whose body is larger than a limit with
+/// a `413 Payload Too Large`.
+///
+/// Requests declaring a larger `Content-Length` are turned away before
+/// they reach the handler, and those declaring a smaller one are handed on
+/// as they are, as hyper doesn't read past it. The bodies of the others
+/// are checked as the handler reads them: once the limit is exceeded
+/// reading fails, and the handler's response, or error, is replaced with
+/// the `413`. Checking them means wrapping them in a new `Body`, which
+/// loses any trailers they'd have been followed by over HTTP/2.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyLimit {
+    max: u64,
+}
+
+impl BodyLimit {
+    /// Limits request bodies to `max` bytes.
+    pub fn new(max: u64) -> Self {
+        BodyLimit { max }
+    }
+}
+
+impl Middleware for BodyLimit {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let max = self.max;
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let known = req.body().content_length();
+            let declared = known.or_else(|| {
+                req.headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|length| length.to_str().ok()?.parse::<u64>().ok())
+            });
+            if declared.map_or(false, |length| length > max) {
+                return Box::new(future::ok(too_large())) as ResponseFuture;
+            }
+            if known.is_some() {
+                return handler.call(req);
+            }
+
+            let exceeded = Arc::new(AtomicBool::new(false));
+            let (parts, body) = req.into_parts();
+            let body = Body::wrap_stream(Limited {
+                body,
+                remaining: max,
+                exceeded: exceeded.clone(),
+            });
+
+            Box::new(
+                handler
+                    .call(Request::from_parts(parts, body))
+                    .then(move |result| {
+                        if exceeded.load(Ordering::SeqCst) {
+                            Ok(too_large())
+                        } else {
+                            result
+                        }
+                    }),
+            )
+        })
+        .wrap(handler)
+    }
+}
+
+fn too_large() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler;
+    use crate::reply::IntoResponse;
+
+    /// A handler limited to 4 bytes that answers with the length of the
+    /// body it was given, if it was known, and what it read of it.
+    fn limited() -> RequestHandler {
+        BodyLimit::new(4).wrap(handler::into_handler(|req: Request<Body>| {
+            let known = req.body().content_length();
+            Box::new(
+                req.into_body()
+                    .concat2()
+                    .map(move |body| format!("{:?} {}", known, body.len()).into_response()),
+            ) as ResponseFuture
+        }))
+    }
+
+    fn send(body: Body) -> (StatusCode, String) {
+        let response = limited()
+            .call(Request::new(body))
+            .wait()
+            .expect("request failed");
+        let status = response.status();
+        let body = response
+            .into_body()
+            .concat2()
+            .wait()
+            .expect("reading the body failed");
+        (status, String::from_utf8(body.to_vec()).expect("UTF-8"))
+    }
+
+    fn streamed(chunks: Vec<&'static str>) -> Body {
+        Body::wrap_stream(futures::stream::iter_ok::<_, hyper::Error>(chunks))
+    }
+
+    #[test]
+    fn bodies_of_known_length_are_handed_on_as_they_are() {
+        assert_eq!(
+            send(Body::from("abcd")),
+            (StatusCode::OK, "Some(4) 4".to_string())
+        );
+        assert_eq!(send(Body::from("abcde")).0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn streamed_bodies_are_checked_as_they_are_read() {
+        assert_eq!(
+            send(streamed(vec!["ab", "cd"])),
+            (StatusCode::OK, "None 4".to_string())
+        );
+        assert_eq!(
+            send(streamed(vec!["ab", "cde"])).0,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
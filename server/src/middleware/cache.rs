@@ -0,0 +1,407 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{future, Future, Stream};
+use hyper::body::Payload;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, CACHE_CONTROL, IF_RANGE, RANGE, SET_COOKIE, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::body::DEFAULT_LIMIT;
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Identifies the requests that share a cached response.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    method: Method,
+    path_and_query: String,
+    headers: Vec<Option<HeaderValue>>,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    expires: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    entries: HashMap<Key, Entry>,
+    /// Keys in the order they were last cached, to evict the oldest.
+    order: VecDeque<Key>,
+}
+
+impl Store {
+    /// The cached response for `key`, dropping it if it has expired.
+    fn get(&mut self, key: &Key) -> Option<Response<Body>> {
+        let entry = self.entries.get(key)?;
+        if entry.expires <= Instant::now() {
+            self.remove(key);
+            return None;
+        }
+
+        let mut response = Response::new(Body::from(entry.body.clone()));
+        *response.status_mut() = entry.status;
+        *response.headers_mut() = entry.headers.clone();
+        Some(response)
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry, max_entries: usize) {
+        if self.entries.insert(key.clone(), entry).is_some() {
+            self.order.retain(|cached| *cached != key);
+        }
+        self.order.push_back(key);
+
+        while self.entries.len() > max_entries {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &Key) {
+        self.entries.remove(key);
+        self.order.retain(|cached| cached != key);
+    }
+}
+
+/// Middleware that keeps successful responses to `GET` and `HEAD` requests
+/// in memory and answers later requests for the same path and query from
+/// it, without calling the handler, until they expire.
+///
+/// Only responses whose status is cacheable by default are kept, e.g.
+/// `200 OK` but not `206 Partial Content`, and requests for ranges are
+/// passed through. Responses marked `Cache-Control: no-store`, `no-cache`
+/// or `private`, those setting cookies and those varying on `*` aren't
+/// cached either.
+///
+/// Requests only share a response if they also agree on the headers given
+/// to [`Cache::vary`]. Once [`Cache::max_entries`] responses are cached the
+/// oldest are evicted. Caching a response means waiting for its whole body,
+/// so only those whose length is known and at most
+/// [`Cache::max_body_size`] are, and streamed ones are passed through as
+/// they come. Clones of the middleware, and the handlers it wraps, share
+/// one cache.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    ttl: Duration,
+    max_entries: usize,
+    max_body_size: u64,
+    vary: Vec<HeaderName>,
+    store: Arc<Mutex<Store>>,
+}
+
+impl Cache {
+    /// Creates a cache whose responses expire after `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Cache {
+            ttl,
+            max_entries: 1024,
+            max_body_size: DEFAULT_LIMIT,
+            vary: vec![],
+            store: Arc::default(),
+        }
+    }
+
+    /// Sets how many responses are kept at most, 1024 by default.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Sets the longest body, in bytes, of the responses that are cached,
+    /// 1 MiB by default.
+    pub fn max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Only shares a cached response between requests with the same value
+    /// of the header `name`.
+    pub fn vary(mut self, name: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .unwrap_or_else(|err| panic!("invalid header name {:?}: {}", name, err));
+        self.vary.push(name);
+        self
+    }
+
+    /// Drops every cached response.
+    pub fn clear(&self) {
+        let mut store = self.lock();
+        store.entries.clear();
+        store.order.clear();
+    }
+
+    fn key(&self, req: &Request<Body>) -> Key {
+        Key {
+            method: req.method().clone(),
+            path_and_query: req
+                .uri()
+                .path_and_query()
+                .map_or_else(|| req.uri().path().to_string(), ToString::to_string),
+            headers: self
+                .vary
+                .iter()
+                .map(|name| req.headers().get(name).cloned())
+                .collect(),
+        }
+    }
+
+    /// Whether `req` may be answered from the cache, and its response
+    /// cached.
+    fn is_cacheable_request(req: &Request<Body>) -> bool {
+        (req.method() == Method::GET || req.method() == Method::HEAD)
+            && !req.headers().contains_key(RANGE)
+            && !req.headers().contains_key(IF_RANGE)
+    }
+
+    /// Whether `response` may be kept and handed to other requests.
+    fn is_cacheable_response(&self, response: &Response<Body>) -> bool {
+        let status = response.status();
+        let headers = response.headers();
+        let cacheable_status = status == StatusCode::OK
+            || status == StatusCode::NON_AUTHORITATIVE_INFORMATION
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::MULTIPLE_CHOICES
+            || status == StatusCode::MOVED_PERMANENTLY
+            || status == StatusCode::PERMANENT_REDIRECT;
+        let restricted = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.splitn(2, '=').next().unwrap_or_default().trim())
+            .any(|directive| {
+                ["no-store", "no-cache", "private"]
+                    .iter()
+                    .any(|restriction| directive.eq_ignore_ascii_case(restriction))
+            });
+        let varies_on_anything = headers
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|name| name.trim() == "*");
+
+        let small_enough = response
+            .body()
+            .content_length()
+            .map_or(false, |length| length <= self.max_body_size);
+
+        cacheable_status
+            && small_enough
+            && !restricted
+            && !varies_on_anything
+            && !headers.contains_key(SET_COOKIE)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Middleware for Cache {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let cache = self.clone();
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            if !Cache::is_cacheable_request(&req) {
+                return handler.call(req);
+            }
+
+            let key = cache.key(&req);
+            if let Some(response) = cache.lock().get(&key) {
+                return Box::new(future::ok(response)) as ResponseFuture;
+            }
+
+            let cache = cache.clone();
+            Box::new(handler.call(req).and_then(move |response| {
+                if !cache.is_cacheable_response(&response) {
+                    return Box::new(future::ok(response)) as ResponseFuture;
+                }
+
+                let (parts, body) = response.into_parts();
+                Box::new(body.concat2().map(move |body| {
+                    let body = body.into_bytes();
+                    let entry = Entry {
+                        status: parts.status,
+                        headers: parts.headers.clone(),
+                        body: body.clone(),
+                        expires: Instant::now() + cache.ttl,
+                    };
+                    cache.lock().insert(key, entry, cache.max_entries);
+                    Response::from_parts(parts, Body::from(body))
+                }))
+            }))
+        })
+        .wrap(handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::handler;
+    use crate::reply;
+
+    /// A handler wrapped in `cache` that counts how often it's called,
+    /// answering with `response` each time.
+    fn counted<F>(cache: &Cache, response: F) -> (RequestHandler, Arc<AtomicUsize>)
+    where
+        F: Fn() -> Response<Body> + Clone + Send + 'static,
+    {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let handler = handler::into_handler(move |_: Request<Body>| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            response()
+        });
+        (cache.wrap(handler), calls)
+    }
+
+    fn get(
+        handler: &mut RequestHandler,
+        path: &str,
+        headers: &[(&'static str, &'static str)],
+    ) -> Bytes {
+        let mut req = Request::get(path)
+            .body(Body::empty())
+            .expect("valid request");
+        for (name, value) in headers {
+            req.headers_mut()
+                .insert(*name, HeaderValue::from_static(*value));
+        }
+        handler
+            .call(req)
+            .and_then(|response| response.into_body().concat2())
+            .wait()
+            .expect("request failed")
+            .into_bytes()
+    }
+
+    fn key(path: &str) -> Key {
+        Key {
+            method: Method::GET,
+            path_and_query: path.to_string(),
+            headers: vec![],
+        }
+    }
+
+    fn entry(expires: Instant) -> Entry {
+        Entry {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(b"cached"),
+            expires,
+        }
+    }
+
+    #[test]
+    fn fresh_responses_are_served_from_the_cache() {
+        let cache = Cache::new(Duration::from_secs(60));
+        let (mut handler, calls) = counted(&cache, || reply::text("hello"));
+
+        assert_eq!(&get(&mut handler, "/a", &[])[..], b"hello");
+        assert_eq!(&get(&mut handler, "/a", &[])[..], b"hello");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        get(&mut handler, "/a?page=2", &[]);
+        get(&mut handler, "/b", &[]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        cache.clear();
+        get(&mut handler, "/a", &[]);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn expired_responses_are_dropped() {
+        let cache = Cache::new(Duration::from_secs(0));
+        let (mut handler, calls) = counted(&cache, || reply::text("hello"));
+
+        get(&mut handler, "/a", &[]);
+        get(&mut handler, "/a", &[]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let mut store = Store::default();
+        store.insert(key("/a"), entry(Instant::now()), 10);
+        assert!(store.get(&key("/a")).is_none());
+        assert!(store.entries.is_empty());
+        assert!(store.order.is_empty());
+    }
+
+    #[test]
+    fn the_least_recently_cached_responses_are_evicted() {
+        let fresh = Instant::now() + Duration::from_secs(60);
+        let mut store = Store::default();
+        store.insert(key("/a"), entry(fresh), 2);
+        store.insert(key("/b"), entry(fresh), 2);
+        store.insert(key("/a"), entry(fresh), 2);
+        store.insert(key("/c"), entry(fresh), 2);
+
+        assert!(store.get(&key("/a")).is_some());
+        assert!(store.get(&key("/b")).is_none());
+        assert!(store.get(&key("/c")).is_some());
+        assert_eq!(store.order, vec![key("/a"), key("/c")]);
+    }
+
+    #[test]
+    fn responses_are_shared_by_requests_agreeing_on_vary() {
+        let cache = Cache::new(Duration::from_secs(60)).vary("accept-language");
+        let (mut handler, calls) = counted(&cache, || reply::text("hello"));
+
+        get(&mut handler, "/a", &[("accept-language", "en")]);
+        get(&mut handler, "/a", &[("accept-language", "en")]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        get(&mut handler, "/a", &[("accept-language", "fr")]);
+        get(&mut handler, "/a", &[]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn uncacheable_responses_are_passed_through() {
+        let cache = Cache::new(Duration::from_secs(60));
+        let (mut handler, calls) = counted(&cache, || {
+            let chunks = futures::stream::iter_ok::<_, hyper::Error>(vec!["hel", "lo"]);
+            reply::stream("text/plain", chunks)
+        });
+        assert_eq!(&get(&mut handler, "/a", &[])[..], b"hello");
+        get(&mut handler, "/a", &[]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let cache = Cache::new(Duration::from_secs(60)).max_body_size(4);
+        let (mut handler, calls) = counted(&cache, || reply::text("hello"));
+        get(&mut handler, "/a", &[]);
+        get(&mut handler, "/a", &[]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let cache = Cache::new(Duration::from_secs(60));
+        let (mut handler, calls) = counted(&cache, || {
+            let mut response = reply::text("hello");
+            response.headers_mut().insert(
+                CACHE_CONTROL,
+                HeaderValue::from_static("max-age=60, private"),
+            );
+            response
+        });
+        get(&mut handler, "/a", &[]);
+        get(&mut handler, "/a", &[]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let (mut handler, calls) = counted(&cache, || reply::text("hello"));
+        get(&mut handler, "/a", &[("range", "bytes=0-1")]);
+        get(&mut handler, "/a", &[("range", "bytes=0-1")]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,54 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use futures::{future, Future};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::error;
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Middleware that answers with a `500 Internal Server Error` when the
+/// handler panics, whether while it's called or while its response is
+/// being produced, logging the panic instead of dropping the connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CatchPanic;
+
+impl CatchPanic {
+    pub fn new() -> Self {
+        CatchPanic
+    }
+}
+
+impl Middleware for CatchPanic {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        from_fn(|req: Request<Body>, handler: &mut RequestHandler| {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+
+            match panic::catch_unwind(AssertUnwindSafe(|| handler.call(req))) {
+                Ok(response) => Box::new(AssertUnwindSafe(response).catch_unwind().then(
+                    move |result| match result {
+                        Ok(result) => result,
+                        Err(panic) => Ok(internal_error(&method, &path, &*panic)),
+                    },
+                )) as ResponseFuture,
+                Err(panic) => Box::new(future::ok(internal_error(&method, &path, &*panic))),
+            }
+        })
+        .wrap(handler)
+    }
+}
+
+fn internal_error(method: &Method, path: &str, panic: &(dyn Any + Send)) -> Response<Body> {
+    let message = panic
+        .downcast_ref::<&str>()
+        .cloned()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<Any>");
+    error!("handler for {} {} panicked: {}", method, path, message);
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    response
+}
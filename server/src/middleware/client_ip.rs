@@ -0,0 +1,308 @@
+use std::net::{IpAddr, SocketAddr};
+
+use hyper::header::{HeaderName, FORWARDED};
+use hyper::{Body, Request};
+
+use super::{from_fn, Middleware};
+use crate::handler::RequestHandler;
+use crate::server::PeerAddr;
+
+/// The address of the client a request originates from, as found in the
+/// request extensions once the [`ClientIpLayer`] middleware has seen it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClientIp(pub IpAddr);
+
+/// A range of addresses, given as an address and the length of the prefix
+/// they share.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    fn contains(self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::max_value()
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::max_value()
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Middleware that works out which client a request originates from when
+/// it may have come through proxies, recording it as a [`ClientIp`].
+///
+/// The addresses proxies add to the `Forwarded` header, or failing that
+/// to `X-Forwarded-For`, are only believed when the request came from a
+/// trusted proxy. They're read from the last one added, and the first
+/// address that isn't itself a trusted proxy is taken to be the client's.
+/// Otherwise the client is the peer the request came from.
+#[derive(Clone, Debug, Default)]
+pub struct ClientIpLayer {
+    trusted: Vec<Network>,
+}
+
+impl ClientIpLayer {
+    pub fn new() -> Self {
+        ClientIpLayer::default()
+    }
+
+    /// Trusts the proxy at `addr`.
+    pub fn trust(self, addr: IpAddr) -> Self {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        self.trust_network(addr, prefix)
+    }
+
+    /// Trusts the proxies whose addresses share their first `prefix` bits
+    /// with `addr`, i.e. `addr/prefix` in CIDR notation.
+    pub fn trust_network(mut self, addr: IpAddr, prefix: u8) -> Self {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            panic!("invalid prefix length {} for {}", prefix, addr);
+        }
+        self.trusted.push(Network { addr, prefix });
+        self
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|network| network.contains(ip))
+    }
+
+    fn client_ip(&self, req: &Request<Body>) -> Option<IpAddr> {
+        let peer = req.extensions().get::<PeerAddr>()?.0.ip();
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+
+        let forwarded = forwarded_for(req);
+        let client = forwarded
+            .iter()
+            .rev()
+            .find(|&&ip| !self.is_trusted(ip))
+            .or_else(|| forwarded.first())
+            .cloned();
+        Some(client.unwrap_or(peer))
+    }
+}
+
+impl Middleware for ClientIpLayer {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let layer = self.clone();
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                if let Some(ip) = layer.client_ip(&req) {
+                    req.extensions_mut().insert(ClientIp(ip));
+                }
+                handler.call(req)
+            },
+        )
+        .wrap(handler)
+    }
+}
+
+/// The addresses listed in the `Forwarded` header or, if there isn't one,
+/// in `X-Forwarded-For`, in the order they were added. Entries that aren't
+/// addresses, such as obfuscated identifiers, are skipped.
+fn forwarded_for(req: &Request<Body>) -> Vec<IpAddr> {
+    let headers = req.headers();
+    if headers.contains_key(FORWARDED) {
+        header_values(req, &FORWARDED)
+            .flat_map(|element| element.split(';'))
+            .filter_map(|pair| {
+                let mut pair = pair.splitn(2, '=');
+                let key = pair.next()?.trim();
+                if key.eq_ignore_ascii_case("for") {
+                    parse_node(pair.next()?.trim().trim_matches('"'))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    } else {
+        header_values(req, &HeaderName::from_static("x-forwarded-for"))
+            .filter_map(|node| parse_node(node.trim()))
+            .collect()
+    }
+}
+
+/// The comma separated elements of every value of the header `name`.
+fn header_values<'a>(
+    req: &'a Request<Body>,
+    name: &HeaderName,
+) -> impl Iterator<Item = &'a str> + 'a {
+    req.headers()
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+}
+
+/// Parses an address, which may have a port and be in brackets.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().expect("valid address")
+    }
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut req = Request::new(Body::empty());
+        req.extensions_mut()
+            .insert(PeerAddr(SocketAddr::new(ip(peer), 1234)));
+        for (name, value) in headers {
+            req.headers_mut().append(
+                HeaderName::from_bytes(name.as_bytes()).expect("valid name"),
+                HeaderValue::from_str(value).expect("valid value"),
+            );
+        }
+        req
+    }
+
+    #[test]
+    fn networks_contain_addresses_sharing_their_prefix() {
+        let network = Network {
+            addr: ip("10.0.0.0"),
+            prefix: 8,
+        };
+        assert!(network.contains(ip("10.1.2.3")));
+        assert!(!network.contains(ip("11.0.0.1")));
+        assert!(!network.contains(ip("::ffff:10.1.2.3")));
+
+        let everything = Network {
+            addr: ip("0.0.0.0"),
+            prefix: 0,
+        };
+        assert!(everything.contains(ip("203.0.113.1")));
+
+        let network = Network {
+            addr: ip("2001:db8::"),
+            prefix: 32,
+        };
+        assert!(network.contains(ip("2001:db8:cafe::17")));
+        assert!(!network.contains(ip("2001:db9::1")));
+
+        let host = Network {
+            addr: ip("::1"),
+            prefix: 128,
+        };
+        assert!(host.contains(ip("::1")));
+        assert!(!host.contains(ip("::2")));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid prefix length")]
+    fn prefixes_longer_than_addresses_panic() {
+        ClientIpLayer::new().trust_network(ip("10.0.0.0"), 33);
+    }
+
+    #[test]
+    fn nodes_may_have_ports_and_brackets() {
+        assert_eq!(parse_node("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("192.0.2.1:80"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("::1"), Some(ip("::1")));
+        assert_eq!(parse_node("[::1]"), Some(ip("::1")));
+        assert_eq!(parse_node("[::1]:80"), Some(ip("::1")));
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node("unknown"), None);
+    }
+
+    #[test]
+    fn forwarded_addresses_are_read_in_order() {
+        let req = request(
+            "10.0.0.1",
+            &[
+                (
+                    "forwarded",
+                    "for=192.0.2.60;proto=http;by=203.0.113.43, For=\"[2001:db8:cafe::17]:4711\"",
+                ),
+                ("forwarded", "for=_hidden, for=198.51.100.17"),
+            ],
+        );
+        assert_eq!(
+            forwarded_for(&req),
+            vec![
+                ip("192.0.2.60"),
+                ip("2001:db8:cafe::17"),
+                ip("198.51.100.17")
+            ]
+        );
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded_for() {
+        let req = request(
+            "10.0.0.1",
+            &[
+                ("x-forwarded-for", "203.0.113.1"),
+                ("forwarded", "for=192.0.2.60"),
+            ],
+        );
+        assert_eq!(forwarded_for(&req), vec![ip("192.0.2.60")]);
+
+        let req = request("10.0.0.1", &[("x-forwarded-for", "203.0.113.1, 10.0.0.2")]);
+        assert_eq!(forwarded_for(&req), vec![ip("203.0.113.1"), ip("10.0.0.2")]);
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let layer = ClientIpLayer::new().trust(ip("10.0.0.1"));
+        let req = request("192.0.2.1", &[("x-forwarded-for", "203.0.113.1")]);
+        assert_eq!(layer.client_ip(&req), Some(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn trusted_proxies_are_skipped() {
+        let layer = ClientIpLayer::new().trust_network(ip("10.0.0.0"), 8);
+        let req = request(
+            "10.0.0.1",
+            &[("x-forwarded-for", "198.51.100.1, 203.0.113.1, 10.0.0.2")],
+        );
+        assert_eq!(layer.client_ip(&req), Some(ip("203.0.113.1")));
+    }
+
+    #[test]
+    fn the_first_address_is_used_when_every_one_is_trusted() {
+        let layer = ClientIpLayer::new().trust_network(ip("10.0.0.0"), 8);
+        let req = request("10.0.0.1", &[("forwarded", "for=10.0.0.3, for=10.0.0.2")]);
+        assert_eq!(layer.client_ip(&req), Some(ip("10.0.0.3")));
+    }
+
+    #[test]
+    fn trusted_peers_without_forwarding_headers_are_the_client() {
+        let layer = ClientIpLayer::new().trust(ip("10.0.0.1"));
+        let req = request("10.0.0.1", &[]);
+        assert_eq!(layer.client_ip(&req), Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn requests_without_a_peer_have_no_client() {
+        let layer = ClientIpLayer::new();
+        assert_eq!(layer.client_ip(&Request::new(Body::empty())), None);
+    }
+}
@@ -0,0 +1,625 @@
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::{future, Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, ETAG,
+    VARY,
+};
+use hyper::{Body, Chunk, Method, Request, Response, StatusCode};
+use typed_headers::{ContentType, HeaderMapExt};
+
+use super::{from_fn, Middleware};
+use crate::conditional::ETag;
+use crate::handler::{RequestHandler, ResponseFuture};
+use crate::negotiate::{self, MediaRange};
+
+/// The media types compressed by default. Event streams are left out, as
+/// proxies and clients tend to hold compressed ones back until they've
+/// got enough of them.
+const COMPRESSIBLE_TYPES: &str = "text/*, text/event-stream;q=0, application/json, \
+                                  application/javascript, application/xml, image/svg+xml";
+
+/// A content coding the middleware can apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+/// Every supported coding, in order of preference.
+#[cfg(feature = "brotli")]
+const ENCODINGS: &[Encoding] = &[Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+#[cfg(not(feature = "brotli"))]
+const ENCODINGS: &[Encoding] = &[Encoding::Gzip, Encoding::Deflate];
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let output = Output::default();
+        let mut encoder = Encoder::new(self, output.clone());
+        encoder.writer().write_all(data)?;
+        encoder.finish()?;
+        Ok(output.take())
+    }
+
+    /// The coding the client prefers according to its `Accept-Encoding`,
+    /// falling back on the order of [`ENCODINGS`] between codings it
+    /// likes equally.
+    fn preferred(accept_encoding: &str) -> Option<Encoding> {
+        let codings = negotiate::parse_tokens(accept_encoding);
+        let quality = |name: &str| {
+            codings
+                .iter()
+                .find(|(coding, _)| coding == name)
+                .or_else(|| codings.iter().find(|(coding, _)| coding == "*"))
+                .map_or(0, |(_, quality)| *quality)
+        };
+
+        // `max_by_key` keeps the last of several equal elements.
+        ENCODINGS
+            .iter()
+            .rev()
+            .map(|&encoding| (encoding, quality(encoding.name())))
+            .filter(|(_, quality)| *quality > 0)
+            .max_by_key(|(_, quality)| *quality)
+            .map(|(encoding, _)| encoding)
+    }
+}
+
+/// Middleware that compresses response bodies with `gzip` or `deflate` when
+/// the client's `Accept-Encoding` allows it. With the `brotli` feature
+/// enabled, `br` is supported as well and preferred over the others unless
+/// the client ranks it lower.
+///
+/// Only responses of the configured media types that are at least
+/// [`Compression::min_size`] bytes long are compressed, text and the usual
+/// structured formats by default. Responses whose length is known are
+/// compressed once their whole body is there, and sent with a
+/// `Content-Length` matching the compressed size, while streamed ones are
+/// compressed as they go, each chunk being sent as soon as it's compressed.
+/// Responses that already have a `Content-Encoding` are left alone, as are
+/// partial ones, whose `Content-Range` is about the unencoded body.
+///
+/// A strong `ETag` is made weak on compressed responses, as they're a
+/// different representation that still matches conditional requests.
+/// Responses to `HEAD` requests get the headers the same `GET` would,
+/// going by their `Content-Length` alone, and lose their `Content-Length`
+/// as the compressed length isn't known without compressing a body.
+#[derive(Clone, Debug)]
+pub struct Compression {
+    min_size: u64,
+    content_types: Vec<MediaRange>,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression::default()
+    }
+
+    /// Sets the smallest body, in bytes, worth compressing. Defaults to
+    /// 1024.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the media types to compress, in the same form as an `Accept`
+    /// header, e.g. `&["text/*", "application/json"]`.
+    pub fn content_types(mut self, media_types: &[&str]) -> Self {
+        self.content_types = negotiate::parse_accept(&media_types.join(","));
+        self
+    }
+
+    fn is_compressible(&self, response: &Response<Body>) -> bool {
+        let status = response.status();
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || response.headers().contains_key(CONTENT_ENCODING)
+            || response.headers().contains_key(CONTENT_RANGE)
+        {
+            return false;
+        }
+
+        let too_small = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse::<u64>().ok())
+            .map_or(false, |length| length < self.min_size);
+        if too_small {
+            return false;
+        }
+
+        match response.headers().typed_get::<ContentType>() {
+            Ok(Some(ContentType(media_type))) => {
+                negotiate::quality(&self.content_types, &media_type) > 0
+            }
+            _ => false,
+        }
+    }
+
+    fn compress(&self, response: Response<Body>, encoding: Encoding, head: bool) -> ResponseFuture {
+        if !self.is_compressible(&response) {
+            return Box::new(future::ok(response));
+        }
+
+        let min_size = self.min_size;
+        let (mut parts, body) = response.into_parts();
+        parts
+            .headers
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+
+        if head {
+            encoded(&mut parts.headers, encoding);
+            parts.headers.remove(CONTENT_LENGTH);
+            return Box::new(future::ok(Response::from_parts(parts, body)));
+        }
+
+        // Bodies of unknown length may go on indefinitely or be large, so
+        // they're compressed without being held in memory.
+        if body.content_length().is_none() && !parts.headers.contains_key(CONTENT_LENGTH) {
+            encoded(&mut parts.headers, encoding);
+            let output = Output::default();
+            let body = Compressed {
+                body,
+                encoder: Some(Encoder::new(encoding, output.clone())),
+                output,
+            };
+            return Box::new(future::ok(Response::from_parts(
+                parts,
+                Body::wrap_stream(body),
+            )));
+        }
+
+        Box::new(body.concat2().map(move |body| {
+            if (body.len() as u64) < min_size {
+                return Response::from_parts(parts, Body::from(body));
+            }
+
+            match encoding.encode(&body) {
+                Ok(encoded) => {
+                    self::encoded(&mut parts.headers, encoding);
+                    parts
+                        .headers
+                        .insert(CONTENT_LENGTH, HeaderValue::from(encoded.len() as u64));
+                    Response::from_parts(parts, Body::from(encoded))
+                }
+                Err(_) => Response::from_parts(parts, Body::from(body)),
+            }
+        }))
+    }
+}
+
+/// Marks the headers of a response as those of its body compressed with
+/// `encoding`, weakening its `ETag` if it's strong.
+fn encoded(headers: &mut HeaderMap, encoding: Encoding) {
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+
+    let etag = headers
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok()?.parse::<ETag>().ok());
+    if let Some(etag) = etag {
+        if !etag.is_weak() {
+            let weak = ETag::weak(etag.tag()).to_string();
+            if let Ok(weak) = HeaderValue::from_str(&weak) {
+                headers.insert(ETAG, weak);
+            }
+        }
+    }
+}
+
+/// An encoder for one of the supported codings, writing to an [`Output`].
+enum Encoder {
+    #[cfg(feature = "brotli")]
+    Brotli(brotli::CompressorWriter<Output>),
+    Gzip(GzEncoder<Output>),
+    Deflate(ZlibEncoder<Output>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding, output: Output) -> Self {
+        let level = flate2::Compression::default();
+        match encoding {
+            // Quality 5 of 11 compresses about as well as gzip's default
+            // while staying fast enough to run on every response.
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Encoder::Brotli(brotli::CompressorWriter::new(output, 4096, 5, 22)),
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(output, level)),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(output, level)),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder,
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Deflate(encoder) => encoder,
+        }
+    }
+
+    /// Writes out the end of the compressed data.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => {
+                encoder.into_inner();
+                Ok(())
+            }
+            Encoder::Gzip(encoder) => encoder.finish().map(drop),
+            Encoder::Deflate(encoder) => encoder.finish().map(drop),
+        }
+    }
+}
+
+/// The compressed data an [`Encoder`] has written so far, to be taken
+/// from outside of it.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Output {
+    fn take(&self) -> Vec<u8> {
+        let mut data = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        mem::replace(&mut *data, Vec::new())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A body compressed chunk by chunk, flushing the encoder after each so
+/// that the client gets them without waiting for more.
+struct Compressed {
+    body: Body,
+    encoder: Option<Encoder>,
+    output: Output,
+}
+
+impl Stream for Compressed {
+    type Item = Chunk;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, io::Error> {
+        loop {
+            if self.encoder.is_none() {
+                return Ok(Async::Ready(None));
+            }
+
+            match self
+                .body
+                .poll()
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+            {
+                Async::Ready(Some(chunk)) => {
+                    if let Some(encoder) = &mut self.encoder {
+                        let writer = encoder.writer();
+                        writer.write_all(&chunk)?;
+                        writer.flush()?;
+                    }
+                }
+                Async::Ready(None) => {
+                    if let Some(encoder) = self.encoder.take() {
+                        encoder.finish()?;
+                    }
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+
+            let compressed = self.output.take();
+            if !compressed.is_empty() {
+                return Ok(Async::Ready(Some(Chunk::from(compressed))));
+            }
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 1024,
+            content_types: negotiate::parse_accept(COMPRESSIBLE_TYPES),
+        }
+    }
+}
+
+impl Middleware for Compression {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let compression = Arc::new(self.clone());
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let encoding = req
+                .headers()
+                .get(ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .and_then(Encoding::preferred);
+            let encoding = match encoding {
+                Some(encoding) => encoding,
+                None => return handler.call(req),
+            };
+
+            let head = req.method() == Method::HEAD;
+            let compression = compression.clone();
+            Box::new(
+                handler
+                    .call(req)
+                    .and_then(move |response| compression.compress(response, encoding, head)),
+            ) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn response(status: StatusCode, content_type: &'static str, body: Body) -> Response<Body> {
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            HeaderValue::from_static(content_type),
+        );
+        response
+    }
+
+    fn compress(compression: &Compression, response: Response<Body>, head: bool) -> Response<Body> {
+        compression
+            .compress(response, Encoding::Gzip, head)
+            .wait()
+            .expect("compressing failed")
+    }
+
+    fn body(response: Response<Body>) -> Vec<u8> {
+        response
+            .into_body()
+            .concat2()
+            .wait()
+            .expect("reading the body failed")
+            .to_vec()
+    }
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut decoded = vec![];
+        GzDecoder::new(data)
+            .read_to_end(&mut decoded)
+            .expect("invalid gzip data");
+        decoded
+    }
+
+    fn text(length: usize) -> Vec<u8> {
+        b"compressible "
+            .iter()
+            .cycle()
+            .take(length)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn the_client_picks_the_encoding() {
+        assert_eq!(Encoding::preferred("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::preferred("deflate"), Some(Encoding::Deflate));
+        assert_eq!(
+            Encoding::preferred("gzip;q=0.5, deflate;q=1"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(
+            Encoding::preferred("gzip;q=0, deflate"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(Encoding::preferred("*"), Some(ENCODINGS[0]));
+        assert_eq!(Encoding::preferred("*;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::preferred("identity"), None);
+        assert_eq!(Encoding::preferred(""), None);
+    }
+
+    #[test]
+    fn sized_bodies_are_compressed_whole() {
+        let original = text(2048);
+        let compressed = compress(
+            &Compression::new(),
+            response(StatusCode::OK, "text/plain", Body::from(original.clone())),
+            false,
+        );
+
+        assert_eq!(compressed.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(compressed.headers()[VARY], "Accept-Encoding");
+        let length = compressed.headers()[CONTENT_LENGTH]
+            .to_str()
+            .expect("lengths are ASCII")
+            .parse::<usize>()
+            .expect("lengths are numbers");
+        let encoded = body(compressed);
+        assert_eq!(encoded.len(), length);
+        assert_eq!(gunzip(&encoded), original);
+    }
+
+    #[test]
+    fn small_bodies_are_left_alone() {
+        let compression = Compression::new();
+        let unchanged = compress(
+            &compression,
+            response(StatusCode::OK, "text/plain", Body::from(text(100))),
+            false,
+        );
+        assert!(!unchanged.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body(unchanged), text(100));
+
+        let mut declared = response(StatusCode::OK, "text/plain", Body::from(text(100)));
+        declared
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from_static("100"));
+        let unchanged = compress(&compression, declared, false);
+        assert!(!unchanged.headers().contains_key(CONTENT_ENCODING));
+
+        let compressed = compress(
+            &Compression::new().min_size(10),
+            response(StatusCode::OK, "text/plain", Body::from(text(100))),
+            false,
+        );
+        assert_eq!(compressed.headers()[CONTENT_ENCODING], "gzip");
+    }
+
+    #[test]
+    fn only_configured_media_types_are_compressed() {
+        let compression = Compression::new();
+        for &content_type in &["text/html; charset=utf-8", "application/json"] {
+            let compressed = compress(
+                &compression,
+                response(StatusCode::OK, content_type, Body::from(text(2048))),
+                false,
+            );
+            assert_eq!(compressed.headers()[CONTENT_ENCODING], "gzip");
+        }
+        for &content_type in &["image/png", "text/event-stream"] {
+            let unchanged = compress(
+                &compression,
+                response(StatusCode::OK, content_type, Body::from(text(2048))),
+                false,
+            );
+            assert!(!unchanged.headers().contains_key(CONTENT_ENCODING));
+        }
+
+        let compression = Compression::new().content_types(&["image/*"]);
+        let compressed = compress(
+            &compression,
+            response(StatusCode::OK, "image/bmp", Body::from(text(2048))),
+            false,
+        );
+        assert_eq!(compressed.headers()[CONTENT_ENCODING], "gzip");
+    }
+
+    #[test]
+    fn partial_and_encoded_responses_are_left_alone() {
+        let compression = Compression::new();
+        let partial = response(
+            StatusCode::PARTIAL_CONTENT,
+            "text/plain",
+            Body::from(text(2048)),
+        );
+        let unchanged = compress(&compression, partial, false);
+        assert!(!unchanged.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body(unchanged), text(2048));
+
+        let mut ranged = response(StatusCode::OK, "text/plain", Body::from(text(2048)));
+        ranged
+            .headers_mut()
+            .insert(CONTENT_RANGE, HeaderValue::from_static("bytes 0-2047/4096"));
+        let unchanged = compress(&compression, ranged, false);
+        assert!(!unchanged.headers().contains_key(CONTENT_ENCODING));
+
+        let mut encoded = response(StatusCode::OK, "text/plain", Body::from(text(2048)));
+        encoded
+            .headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+        let unchanged = compress(&compression, encoded, false);
+        assert_eq!(unchanged.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(body(unchanged), text(2048));
+    }
+
+    #[test]
+    fn streamed_bodies_are_compressed_as_they_go() {
+        let chunks = vec![text(10), text(2000), text(5)];
+        let original = chunks.concat();
+        let stream = futures::stream::iter_ok::<_, hyper::Error>(chunks);
+        let compressed = compress(
+            &Compression::new(),
+            response(StatusCode::OK, "text/plain", Body::wrap_stream(stream)),
+            false,
+        );
+
+        assert_eq!(compressed.headers()[CONTENT_ENCODING], "gzip");
+        assert!(!compressed.headers().contains_key(CONTENT_LENGTH));
+        assert_eq!(compressed.body().content_length(), None);
+
+        let chunks = compressed
+            .into_body()
+            .collect()
+            .wait()
+            .expect("reading the body failed");
+        assert!(chunks.len() > 1);
+        let encoded: Vec<u8> = chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter().cloned())
+            .collect();
+        assert_eq!(gunzip(&encoded), original);
+    }
+
+    #[test]
+    fn strong_etags_are_weakened() {
+        let compression = Compression::new();
+        let mut tagged = response(StatusCode::OK, "text/plain", Body::from(text(2048)));
+        tagged
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_static("\"v1\""));
+        let compressed = compress(&compression, tagged, false);
+        assert_eq!(compressed.headers()[ETAG], "W/\"v1\"");
+
+        let mut tagged = response(StatusCode::OK, "text/plain", Body::from(text(2048)));
+        tagged
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_static("W/\"v1\""));
+        let compressed = compress(&compression, tagged, false);
+        assert_eq!(compressed.headers()[ETAG], "W/\"v1\"");
+
+        let mut small = response(StatusCode::OK, "text/plain", Body::from(text(10)));
+        small
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_static("\"v1\""));
+        let unchanged = compress(&compression, small, false);
+        assert_eq!(unchanged.headers()[ETAG], "\"v1\"");
+    }
+
+    #[test]
+    fn head_responses_get_the_headers_of_compressed_ones() {
+        let mut head = response(StatusCode::OK, "text/plain", Body::empty());
+        let headers = head.headers_mut();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("2048"));
+        headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+        let compressed = compress(&Compression::new(), head, true);
+        assert_eq!(compressed.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(compressed.headers()[ETAG], "W/\"v1\"");
+        assert!(!compressed.headers().contains_key(CONTENT_LENGTH));
+        assert!(body(compressed).is_empty());
+
+        let mut small = response(StatusCode::OK, "text/plain", Body::empty());
+        small
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from_static("100"));
+        let unchanged = compress(&Compression::new(), small, true);
+        assert!(!unchanged.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(unchanged.headers()[CONTENT_LENGTH], "100");
+
+        let empty = response(StatusCode::OK, "text/plain", Body::empty());
+        let compressed = compress(&Compression::new().min_size(0), empty, true);
+        assert!(body(compressed).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use futures::future::{self, Either};
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll};
+use hyper::{Body, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    /// Requests waiting for a slot, each of which is handed the slot of a
+    /// finished request by being sent `()`.
+    waiting: VecDeque<oneshot::Sender<()>>,
+}
+
+#[derive(Debug)]
+struct Limiter {
+    max_in_flight: usize,
+    max_waiting: usize,
+    state: Mutex<State>,
+}
+
+enum Acquired {
+    Now(Permit),
+    Queued(Waiter),
+    Rejected,
+}
+
+impl Limiter {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn acquire(limiter: &Arc<Limiter>) -> Acquired {
+        let mut state = limiter.lock();
+        if state.in_flight < limiter.max_in_flight {
+            state.in_flight += 1;
+            Acquired::Now(Permit(limiter.clone()))
+        } else if state.waiting.len() < limiter.max_waiting {
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.push_back(sender);
+            Acquired::Queued(Waiter {
+                receiver,
+                limiter: limiter.clone(),
+                acquired: false,
+            })
+        } else {
+            Acquired::Rejected
+        }
+    }
+
+    /// Hands a finished request's slot to the next request still waiting
+    /// for one, or frees it if there are none.
+    fn release(&self) {
+        let mut state = self.lock();
+        while let Some(waiting) = state.waiting.pop_front() {
+            if waiting.send(()).is_ok() {
+                return;
+            }
+        }
+        state.in_flight -= 1;
+    }
+}
+
+/// A slot for a request in flight, given back when dropped.
+struct Permit(Arc<Limiter>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Resolves to a permit once a slot is handed over.
+struct Waiter {
+    receiver: oneshot::Receiver<()>,
+    limiter: Arc<Limiter>,
+    acquired: bool,
+}
+
+impl Future for Waiter {
+    type Item = Permit;
+    type Error = oneshot::Canceled;
+
+    fn poll(&mut self) -> Poll<Permit, oneshot::Canceled> {
+        match self.receiver.poll()? {
+            Async::Ready(()) => {
+                self.acquired = true;
+                Ok(Async::Ready(Permit(self.limiter.clone())))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        // A slot may have been handed over after the request was abandoned,
+        // in which case it has to be passed on again.
+        if !self.acquired {
+            self.receiver.close();
+            if let Ok(Some(())) = self.receiver.try_recv() {
+                self.limiter.release();
+            }
+        }
+    }
+}
+
+/// Middleware that caps how many requests are handled at once, until their
+/// responses are ready, answering requests over the cap with a `503
+/// Service Unavailable`.
+///
+/// Optionally, up to [`ConcurrencyLimit::queue`] requests over the cap wait
+/// for a slot instead of being turned away, in the order they arrived. As
+/// with [`RateLimit`](super::RateLimit), each handler the middleware wraps
+/// is limited separately.
+#[derive(Clone, Copy, Debug)]
+pub struct ConcurrencyLimit {
+    max_in_flight: usize,
+    max_waiting: usize,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max_in_flight: usize) -> Self {
+        ConcurrencyLimit {
+            max_in_flight,
+            max_waiting: 0,
+        }
+    }
+
+    /// Lets up to `max_waiting` requests wait for a slot.
+    pub fn queue(mut self, max_waiting: usize) -> Self {
+        self.max_waiting = max_waiting;
+        self
+    }
+}
+
+impl Middleware for ConcurrencyLimit {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let limiter = Arc::new(Limiter {
+            max_in_flight: self.max_in_flight,
+            max_waiting: self.max_waiting,
+            state: Mutex::default(),
+        });
+
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            match Limiter::acquire(&limiter) {
+                Acquired::Now(permit) => Box::new(handler.call(req).then(move |result| {
+                    drop(permit);
+                    result
+                })) as ResponseFuture,
+                Acquired::Queued(waiter) => {
+                    let mut handler = handler.clone();
+                    Box::new(waiter.then(move |permit| match permit {
+                        Ok(permit) => Either::A(handler.call(req).then(move |result| {
+                            drop(permit);
+                            result
+                        })),
+                        Err(_) => Either::B(future::ok(unavailable())),
+                    }))
+                }
+                Acquired::Rejected => Box::new(future::ok(unavailable())),
+            }
+        })
+        .wrap(handler)
+    }
+}
+
+fn unavailable() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    response
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future};
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Middleware implementing CORS, so that browsers let scripts from other
+/// origins call the server.
+///
+/// Preflight requests, i.e. `OPTIONS` requests carrying
+/// `Access-Control-Request-Method`, are answered directly with a `204 No
+/// Content` if the origin and method are allowed, and a `403 Forbidden`
+/// otherwise. Other requests from an allowed origin are handed on as usual
+/// with the CORS headers added to their response.
+///
+/// By default any origin may use the methods `GET`, `HEAD` and `POST` with
+/// whatever headers a preflight request asks for.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    /// `None` allows any origin.
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    /// `None` allows whatever headers are asked for.
+    headers: Option<Vec<HeaderName>>,
+    expose_headers: Vec<HeaderName>,
+    max_age: Option<Duration>,
+    credentials: bool,
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Cors::default()
+    }
+
+    /// Allows requests from `origin`, e.g. `https://example.com`. Once an
+    /// origin is given, only the origins given are allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins
+            .get_or_insert_with(Vec::new)
+            .push(origin.to_string());
+        self
+    }
+
+    /// Sets the methods allowed in cross-origin requests.
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// Allows cross-origin requests to send the header `name`. Once a
+    /// header is given, only the headers given are allowed.
+    pub fn allow_header(mut self, name: &str) -> Self {
+        self.headers
+            .get_or_insert_with(Vec::new)
+            .push(header_name(name));
+        self
+    }
+
+    /// Lets scripts read the response header `name`.
+    pub fn expose_header(mut self, name: &str) -> Self {
+        self.expose_headers.push(header_name(name));
+        self
+    }
+
+    /// Sets how long browsers may cache the result of a preflight request.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets whether requests may include credentials such as cookies.
+    pub fn allow_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// The value of `Access-Control-Allow-Origin` for a request from
+    /// `origin`, if it's allowed.
+    fn allowed_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.origins {
+            None if !self.credentials => Some(HeaderValue::from_static("*")),
+            None => Some(origin.clone()),
+            Some(origins) => origins
+                .iter()
+                .find(|allowed| allowed.as_bytes() == origin.as_bytes())
+                .map(|_| origin.clone()),
+        }
+    }
+
+    fn preflight(&self, req: &Request<Body>, origin: &HeaderValue) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+
+        let method_allowed = req
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+            .map_or(false, |method| self.methods.contains(&method));
+        let allowed_origin = match (self.allowed_origin(origin), method_allowed) {
+            (Some(allowed_origin), true) => allowed_origin,
+            _ => {
+                *response.status_mut() = StatusCode::FORBIDDEN;
+                return response;
+            }
+        };
+
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        self.add_origin_headers(response.headers_mut(), allowed_origin);
+
+        let headers = response.headers_mut();
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods));
+        let allowed_headers = match &self.headers {
+            Some(allowed) => Some(join(allowed)),
+            None => req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS).cloned(),
+        };
+        if let Some(allowed_headers) = allowed_headers {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs()));
+        }
+
+        response
+    }
+
+    fn add_origin_headers(&self, headers: &mut HeaderMap, allowed_origin: HeaderValue) {
+        if allowed_origin != "*" {
+            headers.append(VARY, HeaderValue::from_static("Origin"));
+        }
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: None,
+            methods: vec![Method::GET, Method::HEAD, Method::POST],
+            headers: None,
+            expose_headers: vec![],
+            max_age: None,
+            credentials: false,
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let cors = Arc::new(self.clone());
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let origin = match req.headers().get(ORIGIN) {
+                Some(origin) => origin.clone(),
+                None => return handler.call(req),
+            };
+
+            if req.method() == Method::OPTIONS
+                && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+            {
+                return Box::new(future::ok(cors.preflight(&req, &origin))) as ResponseFuture;
+            }
+
+            let allowed_origin = match cors.allowed_origin(&origin) {
+                Some(allowed_origin) => allowed_origin,
+                None => return handler.call(req),
+            };
+
+            let cors = cors.clone();
+            Box::new(handler.call(req).map(move |mut response| {
+                let headers = response.headers_mut();
+                cors.add_origin_headers(headers, allowed_origin);
+                if !cors.expose_headers.is_empty() {
+                    headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, join(&cors.expose_headers));
+                }
+                response
+            })) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
+
+fn header_name(name: &str) -> HeaderName {
+    HeaderName::from_bytes(name.as_bytes())
+        .unwrap_or_else(|err| panic!("invalid header name {:?}: {}", name, err))
+}
+
+/// Joins `items` into a comma separated header value.
+fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+    let values: Vec<&str> = items.iter().map(AsRef::as_ref).collect();
+    HeaderValue::from_str(&values.join(", ")).expect("tokens are valid header values")
+}
@@ -0,0 +1,192 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use flate2::write::{GzDecoder, ZlibDecoder};
+use futures::{future, Async, Future, Poll, Stream};
+use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use hyper::{Body, Chunk, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+enum Decoder {
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+}
+
+impl Decoder {
+    fn new(encoding: &str) -> Option<Decoder> {
+        if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") {
+            Some(Decoder::Gzip(GzDecoder::new(Vec::new())))
+        } else if encoding.eq_ignore_ascii_case("deflate") {
+            Some(Decoder::Deflate(ZlibDecoder::new(Vec::new())))
+        } else {
+            None
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.write_all(data),
+            Decoder::Deflate(decoder) => decoder.write_all(data),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.try_finish(),
+            Decoder::Deflate(decoder) => decoder.try_finish(),
+        }
+    }
+
+    /// Takes what has been decoded so far.
+    fn take(&mut self) -> Vec<u8> {
+        let output = match self {
+            Decoder::Gzip(decoder) => decoder.get_mut(),
+            Decoder::Deflate(decoder) => decoder.get_mut(),
+        };
+        mem::replace(output, Vec::new())
+    }
+}
+
+/// A request body decoded as it's read, which fails once it decodes to
+/// more than `remaining` bytes or turns out to be corrupt, recording the
+/// status to answer the request with instead.
+struct Decoded {
+    body: Body,
+    decoder: Decoder,
+    remaining: u64,
+    done: bool,
+    failure: Arc<Mutex<Option<StatusCode>>>,
+}
+
+impl Decoded {
+    fn fail(&self, status: StatusCode, reason: &str) -> Box<dyn Error + Send + Sync> {
+        *self.failure.lock().unwrap_or_else(PoisonError::into_inner) = Some(status);
+        reason.into()
+    }
+}
+
+impl Stream for Decoded {
+    type Item = Chunk;
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Self::Error> {
+        while !self.done {
+            let decoded = match self.body.poll()? {
+                Async::Ready(Some(chunk)) => self.decoder.write(&chunk),
+                Async::Ready(None) => {
+                    self.done = true;
+                    self.decoder.finish()
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            if decoded.is_err() {
+                return Err(self.fail(StatusCode::BAD_REQUEST, "request body is corrupt"));
+            }
+
+            let output = self.decoder.take();
+            let length = output.len() as u64;
+            if length > self.remaining {
+                return Err(self.fail(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "decompressed request body too large",
+                ));
+            }
+            self.remaining -= length;
+            if length > 0 {
+                return Ok(Async::Ready(Some(Chunk::from(output))));
+            }
+        }
+        Ok(Async::Ready(None))
+    }
+}
+
+/// Middleware that decompresses request bodies sent with a gzip or deflate
+/// `Content-Encoding`, as they're read, so that handlers and the body
+/// helpers see the original body. The encoding and length headers are
+/// removed from such requests.
+///
+/// Requests with any other encoding are answered with a `415 Unsupported
+/// Media Type`. Bodies that decompress to more than the maximum size or
+/// that are corrupt fail to be read, and the handler's response is
+/// replaced with a `413 Payload Too Large` or a `400 Bad Request`.
+#[derive(Clone, Copy, Debug)]
+pub struct Decompression {
+    max_size: u64,
+}
+
+impl Decompression {
+    pub fn new() -> Self {
+        Decompression::default()
+    }
+
+    /// Limits decompressed bodies to `max_size` bytes, 10 MiB by default.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl Default for Decompression {
+    fn default() -> Self {
+        Decompression {
+            max_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl Middleware for Decompression {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let max_size = self.max_size;
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let encoding = match req.headers().get(CONTENT_ENCODING) {
+                Some(encoding) => encoding.to_str().unwrap_or_default().trim(),
+                None => return handler.call(req),
+            };
+            if encoding.eq_ignore_ascii_case("identity") {
+                return handler.call(req);
+            }
+            let decoder = match Decoder::new(encoding) {
+                Some(decoder) => decoder,
+                None => return status(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            };
+
+            let failure = Arc::new(Mutex::new(None));
+            let (mut parts, body) = req.into_parts();
+            parts.headers.remove(CONTENT_ENCODING);
+            parts.headers.remove(CONTENT_LENGTH);
+            let body = Body::wrap_stream(Decoded {
+                body,
+                decoder,
+                remaining: max_size,
+                done: false,
+                failure: failure.clone(),
+            });
+
+            Box::new(
+                handler
+                    .call(Request::from_parts(parts, body))
+                    .then(move |result| {
+                        match *failure.lock().unwrap_or_else(PoisonError::into_inner) {
+                            Some(status) => {
+                                let mut response = Response::new(Body::empty());
+                                *response.status_mut() = status;
+                                Ok(response)
+                            }
+                            None => result,
+                        }
+                    }),
+            )
+        })
+        .wrap(handler)
+    }
+}
+
+fn status(status: StatusCode) -> ResponseFuture {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    Box::new(future::ok(response))
+}
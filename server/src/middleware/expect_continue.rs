@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use futures::{future, stream, Future, Stream};
+use hyper::header::EXPECT;
+use hyper::{Body, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Decides whether to accept a request expecting a `100 Continue`,
+/// returning the response to reject it with otherwise.
+pub type ContinueCheck = Arc<dyn Fn(&Request<Body>) -> Result<(), Response<Body>> + Send + Sync>;
+
+/// Middleware that answers `Expect: 100-continue` right away.
+///
+/// Hyper only sends the `100 Continue` interim response once the request
+/// body is first read, so clients waiting for it before sending the body
+/// would otherwise hang against handlers that don't read it straight away.
+/// This starts reading the body before calling the handler instead. With a
+/// check set, requests it rejects are answered without a `100 Continue`,
+/// so their body is never sent. Requests with any other expectation are
+/// answered with a `417 Expectation Failed`.
+#[derive(Clone, Default)]
+pub struct ExpectContinue {
+    check: Option<ContinueCheck>,
+}
+
+impl ExpectContinue {
+    pub fn new() -> Self {
+        ExpectContinue::default()
+    }
+
+    /// Only sends a `100 Continue` to requests `check` accepts, answering
+    /// the others with the response it returns.
+    pub fn check<F>(mut self, check: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> Result<(), Response<Body>> + Send + Sync + 'static,
+    {
+        self.check = Some(Arc::new(check));
+        self
+    }
+}
+
+impl Middleware for ExpectContinue {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let check = self.check.clone();
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let expects_continue = req
+                .headers()
+                .get(EXPECT)
+                .map(|value| value.as_bytes().eq_ignore_ascii_case(b"100-continue"));
+            match expects_continue {
+                None => return handler.call(req),
+                Some(false) => {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::EXPECTATION_FAILED;
+                    return Box::new(future::ok(response));
+                }
+                Some(true) => {}
+            }
+
+            if let Some(check) = &check {
+                if let Err(response) = check(&req) {
+                    return Box::new(future::ok(response));
+                }
+            }
+
+            // Reading the first chunk sends the `100 Continue`, after which
+            // it's put back in front of the rest of the body.
+            let mut handler = handler.clone();
+            let (parts, body) = req.into_parts();
+            Box::new(
+                body.into_future()
+                    .map_err(|(err, _)| err)
+                    .and_then(move |(first, rest)| {
+                        let body = Body::wrap_stream(stream::iter_ok(first).chain(rest));
+                        handler.call(Request::from_parts(parts, body))
+                    }),
+            ) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
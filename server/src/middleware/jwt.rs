@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future;
+use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{Body, Request, Response, StatusCode};
+use jsonwebtoken::{Algorithm, Validation};
+use serde_json::Value;
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// The claims of a validated token, as found in the request extensions once
+/// the [`Jwt`] middleware has let a request through. They can be read into
+/// a type of the handler's own with `serde_json::from_value`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JwtClaims(pub Value);
+
+/// Middleware that only lets requests through with an `Authorization:
+/// Bearer` JSON Web Token signed with the configured key, answering any
+/// others with a `401 Unauthorized` and a `WWW-Authenticate` challenge.
+///
+/// Tokens that have expired are rejected, as are those without the
+/// audience or issuer set with [`Jwt::audience`] and [`Jwt::issuer`].
+#[derive(Clone)]
+pub struct Jwt {
+    key: Arc<Vec<u8>>,
+    validation: Validation,
+}
+
+impl Jwt {
+    /// Validates tokens signed with HMAC SHA-256 using `secret`.
+    pub fn hs256(secret: &[u8]) -> Self {
+        Jwt::new(Algorithm::HS256, secret)
+    }
+
+    /// Validates tokens signed with RSA SHA-256, given the DER encoded
+    /// public key.
+    pub fn rs256(public_key: &[u8]) -> Self {
+        Jwt::new(Algorithm::RS256, public_key)
+    }
+
+    fn new(algorithm: Algorithm, key: &[u8]) -> Self {
+        Jwt {
+            key: Arc::new(key.to_vec()),
+            validation: Validation::new(algorithm),
+        }
+    }
+
+    /// Only accepts tokens for `audience`.
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.validation.set_audience(&audience);
+        self
+    }
+
+    /// Only accepts tokens issued by `issuer`.
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.validation.iss = Some(issuer.to_string());
+        self
+    }
+
+    /// Allows for clocks being out of sync by up to `leeway` when checking
+    /// whether a token has expired.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.validation.leeway = leeway.as_secs();
+        self
+    }
+}
+
+impl Middleware for Jwt {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let jwt = self.clone();
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                let token = match bearer_token(&req) {
+                    Some(token) => token,
+                    None => return unauthorized("Bearer"),
+                };
+
+                match jsonwebtoken::decode::<Value>(&token, &jwt.key, &jwt.validation) {
+                    Ok(data) => {
+                        req.extensions_mut().insert(JwtClaims(data.claims));
+                        handler.call(req)
+                    }
+                    Err(_) => unauthorized("Bearer error=\"invalid_token\""),
+                }
+            },
+        )
+        .wrap(handler)
+    }
+}
+
+fn bearer_token(req: &Request<Body>) -> Option<String> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = header.splitn(2, ' ');
+    if parts.next()?.eq_ignore_ascii_case("bearer") {
+        Some(parts.next()?.trim().to_string())
+    } else {
+        None
+    }
+}
+
+fn unauthorized(challenge: &'static str) -> ResponseFuture {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+    Box::new(future::ok(response))
+}
@@ -0,0 +1,79 @@
+use std::time::Instant;
+
+use futures::Future;
+use hyper::body::Payload;
+use hyper::header::{HeaderMap, CONTENT_LENGTH};
+use hyper::{Body, Request};
+use log::{error, log, Level};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Middleware that logs every request through the `log` crate, once its
+/// response is ready: the method, path, status, the length of the request
+/// and response bodies and how long the response took.
+///
+/// Body lengths are taken from `Content-Length` or from bodies whose length
+/// is known up front, and logged as `-` for streamed bodies. Requests whose
+/// handler fails are logged as errors.
+#[derive(Clone, Copy, Debug)]
+pub struct Logger {
+    level: Level,
+}
+
+impl Logger {
+    pub fn new() -> Self {
+        Logger::default()
+    }
+
+    /// Sets the level requests are logged at, `Info` by default.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger { level: Level::Info }
+    }
+}
+
+impl Middleware for Logger {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let level = self.level;
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let start = Instant::now();
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let received = body_length(req.headers(), req.body());
+
+            Box::new(handler.call(req).then(move |result| {
+                let elapsed = start.elapsed();
+                match &result {
+                    Ok(response) => log!(
+                        level,
+                        "{} {} {} {} {} {:?}",
+                        method,
+                        path,
+                        response.status().as_u16(),
+                        received,
+                        body_length(response.headers(), response.body()),
+                        elapsed
+                    ),
+                    Err(err) => error!("{} {} failed after {:?}: {}", method, path, elapsed, err),
+                }
+                result
+            })) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
+
+fn body_length(headers: &HeaderMap, body: &Body) -> String {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+        .or_else(|| body.content_length())
+        .map_or_else(|| "-".to_string(), |length| length.to_string())
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use futures::Future;
+use hyper::{Body, Request, Response};
+
+use super::{from_fn, Middleware};
+use crate::error::{self, Error};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Middleware that renders the [`Error`]s handlers fail with its own way,
+/// replacing the status, headers and body of their responses with those
+/// of the response its mapper produces. The error stays in the response
+/// extensions, so that the mappers of outer layers can still see it.
+#[derive(Clone)]
+pub struct MapError {
+    mapper: Arc<dyn Fn(&Error) -> Response<Body> + Send + Sync>,
+}
+
+impl MapError {
+    pub fn new<F>(mapper: F) -> Self
+    where
+        F: Fn(&Error) -> Response<Body> + Send + Sync + 'static,
+    {
+        MapError {
+            mapper: Arc::new(mapper),
+        }
+    }
+
+    /// Renders errors as plain text rather than as problem documents.
+    pub fn plain_text() -> Self {
+        MapError::new(error::plain_text)
+    }
+}
+
+impl Middleware for MapError {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let mapper = self.mapper.clone();
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let mapper = mapper.clone();
+            Box::new(handler.call(req).map(move |mut response| {
+                let err = match response.extensions_mut().remove::<Error>() {
+                    Some(err) => err,
+                    None => return response,
+                };
+
+                let (parts, body) = mapper(&err).into_parts();
+                *response.status_mut() = parts.status;
+                *response.headers_mut() = parts.headers;
+                *response.body_mut() = body;
+                response.extensions_mut().insert(err);
+                response
+            })) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use hyper::{Body, Method, Request};
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+use crate::router::MatchedRoute;
+
+/// The route label of requests that matched no route.
+const UNMATCHED: &str = "unmatched";
+
+/// The method label of requests with an extension method, which clients
+/// could otherwise make up without end.
+const OTHER_METHOD: &str = "other";
+
+const STANDARD_METHODS: &[Method] = &[
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::CONNECT,
+    Method::OPTIONS,
+    Method::TRACE,
+    Method::PATCH,
+];
+
+/// Middleware that records Prometheus metrics for every request:
+///
+/// - `http_requests_total`, a counter labelled with the method, route and
+///   status class, such as `2xx`, or `error` if the handler failed, with
+///   methods other than the standard ones labelled `other`
+/// - `http_request_duration_seconds`, a histogram of how long responses
+///   took to be ready, labelled with the method and route
+///
+/// Routes are labelled with their pattern, so it has to wrap a router for
+/// requests to be told apart by the route that served them. The metrics
+/// are registered with a [`Registry`] that callers can gather them from
+/// to export them however they like.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::with_registry(Registry::new()).expect("a new registry has no metrics yet")
+    }
+
+    /// Registers the metrics with `registry`, failing if it already has
+    /// metrics of the same names.
+    pub fn with_registry(registry: Registry) -> Result<Self, prometheus::Error> {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests handled."),
+            &["method", "route", "status"],
+        )?;
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "How long HTTP responses took to be ready.",
+            ),
+            &["method", "route"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(duration.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            requests,
+            duration,
+        })
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Middleware for Metrics {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let metrics = self.clone();
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let start = Instant::now();
+            let method = method_label(req.method());
+            let metrics = metrics.clone();
+
+            Box::new(handler.call(req).then(move |result| {
+                let (route, status) = match &result {
+                    Ok(response) => (
+                        response.extensions().get::<MatchedRoute>().map_or_else(
+                            || UNMATCHED.to_string(),
+                            |matched| matched.pattern.clone(),
+                        ),
+                        format!("{}xx", response.status().as_u16() / 100),
+                    ),
+                    Err(_) => (UNMATCHED.to_string(), "error".to_string()),
+                };
+
+                metrics
+                    .requests
+                    .with_label_values(&[method, &route, &status])
+                    .inc();
+                metrics
+                    .duration
+                    .with_label_values(&[method, &route])
+                    .observe(seconds(start.elapsed()));
+                result
+            })) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
+
+fn method_label(method: &Method) -> &'static str {
+    STANDARD_METHODS
+        .iter()
+        .find(|standard| *standard == method)
+        .map_or(OTHER_METHOD, Method::as_str)
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use futures::{future, Future};
+use hyper::{Body, Request, Response};
+
+use crate::handler::{CloneableService, RequestHandler, ResponseFuture};
+
+mod api_key;
+mod basic_auth;
+mod body_limit;
+mod cache;
+mod catch_panic;
+mod client_ip;
+mod compression;
+mod concurrency;
+mod cors;
+mod decompression;
+mod expect_continue;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod logging;
+mod map_error;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod rate_limit;
+mod request_id;
+mod rewrite;
+mod security_headers;
+mod timeout;
+mod tower;
+mod trace;
+
+pub use self::api_key::{ApiKey, ApiKeyLabel};
+pub use self::basic_auth::{BasicAuth, BasicUser, Verifier};
+pub use self::body_limit::BodyLimit;
+pub use self::cache::Cache;
+pub use self::catch_panic::CatchPanic;
+pub use self::client_ip::{ClientIp, ClientIpLayer};
+pub use self::compression::Compression;
+pub use self::concurrency::ConcurrencyLimit;
+pub use self::cors::Cors;
+pub use self::decompression::Decompression;
+pub use self::expect_continue::{ContinueCheck, ExpectContinue};
+#[cfg(feature = "jwt")]
+pub use self::jwt::{Jwt, JwtClaims};
+pub use self::logging::Logger;
+pub use self::map_error::MapError;
+#[cfg(feature = "metrics")]
+pub use self::metrics::Metrics;
+pub use self::rate_limit::RateLimit;
+pub use self::request_id::{RequestId, RequestIdLayer};
+pub use self::rewrite::Rewrite;
+pub use self::security_headers::SecurityHeaders;
+pub use self::timeout::{Deadline, Timeout};
+pub use self::tower::{service_fn, HandlerService, ServiceFn, TowerLayer};
+pub use self::trace::Trace;
+
+pub(crate) use self::timeout::{set_deadline, with_timeout};
+
+/// Code that runs around a handler, e.g. to log requests or to turn away
+/// unauthenticated ones.
+///
+/// Middleware wraps a handler in another handler, which can act on the
+/// request before passing it on and on the response once its future
+/// resolves, or answer the request itself without calling the inner
+/// handler. Wrapping a handler several times nests the layers like an
+/// onion, with the outermost layer seeing the request first and the
+/// response last.
+///
+/// Middleware can be applied to a single route with `Route::wrap`, to a
+/// group of routes through `scope` in `routes!`, or to every request a
+/// dispatcher handles with `Router::wrap`. Any closure taking and returning
+/// a [`RequestHandler`] is middleware.
+pub trait Middleware: Send + Sync {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(RequestHandler) -> RequestHandler + Send + Sync,
+{
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        (self)(handler)
+    }
+}
+
+/// Creates middleware from a function that is handed each request along
+/// with the handler it wraps, which it can call or not as it sees fit.
+pub fn from_fn<F>(f: F) -> impl Middleware + Clone
+where
+    F: Fn(Request<Body>, &mut RequestHandler) -> ResponseFuture + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    move |handler: RequestHandler| {
+        Box::new(Layer {
+            f: f.clone(),
+            handler,
+        }) as RequestHandler
+    }
+}
+
+/// Creates middleware that runs `f` on each request before it reaches the
+/// handler. Returning a response from `f` answers the request with it
+/// without calling the handler.
+pub fn before<F>(f: F) -> impl Middleware + Clone
+where
+    F: Fn(Request<Body>) -> Result<Request<Body>, Response<Body>> + Send + Sync + 'static,
+{
+    from_fn(move |req, handler| match f(req) {
+        Ok(req) => handler.call(req),
+        Err(response) => Box::new(future::ok(response)) as ResponseFuture,
+    })
+}
+
+/// Creates middleware that runs `f` on each response once the handler has
+/// produced it.
+pub fn after<F>(f: F) -> impl Middleware + Clone
+where
+    F: Fn(Response<Body>) -> Response<Body> + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    from_fn(move |req, handler| {
+        let f = f.clone();
+        Box::new(handler.call(req).map(move |response| f(response))) as ResponseFuture
+    })
+}
+
+/// A handler wrapped by middleware created with [`from_fn`].
+struct Layer<F> {
+    f: Arc<F>,
+    handler: RequestHandler,
+}
+
+impl<F> Clone for Layer<F> {
+    fn clone(&self) -> Self {
+        Layer {
+            f: self.f.clone(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<F> CloneableService for Layer<F>
+where
+    F: Fn(Request<Body>, &mut RequestHandler) -> ResponseFuture,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn call(&mut self, req: Request<Body>) -> ResponseFuture {
+        (self.f)(req, &mut self.handler)
+    }
+}
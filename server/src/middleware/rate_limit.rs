@@ -0,0 +1,102 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use futures::future;
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::{Body, Request, Response, StatusCode};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Token bucket state: tokens are added continuously at the configured
+/// rate up to the bucket's capacity, and each request takes one.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Middleware that limits how fast requests are handled, answering those
+/// over the limit with a `429 Too Many Requests` and a `Retry-After` header
+/// saying when to try again.
+///
+/// Each handler the middleware wraps gets a bucket of its own, so wrapping
+/// the whole dispatcher with `Router::wrap` limits all requests together,
+/// while wrapping routes or a scope limits every route separately.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Tokens added per second.
+    rate: f64,
+    capacity: f64,
+}
+
+impl RateLimit {
+    /// Allows `requests` requests every `per`, all of which may arrive at
+    /// once.
+    pub fn new(requests: u32, per: Duration) -> Self {
+        let per = seconds(per);
+        assert!(per > 0.0, "rate limit period must not be zero");
+
+        RateLimit {
+            rate: f64::from(requests) / per,
+            capacity: f64::from(requests),
+        }
+    }
+
+    /// Sets how many requests may arrive at once, after a quiet period,
+    /// before the rate applies.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.capacity = f64::from(burst);
+        self
+    }
+
+    /// Takes a token from `bucket`, or returns how many whole seconds it
+    /// will take until one is available.
+    fn acquire(&self, bucket: &mut Bucket) -> Result<(), u64> {
+        let now = Instant::now();
+        let elapsed = seconds(now.duration_since(bucket.updated));
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.rate).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let limit = *self;
+        let bucket = Arc::new(Mutex::new(Bucket {
+            tokens: limit.capacity,
+            updated: Instant::now(),
+        }));
+
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let acquired = {
+                let mut bucket = bucket.lock().unwrap_or_else(PoisonError::into_inner);
+                limit.acquire(&mut bucket)
+            };
+
+            match acquired {
+                Ok(()) => handler.call(req),
+                Err(retry_after) => {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                    Box::new(future::ok(response)) as ResponseFuture
+                }
+            }
+        })
+        .wrap(handler)
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
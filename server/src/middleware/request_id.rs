@@ -0,0 +1,84 @@
+use futures::Future;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Request};
+use uuid::Uuid;
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// The identifier of a request, as found in the request extensions once
+/// the [`RequestIdLayer`] middleware has seen it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Middleware that tags every request with an identifier, taken from its
+/// `X-Request-Id` header or generated as a random UUID if it has none. The
+/// identifier is added to the request extensions as a [`RequestId`] and
+/// echoed in the same header of the response, so that the logs of a client
+/// and server can be correlated.
+#[derive(Clone, Debug)]
+pub struct RequestIdLayer {
+    header: HeaderName,
+}
+
+impl RequestIdLayer {
+    pub fn new() -> Self {
+        RequestIdLayer::default()
+    }
+
+    /// Reads and writes the identifier in the header `name` instead.
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = HeaderName::from_bytes(name.as_bytes())
+            .unwrap_or_else(|err| panic!("invalid header name {:?}: {}", name, err));
+        self
+    }
+}
+
+impl Default for RequestIdLayer {
+    fn default() -> Self {
+        RequestIdLayer {
+            header: HeaderName::from_static("x-request-id"),
+        }
+    }
+}
+
+impl Middleware for RequestIdLayer {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let header = self.header.clone();
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                let existing = req
+                    .headers()
+                    .get(&header)
+                    .filter(|value| value.to_str().is_ok())
+                    .cloned();
+                let value = match existing {
+                    Some(value) => value,
+                    None => {
+                        let id = Uuid::new_v4().to_hyphenated().to_string();
+                        let value =
+                            HeaderValue::from_str(&id).expect("UUIDs are valid header values");
+                        req.headers_mut().insert(header.clone(), value.clone());
+                        value
+                    }
+                };
+
+                let id = value.to_str().expect("checked above").to_string();
+                req.extensions_mut().insert(RequestId(id));
+
+                let header = header.clone();
+                Box::new(handler.call(req).map(move |mut response| {
+                    response.headers_mut().insert(header, value);
+                    response
+                })) as ResponseFuture
+            },
+        )
+        .wrap(handler)
+    }
+}
@@ -0,0 +1,118 @@
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Request};
+
+use super::{from_fn, Middleware};
+use crate::handler::RequestHandler;
+use crate::router::with_path;
+
+#[derive(Clone, Debug)]
+enum Rule {
+    StripPrefix(String),
+    AddPrefix(String),
+    SetHeader(HeaderName, HeaderValue),
+    RemoveHeader(HeaderName),
+}
+
+/// Middleware that rewrites requests before passing them on, by changing
+/// their path or headers. Wrapping a router in it lets the same routes
+/// serve requests that arrive under other prefixes.
+///
+/// Rules are applied in the order they're added. Prefixes only match whole
+/// segments, so stripping `/v1` turns `/v1/users` into `/users` and `/v1`
+/// into `/`, but leaves `/v10` alone.
+#[derive(Clone, Debug, Default)]
+pub struct Rewrite {
+    rules: Vec<Rule>,
+}
+
+impl Rewrite {
+    pub fn new() -> Self {
+        Rewrite::default()
+    }
+
+    /// Removes `prefix` from the start of the path, if it's there.
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        self.rules
+            .push(Rule::StripPrefix(prefix.trim_end_matches('/').to_string()));
+        self
+    }
+
+    /// Adds `prefix` to the start of the path.
+    pub fn add_prefix(mut self, prefix: &str) -> Self {
+        if !prefix.starts_with('/') {
+            panic!("prefix {:?} doesn't start with a slash", prefix);
+        }
+        self.rules
+            .push(Rule::AddPrefix(prefix.trim_end_matches('/').to_string()));
+        self
+    }
+
+    /// Sets the header `name` to `value`, replacing any values it had.
+    pub fn set_header(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .unwrap_or_else(|err| panic!("invalid header name {:?}: {}", name, err));
+        let value = HeaderValue::from_str(value)
+            .unwrap_or_else(|err| panic!("invalid value {:?} for {}: {}", value, name, err));
+        self.rules.push(Rule::SetHeader(name, value));
+        self
+    }
+
+    pub fn remove_header(mut self, name: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .unwrap_or_else(|err| panic!("invalid header name {:?}: {}", name, err));
+        self.rules.push(Rule::RemoveHeader(name));
+        self
+    }
+
+    fn rewrite(&self, req: &mut Request<Body>) {
+        for rule in &self.rules {
+            match rule {
+                Rule::StripPrefix(prefix) => {
+                    let path = req.uri().path();
+                    let rest = if path.starts_with(prefix.as_str()) {
+                        &path[prefix.len()..]
+                    } else {
+                        continue;
+                    };
+                    if rest.is_empty() {
+                        set_path(req, "/");
+                    } else if rest.starts_with('/') {
+                        let rest = rest.to_string();
+                        set_path(req, &rest);
+                    }
+                }
+                Rule::AddPrefix(prefix) => {
+                    let path = format!("{}{}", prefix, req.uri().path());
+                    set_path(req, &path);
+                }
+                Rule::SetHeader(name, value) => {
+                    req.headers_mut().insert(name.clone(), value.clone());
+                }
+                Rule::RemoveHeader(name) => {
+                    req.headers_mut().remove(name);
+                }
+            }
+        }
+    }
+}
+
+impl Middleware for Rewrite {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let rewrite = self.clone();
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                rewrite.rewrite(&mut req);
+                handler.call(req)
+            },
+        )
+        .wrap(handler)
+    }
+}
+
+/// Replaces the path of `req`, leaving it as it was if the new one isn't
+/// valid in a URI.
+fn set_path(req: &mut Request<Body>, path: &str) {
+    if let Some(uri) = with_path(req.uri(), path) {
+        *req.uri_mut() = uri;
+    }
+}
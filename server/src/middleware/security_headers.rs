@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use futures::Future;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY,
+    STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use hyper::{Body, Request};
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// Middleware that adds hardening headers to every response that doesn't
+/// already set them. By default these are:
+///
+/// - `Strict-Transport-Security: max-age=31536000; includeSubDomains`
+/// - `X-Content-Type-Options: nosniff`
+/// - `X-Frame-Options: DENY`
+/// - `Referrer-Policy: no-referrer`
+/// - `Content-Security-Policy: default-src 'self'`
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    headers: HeaderMap,
+}
+
+impl SecurityHeaders {
+    pub fn new() -> Self {
+        SecurityHeaders::default()
+    }
+
+    /// Sets how long clients should only use HTTPS for the host, and
+    /// whether that applies to its subdomains too.
+    pub fn hsts(self, max_age: Duration, include_subdomains: bool) -> Self {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        self.header(STRICT_TRANSPORT_SECURITY, &value)
+    }
+
+    pub fn frame_options(self, value: &str) -> Self {
+        self.header(X_FRAME_OPTIONS, value)
+    }
+
+    pub fn referrer_policy(self, value: &str) -> Self {
+        self.header(REFERRER_POLICY, value)
+    }
+
+    pub fn content_security_policy(self, value: &str) -> Self {
+        self.header(CONTENT_SECURITY_POLICY, value)
+    }
+
+    /// Stops adding the header `name`.
+    pub fn remove(mut self, name: &str) -> Self {
+        self.headers.remove(name);
+        self
+    }
+
+    fn header(mut self, name: HeaderName, value: &str) -> Self {
+        let value = HeaderValue::from_str(value)
+            .unwrap_or_else(|err| panic!("invalid value {:?} for {}: {}", value, name, err));
+        self.headers.insert(name, value);
+        self
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static("max-age=31536000; includeSubDomains"),
+        );
+        headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+        headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        headers.insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+        headers.insert(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("default-src 'self'"),
+        );
+        SecurityHeaders { headers }
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let headers = self.headers.clone();
+        from_fn(move |req: Request<Body>, handler: &mut RequestHandler| {
+            let headers = headers.clone();
+            Box::new(handler.call(req).map(move |mut response| {
+                for (name, value) in &headers {
+                    if !response.headers().contains_key(name) {
+                        response.headers_mut().insert(name.clone(), value.clone());
+                    }
+                }
+                response
+            })) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
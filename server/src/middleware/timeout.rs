@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::timer;
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+
+/// When a request has to be answered by, as found in its extensions when a
+/// timeout applies to it, so that handlers can pass it on to the services
+/// they call or give up early rather than do work that would be cut off.
+/// Where several timeouts apply, it's the earliest of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    /// How long is left until the deadline, or zero if it has passed.
+    pub fn remaining(&self) -> Duration {
+        let now = Instant::now();
+        if self.0 > now {
+            self.0 - now
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+/// Middleware that answers with a `504 Gateway Timeout` if the handler's
+/// response isn't ready within a duration.
+///
+/// Wrapping a router in it puts a limit on every request, on top of any
+/// timeouts set on individual routes: whichever is shorter applies.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeout {
+    timeout: Duration,
+}
+
+impl Timeout {
+    pub fn new(timeout: Duration) -> Self {
+        Timeout { timeout }
+    }
+}
+
+impl Middleware for Timeout {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let timeout = self.timeout;
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                set_deadline(&mut req, timeout);
+                with_timeout(handler.call(req), timeout)
+            },
+        )
+        .wrap(handler)
+    }
+}
+
+/// Records the [`Deadline`] of a request that has to be answered within
+/// `timeout`, unless it already has an earlier one.
+pub(crate) fn set_deadline(req: &mut Request<Body>, timeout: Duration) {
+    let deadline = Deadline(Instant::now() + timeout);
+    let earlier = req
+        .extensions()
+        .get::<Deadline>()
+        .map_or(false, |existing| *existing <= deadline);
+    if !earlier {
+        req.extensions_mut().insert(deadline);
+    }
+}
+
+/// Resolves to a `504 Gateway Timeout` if `response` isn't ready within
+/// `timeout`.
+pub(crate) fn with_timeout(response: ResponseFuture, timeout: Duration) -> ResponseFuture {
+    Box::new(timer::Timeout::new(response, timeout).or_else(|err| {
+        if err.is_elapsed() {
+            Ok(status(StatusCode::GATEWAY_TIMEOUT))
+        } else {
+            match err.into_inner() {
+                Some(err) => Err(err),
+                // The timer itself failed, e.g. because the runtime is
+                // shutting down.
+                None => Ok(status(StatusCode::INTERNAL_SERVER_ERROR)),
+            }
+        }
+    }))
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
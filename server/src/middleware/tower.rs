@@ -0,0 +1,148 @@
+use std::error::Error;
+
+use futures::{try_ready, Async, Future, Poll};
+use hyper::{Body, Request, Response, StatusCode};
+use log::error;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::Middleware;
+use crate::handler::{CloneableService, RequestHandler, ResponseFuture};
+
+/// A handler as a `tower::Service`, e.g. to hand it to a `tower` layer.
+#[derive(Clone)]
+pub struct HandlerService(RequestHandler);
+
+impl HandlerService {
+    pub fn new(handler: RequestHandler) -> Self {
+        HandlerService(handler)
+    }
+}
+
+impl Service<Request<Body>> for HandlerService {
+    type Response = Response<Body>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), hyper::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> ResponseFuture {
+        self.0.call(req)
+    }
+}
+
+/// A function handling requests, such as the dispatcher returned by
+/// `make_req_dispatcher`, as a `tower::Service`.
+#[derive(Clone)]
+pub struct ServiceFn<F>(F);
+
+/// Turns `f` into a `tower::Service`.
+pub fn service_fn<F>(f: F) -> ServiceFn<F>
+where
+    F: FnMut(Request<Body>) -> ResponseFuture,
+{
+    ServiceFn(f)
+}
+
+impl<F> Service<Request<Body>> for ServiceFn<F>
+where
+    F: FnMut(Request<Body>) -> ResponseFuture,
+{
+    type Response = Response<Body>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), hyper::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> ResponseFuture {
+        (self.0)(req)
+    }
+}
+
+/// Middleware applying a `tower::Layer`, so that middleware written for
+/// `tower` can be used with routes and routers.
+///
+/// Each request is handed to a clone of the service the layer produces
+/// once it's ready. As handlers can only fail with a `hyper::Error`, the
+/// service failing with any other error is logged and answered with a
+/// `500 Internal Server Error`.
+#[derive(Clone, Debug)]
+pub struct TowerLayer<L>(L);
+
+impl<L> TowerLayer<L> {
+    pub fn new(layer: L) -> Self {
+        TowerLayer(layer)
+    }
+}
+
+impl<L, S> Middleware for TowerLayer<L>
+where
+    L: Layer<HandlerService, Service = S> + Send + Sync,
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send + 'static,
+{
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        Box::new(FromService(self.0.layer(HandlerService(handler))))
+    }
+}
+
+/// A `tower::Service` as a handler.
+#[derive(Clone)]
+struct FromService<S>(S);
+
+impl<S> CloneableService for FromService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send + 'static,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn call(&mut self, req: Request<Body>) -> ResponseFuture {
+        let ready = Ready(Some(self.0.clone()));
+        Box::new(
+            ready
+                .and_then(move |mut service| service.call(req))
+                .or_else(|err| {
+                    let err: Box<dyn Error + Send + Sync> = err.into();
+                    match err.downcast::<hyper::Error>() {
+                        Ok(err) => Err(*err),
+                        Err(err) => {
+                            error!("service failed: {}", err);
+                            let mut response = Response::new(Body::empty());
+                            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                            Ok(response)
+                        }
+                    }
+                }),
+        )
+    }
+}
+
+/// Resolves to the service once it's ready to be called.
+struct Ready<S>(Option<S>);
+
+impl<S> Future for Ready<S>
+where
+    S: Service<Request<Body>>,
+{
+    type Item = S;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<S, S::Error> {
+        try_ready!(self
+            .0
+            .as_mut()
+            .expect("polled after it was ready")
+            .poll_ready());
+        Ok(Async::Ready(self.0.take().expect("checked above")))
+    }
+}
@@ -0,0 +1,58 @@
+use futures::Future;
+use hyper::{Body, Request};
+use tracing::{field, info_span};
+use tracing_futures::Instrument;
+
+use super::{from_fn, Middleware};
+use crate::handler::{RequestHandler, ResponseFuture};
+use crate::router::MatchedRoute;
+
+/// Middleware that runs every request inside a `tracing` span named
+/// `request`, so that events emitted by handlers, and by the futures they
+/// return, are recorded under it.
+///
+/// The span carries the request's method and path, and once the response
+/// is ready the pattern of the route that served it and its status. Routes
+/// are only known when the middleware wraps the whole dispatcher through
+/// `Router::wrap`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trace;
+
+impl Trace {
+    pub fn new() -> Self {
+        Trace
+    }
+}
+
+impl Middleware for Trace {
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        from_fn(|req: Request<Body>, handler: &mut RequestHandler| {
+            let span = info_span!(
+                "request",
+                method = %req.method(),
+                path = %req.uri().path(),
+                route = field::Empty,
+                status = field::Empty,
+            );
+
+            let response = {
+                let _entered = span.enter();
+                handler.call(req)
+            };
+
+            let recorded = span.clone();
+            Box::new(
+                response
+                    .map(move |response| {
+                        if let Some(route) = response.extensions().get::<MatchedRoute>() {
+                            recorded.record("route", &route.pattern.as_str());
+                        }
+                        recorded.record("status", &response.status().as_u16());
+                        response
+                    })
+                    .instrument(span),
+            ) as ResponseFuture
+        })
+        .wrap(handler)
+    }
+}
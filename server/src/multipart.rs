@@ -0,0 +1,550 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use bytes::{Bytes, BytesMut};
+use futures::{try_ready, Async, Poll, Stream};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_DISPOSITION};
+use hyper::{Body, Request, Response, StatusCode};
+use typed_headers::mime::{self, Mime};
+use typed_headers::{ContentType, HeaderMapExt};
+
+use crate::body::{content_type, rejection};
+
+/// The most headers a single part may have.
+const MAX_HEADERS: usize = 16;
+
+/// The longest the headers of a single part may be, in bytes, so that a
+/// client that never ends them can't make them take up ever more memory.
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+/// The error a [`Multipart`] stream or one of its fields fails with.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The body couldn't be read.
+    Body(hyper::Error),
+    /// The body isn't valid `multipart/form-data`.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Body(err) => write!(f, "failed to read multipart body: {}", err),
+            MultipartError::Malformed(reason) => write!(f, "malformed multipart body: {}", reason),
+        }
+    }
+}
+
+impl Error for MultipartError {}
+
+/// A `multipart/form-data` request body, read as a stream of its fields
+/// without buffering it whole.
+///
+/// Each [`Field`] is itself a stream of the field's data, which has to be
+/// read before polling for the next field: whatever is left of a field
+/// when the next one is polled for is skipped.
+pub struct Multipart {
+    parser: Arc<Mutex<Parser>>,
+}
+
+impl Multipart {
+    /// Starts reading the body of `req`, failing with a `415 Unsupported
+    /// Media Type` if it isn't declared to be `multipart/form-data` or a
+    /// `400 Bad Request` if it has no boundary.
+    pub fn new(req: Request<Body>) -> Result<Self, Response<Body>> {
+        let content_type = content_type(&req)
+            .filter(|content_type| {
+                content_type.type_() == mime::MULTIPART && content_type.subtype() == mime::FORM_DATA
+            })
+            .ok_or_else(|| rejection(StatusCode::UNSUPPORTED_MEDIA_TYPE, None))?;
+        let boundary = content_type.get_param(mime::BOUNDARY).ok_or_else(|| {
+            rejection(
+                StatusCode::BAD_REQUEST,
+                Some("multipart body has no boundary".to_string()),
+            )
+        })?;
+
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_str().as_bytes());
+
+        Ok(Multipart {
+            parser: Arc::new(Mutex::new(Parser {
+                body: req.into_body(),
+                // The first delimiter isn't preceded by a line break, so
+                // one is made up for it to be found like the others.
+                buffer: BytesMut::from(&b"\r\n"[..]),
+                delimiter,
+                state: State::Delimiter,
+                field: 0,
+            })),
+        })
+    }
+}
+
+impl Stream for Multipart {
+    type Item = Field;
+    type Error = MultipartError;
+
+    fn poll(&mut self) -> Poll<Option<Field>, MultipartError> {
+        let mut parser = lock(&self.parser);
+        let headers = match try_ready!(parser.poll_headers()) {
+            Some(headers) => headers,
+            None => return Ok(Async::Ready(None)),
+        };
+        Ok(Async::Ready(Some(Field::new(
+            headers,
+            self.parser.clone(),
+            parser.field,
+        )?)))
+    }
+}
+
+/// A field of a [`Multipart`] body, which is a stream of its data.
+pub struct Field {
+    name: String,
+    file_name: Option<String>,
+    content_type: Option<Mime>,
+    headers: HeaderMap,
+    parser: Arc<Mutex<Parser>>,
+    index: usize,
+}
+
+impl Field {
+    fn new(
+        headers: HeaderMap,
+        parser: Arc<Mutex<Parser>>,
+        index: usize,
+    ) -> Result<Self, MultipartError> {
+        let disposition = headers
+            .get(CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(MultipartError::Malformed("part has no Content-Disposition"))?;
+        let mut params = disposition.splitn(2, ';');
+        if !params
+            .next()
+            .map_or(false, |kind| kind.trim().eq_ignore_ascii_case("form-data"))
+        {
+            return Err(MultipartError::Malformed("part isn't form data"));
+        }
+
+        let mut name = None;
+        let mut file_name = None;
+        for (key, value) in parse_params(params.next().unwrap_or_default()) {
+            if key.eq_ignore_ascii_case("name") {
+                name = Some(value);
+            } else if key.eq_ignore_ascii_case("filename") {
+                file_name = Some(value);
+            }
+        }
+
+        let content_type = match headers.typed_get::<ContentType>() {
+            Ok(content_type) => content_type.map(|ContentType(content_type)| content_type),
+            Err(_) => {
+                return Err(MultipartError::Malformed(
+                    "part has an invalid Content-Type",
+                ))
+            }
+        };
+
+        Ok(Field {
+            name: name.ok_or(MultipartError::Malformed("part has no name"))?,
+            file_name,
+            content_type,
+            headers,
+            parser,
+            index,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the file the field was uploaded from, if any.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_ref().map(String::as_str)
+    }
+
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.content_type.as_ref()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+}
+
+impl Stream for Field {
+    type Item = Bytes;
+    type Error = MultipartError;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, MultipartError> {
+        lock(&self.parser).poll_data(self.index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Looking for the next delimiter, skipping anything before it.
+    Delimiter,
+    /// Right after a delimiter, which is followed by a line break or, for
+    /// the last one, by `--`.
+    AfterDelimiter,
+    Headers,
+    Data,
+    Done,
+}
+
+struct Parser {
+    body: Body,
+    buffer: BytesMut,
+    /// A line break, `--` and the boundary.
+    delimiter: Vec<u8>,
+    state: State,
+    /// The number of fields found so far, which identifies the last one.
+    field: usize,
+}
+
+impl Parser {
+    fn poll_headers(&mut self) -> Poll<Option<HeaderMap>, MultipartError> {
+        loop {
+            match self.state {
+                State::Delimiter | State::Data => match self.find_delimiter() {
+                    Some(position) => {
+                        self.buffer.advance(position + self.delimiter.len());
+                        self.state = State::AfterDelimiter;
+                    }
+                    None => {
+                        self.state = State::Delimiter;
+                        let keep = self.delimiter.len() - 1;
+                        if self.buffer.len() > keep {
+                            let skip = self.buffer.len() - keep;
+                            self.buffer.advance(skip);
+                        }
+                        try_ready!(self.fill());
+                    }
+                },
+                State::AfterDelimiter => {
+                    if self.buffer.len() < 2 {
+                        try_ready!(self.fill());
+                    } else if self.buffer.starts_with(b"--") {
+                        self.state = State::Done;
+                    } else if self.buffer.starts_with(b"\r\n") {
+                        self.buffer.advance(2);
+                        self.state = State::Headers;
+                    } else {
+                        return Err(MultipartError::Malformed(
+                            "delimiter is followed by garbage",
+                        ));
+                    }
+                }
+                State::Headers => {
+                    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+                    match httparse::parse_headers(&self.buffer, &mut headers) {
+                        Ok(httparse::Status::Complete((length, headers))) => {
+                            let headers = header_map(headers)?;
+                            self.buffer.advance(length);
+                            self.state = State::Data;
+                            self.field += 1;
+                            return Ok(Async::Ready(Some(headers)));
+                        }
+                        Ok(httparse::Status::Partial) => {
+                            if self.buffer.len() > MAX_HEADERS_SIZE {
+                                return Err(MultipartError::Malformed("part headers are too long"));
+                            }
+                            try_ready!(self.fill());
+                        }
+                        Err(_) => {
+                            return Err(MultipartError::Malformed("part has invalid headers"))
+                        }
+                    }
+                }
+                State::Done => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+
+    fn poll_data(&mut self, field: usize) -> Poll<Option<Bytes>, MultipartError> {
+        if self.state != State::Data || self.field != field {
+            return Ok(Async::Ready(None));
+        }
+
+        loop {
+            match self.find_delimiter() {
+                Some(0) => {
+                    self.buffer.advance(self.delimiter.len());
+                    self.state = State::AfterDelimiter;
+                    return Ok(Async::Ready(None));
+                }
+                Some(position) => {
+                    return Ok(Async::Ready(Some(self.buffer.split_to(position).freeze())));
+                }
+                None => {
+                    // The end of the buffer could be the start of a
+                    // delimiter, so it's held back until there's more.
+                    let keep = self.delimiter.len() - 1;
+                    if self.buffer.len() > keep {
+                        let length = self.buffer.len() - keep;
+                        return Ok(Async::Ready(Some(self.buffer.split_to(length).freeze())));
+                    }
+                    try_ready!(self.fill());
+                }
+            }
+        }
+    }
+
+    fn find_delimiter(&self) -> Option<usize> {
+        self.buffer
+            .windows(self.delimiter.len())
+            .position(|window| window == &self.delimiter[..])
+    }
+
+    /// Reads the next chunk of the body into the buffer.
+    fn fill(&mut self) -> Poll<(), MultipartError> {
+        match self.body.poll().map_err(MultipartError::Body)? {
+            Async::Ready(Some(chunk)) => {
+                self.buffer.extend_from_slice(&chunk);
+                Ok(Async::Ready(()))
+            }
+            Async::Ready(None) => Err(MultipartError::Malformed(
+                "body ended before the last delimiter",
+            )),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+fn lock(parser: &Mutex<Parser>) -> MutexGuard<'_, Parser> {
+    parser.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn header_map(headers: &[httparse::Header<'_>]) -> Result<HeaderMap, MultipartError> {
+    let mut map = HeaderMap::new();
+    for header in headers {
+        let name = HeaderName::from_bytes(header.name.as_bytes())
+            .map_err(|_| MultipartError::Malformed("part has an invalid header name"))?;
+        let value = HeaderValue::from_bytes(header.value)
+            .map_err(|_| MultipartError::Malformed("part has an invalid header value"))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// Parses the `key=value` parameters of a `Content-Disposition` header,
+/// whose values may be quoted.
+fn parse_params(params: &str) -> Vec<(String, String)> {
+    let mut parsed = vec![];
+    let mut chars = params.chars().peekable();
+
+    loop {
+        let key: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        let key = key.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        if key.is_empty() {
+            return parsed;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            // Skip anything up to the next parameter.
+            while chars.peek().map_or(false, |&c| c != ';') {
+                chars.next();
+            }
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ';').collect();
+        }
+        parsed.push((key.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+    use hyper::header::CONTENT_TYPE;
+
+    use super::*;
+
+    const BODY: &str = "preamble\r\n\
+                        --XYZ\r\n\
+                        Content-Disposition: form-data; name=\"title\"\r\n\
+                        \r\n\
+                        hello\r\n\
+                        --XYZ\r\n\
+                        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                        Content-Type: text/plain\r\n\
+                        \r\n\
+                        line 1\r\n--XY\r\nline 2\r\n\
+                        --XYZ--\r\n\
+                        epilogue";
+
+    #[derive(Debug, PartialEq)]
+    struct Part {
+        name: String,
+        file_name: Option<String>,
+        content_type: Option<String>,
+        data: String,
+    }
+
+    fn part(name: &str, file_name: Option<&str>, content_type: Option<&str>, data: &str) -> Part {
+        Part {
+            name: name.to_string(),
+            file_name: file_name.map(str::to_string),
+            content_type: content_type.map(str::to_string),
+            data: data.to_string(),
+        }
+    }
+
+    fn request(content_type: &str, body: Body) -> Request<Body> {
+        let mut req = Request::new(body);
+        req.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_str(content_type).expect("valid"),
+        );
+        req
+    }
+
+    /// A body sent `size` bytes at a time.
+    fn chunked(body: &str, size: usize) -> Body {
+        let chunks: Vec<Vec<u8>> = body.as_bytes().chunks(size).map(<[u8]>::to_vec).collect();
+        Body::wrap_stream(stream::iter_ok::<_, hyper::Error>(chunks))
+    }
+
+    fn read<S>(fields: S) -> Result<Vec<Part>, MultipartError>
+    where
+        S: Stream<Item = Field, Error = MultipartError>,
+    {
+        fields
+            .and_then(|field| {
+                let name = field.name().to_string();
+                let file_name = field.file_name().map(str::to_string);
+                let content_type = field.content_type().map(ToString::to_string);
+                field.concat2().map(move |data| Part {
+                    name,
+                    file_name,
+                    content_type,
+                    data: String::from_utf8(data.to_vec()).expect("UTF-8"),
+                })
+            })
+            .collect()
+            .wait()
+    }
+
+    fn multipart(body: Body) -> Multipart {
+        Multipart::new(request("multipart/form-data; boundary=XYZ", body)).expect("multipart")
+    }
+
+    fn expected() -> Vec<Part> {
+        vec![
+            part("title", None, None, "hello"),
+            part(
+                "file",
+                Some("a.txt"),
+                Some("text/plain"),
+                "line 1\r\n--XY\r\nline 2",
+            ),
+        ]
+    }
+
+    #[test]
+    fn fields_are_read_in_order() {
+        assert_eq!(
+            read(multipart(Body::from(BODY))).expect("valid"),
+            expected()
+        );
+    }
+
+    #[test]
+    fn delimiters_are_found_across_chunks() {
+        for size in 1..8 {
+            assert_eq!(
+                read(multipart(chunked(BODY, size))).expect("valid"),
+                expected(),
+                "chunks of {} bytes",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn unread_fields_are_skipped() {
+        assert_eq!(
+            read(multipart(chunked(BODY, 3)).skip(1)).expect("valid"),
+            &expected()[1..]
+        );
+    }
+
+    #[test]
+    fn truncated_bodies_fail() {
+        let truncated = &BODY[..BODY.find("--XYZ--").expect("has an end")];
+        match read(multipart(Body::from(truncated))) {
+            Err(MultipartError::Malformed(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn endless_headers_fail() {
+        let head = b"--XYZ\r\nContent-Disposition: form-data; name=\"a\"\r\nX-Padding: ".to_vec();
+        let padding = vec![b'a'; 1024];
+        let chunks = stream::iter_ok::<_, hyper::Error>(vec![head])
+            .chain(stream::iter_ok(vec![padding; 64]));
+        match read(multipart(Body::wrap_stream(chunks))) {
+            Err(MultipartError::Malformed("part headers are too long")) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parts_need_a_form_data_disposition_with_a_name() {
+        let body = "--XYZ\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\nx\r\n--XYZ--\r\n";
+        assert!(read(multipart(Body::from(body))).is_err());
+
+        let body = "--XYZ\r\nContent-Disposition: form-data\r\n\r\nx\r\n--XYZ--\r\n";
+        assert!(read(multipart(Body::from(body))).is_err());
+
+        let body = "--XYZ\r\nContent-Type: text/plain\r\n\r\nx\r\n--XYZ--\r\n";
+        assert!(read(multipart(Body::from(body))).is_err());
+    }
+
+    #[test]
+    fn other_bodies_are_rejected() {
+        let status = |content_type: &str| {
+            Multipart::new(request(content_type, Body::empty()))
+                .err()
+                .map(|response| response.status())
+        };
+        assert_eq!(
+            status("application/json"),
+            Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+        assert_eq!(status("multipart/form-data"), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(status("multipart/form-data; boundary=XYZ"), None);
+    }
+
+    #[test]
+    fn disposition_params_may_be_quoted() {
+        assert_eq!(
+            parse_params(r#" name="a"; filename="b \"c\"; d.txt""#),
+            vec![
+                ("name".to_string(), "a".to_string()),
+                ("filename".to_string(), "b \"c\"; d.txt".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_params(" name=a ; size=1"),
+            vec![
+                ("name".to_string(), "a".to_string()),
+                ("size".to_string(), "1".to_string()),
+            ]
+        );
+        assert_eq!(parse_params(""), vec![]);
+    }
+}
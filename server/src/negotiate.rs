@@ -0,0 +1,144 @@
+use hyper::header::ACCEPT;
+use hyper::{Body, Request};
+use typed_headers::mime::Mime;
+
+/// A quality value scaled to an integer between 0 and 1000, so that it can
+/// be compared and used as a sort key.
+pub(crate) type Quality = u16;
+
+pub(crate) const MAX_QUALITY: Quality = 1000;
+
+/// One entry of an `Accept` header, e.g. `text/*;q=0.5`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MediaRange {
+    type_: String,
+    subtype: String,
+    quality: Quality,
+}
+
+impl MediaRange {
+    /// How specifically this range names `media_type`, or `None` if it
+    /// doesn't match it at all.
+    fn specificity(&self, media_type: &Mime) -> Option<u8> {
+        if self.type_ == "*" {
+            Some(0)
+        } else if !self.type_.eq_ignore_ascii_case(media_type.type_().as_str()) {
+            None
+        } else if self.subtype == "*" {
+            Some(1)
+        } else if self
+            .subtype
+            .eq_ignore_ascii_case(media_type.subtype().as_str())
+        {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses the value of an `Accept` header, skipping malformed entries.
+pub(crate) fn parse_accept(header: &str) -> Vec<MediaRange> {
+    header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let mut media_type = parts.next()?.splitn(2, '/');
+            let type_ = media_type.next()?.trim();
+            let subtype = media_type.next()?.trim();
+            if type_.is_empty() || subtype.is_empty() {
+                return None;
+            }
+
+            Some(MediaRange {
+                type_: type_.to_string(),
+                subtype: subtype.to_string(),
+                quality: parse_quality(parts),
+            })
+        })
+        .collect()
+}
+
+/// Parses the value of a header such as `Accept-Encoding` that lists
+/// tokens with optional quality values, skipping empty entries.
+pub(crate) fn parse_tokens(header: &str) -> Vec<(String, Quality)> {
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let token = parts.next()?;
+            if token.is_empty() {
+                return None;
+            }
+            Some((token.to_ascii_lowercase(), parse_quality(parts)))
+        })
+        .collect()
+}
+
+/// The quality given by the `q` parameter among `params`, defaulting to the
+/// maximum.
+fn parse_quality<'a, I>(params: I) -> Quality
+where
+    I: Iterator<Item = &'a str>,
+{
+    params
+        .filter_map(|param| {
+            let mut param = param.splitn(2, '=');
+            match (param.next(), param.next()) {
+                (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("q") => {
+                    value.trim().parse::<f32>().ok()
+                }
+                _ => None,
+            }
+        })
+        .next()
+        .map_or(MAX_QUALITY, |q| {
+            (q.max(0.0).min(1.0) * f32::from(MAX_QUALITY)).round() as Quality
+        })
+}
+
+/// The quality the client assigned to `media_type`, taken from the most
+/// specific matching range as RFC 7231 requires. Zero means the client
+/// won't accept it.
+pub(crate) fn quality(ranges: &[MediaRange], media_type: &Mime) -> Quality {
+    ranges
+        .iter()
+        .filter_map(|range| {
+            range
+                .specificity(media_type)
+                .map(|specificity| (specificity, range.quality))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0, |(_, quality)| quality)
+}
+
+/// Picks the media type to answer `req` with among those a handler
+/// `offered`, going by the quality values of its `Accept` header and
+/// preferring the ones offered first among those the client likes
+/// equally. Requests without an `Accept` header get the first one, and
+/// `None` is returned if the client accepts none of them.
+///
+/// Panics if one of the offered media types is invalid.
+pub fn best_match<'a>(req: &Request<Body>, offered: &[&'a str]) -> Option<&'a str> {
+    let ranges = match req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(accept) => parse_accept(accept),
+        None => return offered.first().cloned(),
+    };
+
+    offered
+        .iter()
+        .rev()
+        .map(|&media_type| {
+            let parsed = media_type
+                .parse()
+                .unwrap_or_else(|err| panic!("invalid media type {:?}: {}", media_type, err));
+            (quality(&ranges, &parsed), media_type)
+        })
+        .filter(|(quality, _)| *quality > 0)
+        .max_by_key(|(quality, _)| *quality)
+        .map(|(_, media_type)| media_type)
+}
@@ -0,0 +1,50 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::mem;
+
+use futures::{try_ready, Async, Poll, Stream};
+use tokio::reactor::Handle;
+use tokio_named_pipes::NamedPipe;
+
+/// The clients connecting to a named pipe, each served over an instance of
+/// the pipe of its own.
+pub(crate) struct Incoming {
+    name: OsString,
+    pipe: NamedPipe,
+}
+
+impl Incoming {
+    /// Creates the first instance of the pipe called `name`, e.g.
+    /// `\\.\pipe\docker_engine`, which clients can connect to right away.
+    pub(crate) fn new<S>(name: S) -> io::Result<Self>
+    where
+        S: AsRef<OsStr>,
+    {
+        let name = name.as_ref().to_os_string();
+        let pipe = NamedPipe::new(&name, &Handle::default())?;
+        Ok(Incoming { name, pipe })
+    }
+}
+
+impl Stream for Incoming {
+    type Item = NamedPipe;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<NamedPipe>, io::Error> {
+        loop {
+            match self.pipe.connect() {
+                Ok(()) => break,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    // The pipe becomes writable once a client connects.
+                    try_ready!(self.pipe.poll_write_ready());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // The connected instance is handed to the client, and the next
+        // client connects to a new one.
+        let next = NamedPipe::new(&self.name, &Handle::default())?;
+        Ok(Async::Ready(Some(mem::replace(&mut self.pipe, next))))
+    }
+}
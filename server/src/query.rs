@@ -0,0 +1,16 @@
+use hyper::{Body, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::body::rejection;
+
+/// Deserializes the query string of `req`, treating a request without one
+/// as having an empty query so that fields with defaults or of `Option`
+/// types can be left out. Fails with a `400 Bad Request` describing the
+/// problem, which a handler can answer the request with as is.
+pub fn query<T>(req: &Request<Body>) -> Result<T, Response<Body>>
+where
+    T: DeserializeOwned,
+{
+    serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+        .map_err(|err| rejection(StatusCode::BAD_REQUEST, Some(err.to_string())))
+}
@@ -0,0 +1,360 @@
+use std::ops::Range;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hyper::header::{
+    HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use uuid::Uuid;
+
+/// A range of bytes requested in a `Range` header, with the last byte
+/// position included as it is in the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The bytes from the first position to the second.
+    FromTo(u64, u64),
+    /// The bytes from a position until the end.
+    From(u64),
+    /// The given number of bytes at the end.
+    Last(u64),
+}
+
+impl ByteRange {
+    /// The bytes this range covers of a representation `length` bytes
+    /// long, or `None` if it covers none of them.
+    pub fn resolve(self, length: u64) -> Option<Range<u64>> {
+        let range = match self {
+            ByteRange::FromTo(first, last) => first..length.min(last.saturating_add(1)),
+            ByteRange::From(first) => first..length,
+            ByteRange::Last(count) => length.saturating_sub(count)..length,
+        };
+        if range.start < range.end {
+            Some(range)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses the value of a `Range` header, returning `None` if it's
+/// malformed or not in bytes, in which case it has to be ignored.
+pub fn parse_range(header: &str) -> Option<Vec<ByteRange>> {
+    let header = header.trim();
+    if header.len() < 6 || !header[..6].eq_ignore_ascii_case("bytes=") {
+        return None;
+    }
+
+    header[6..]
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| {
+            let mut positions = spec.splitn(2, '-');
+            let first = positions.next()?.trim();
+            let last = positions.next()?.trim();
+            match (first.is_empty(), last.is_empty()) {
+                (true, false) => Some(ByteRange::Last(last.parse().ok()?)),
+                (false, true) => Some(ByteRange::From(first.parse().ok()?)),
+                (false, false) => {
+                    let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+                    if first <= last {
+                        Some(ByteRange::FromTo(first, last))
+                    } else {
+                        None
+                    }
+                }
+                (true, true) => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|ranges| !ranges.is_empty())
+}
+
+/// What the `Range` header asks for of a representation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The whole representation, as there's no valid `Range` header or the
+    /// request isn't a `GET`.
+    Full,
+    /// The given ranges of it, in the order they were requested.
+    Partial(Vec<Range<u64>>),
+    /// None of it, as none of the requested ranges overlap it.
+    Unsatisfiable,
+}
+
+/// Works out what the `Range` header of `req` asks for of a representation
+/// `length` bytes long.
+pub fn ranges(req: &Request<Body>, length: u64) -> Ranges {
+    if *req.method() != Method::GET {
+        return Ranges::Full;
+    }
+    let requested = match req
+        .headers()
+        .get(RANGE)
+        .and_then(|header| header.to_str().ok())
+        .and_then(parse_range)
+    {
+        Some(requested) => requested,
+        None => return Ranges::Full,
+    };
+
+    let satisfiable: Vec<_> = requested
+        .into_iter()
+        .filter_map(|range| range.resolve(length))
+        .collect();
+    if satisfiable.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Partial(satisfiable)
+    }
+}
+
+/// The value of the `Content-Range` header for `range` of a representation
+/// `length` bytes long.
+pub fn content_range(range: &Range<u64>, length: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "bytes {}-{}/{}",
+        range.start,
+        range.end - 1,
+        length
+    ))
+    .expect("ranges are valid header values")
+}
+
+/// A `416 Range Not Satisfiable` response for a representation `length`
+/// bytes long.
+pub fn unsatisfiable(length: u64) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+    response.headers_mut().insert(
+        CONTENT_RANGE,
+        HeaderValue::from_str(&format!("bytes */{}", length))
+            .expect("lengths are valid header values"),
+    );
+    response
+}
+
+/// Answers `req` with `data`, or with the parts of it the `Range` header
+/// asks for.
+///
+/// That's a `200 OK` with all of it if there's no valid `Range` header, a
+/// `206 Partial Content` with the requested range, or with a
+/// `multipart/byteranges` body if several were requested, and a `416 Range
+/// Not Satisfiable` if none of them overlap the data. Every response
+/// advertises that ranges are accepted.
+pub fn respond(req: &Request<Body>, data: Bytes, content_type: &str) -> Response<Body> {
+    let length = data.len() as u64;
+    let content_type = HeaderValue::from_str(content_type)
+        .unwrap_or_else(|err| panic!("invalid content type {:?}: {}", content_type, err));
+
+    let mut response = match ranges(req, length) {
+        Ranges::Full => {
+            let mut response = Response::new(Body::from(data));
+            response.headers_mut().insert(CONTENT_TYPE, content_type);
+            response
+        }
+        Ranges::Partial(ref ranges) if ranges.len() == 1 => {
+            let range = &ranges[0];
+            let part = data.slice(range.start as usize, range.end as usize);
+            let mut response = Response::new(Body::from(part));
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            let headers = response.headers_mut();
+            headers.insert(CONTENT_TYPE, content_type);
+            headers.insert(CONTENT_RANGE, content_range(range, length));
+            response
+        }
+        Ranges::Partial(ranges) => multipart(&data, &ranges, &content_type),
+        Ranges::Unsatisfiable => unsatisfiable(length),
+    };
+
+    response
+        .headers_mut()
+        .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response
+}
+
+fn multipart(data: &Bytes, ranges: &[Range<u64>], content_type: &HeaderValue) -> Response<Body> {
+    let boundary = Uuid::new_v4().to_simple().to_string();
+    let length = data.len() as u64;
+
+    let mut body = BytesMut::new();
+    for range in ranges {
+        let headers = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary,
+            content_type.to_str().unwrap_or_default(),
+            content_range(range, length)
+                .to_str()
+                .expect("ranges are ASCII")
+        );
+        body.reserve(headers.len() + (range.end - range.start) as usize);
+        body.put_slice(headers.as_bytes());
+        body.put_slice(&data[range.start as usize..range.end as usize]);
+    }
+    let end = format!("\r\n--{}--\r\n", boundary);
+    body.reserve(end.len());
+    body.put_slice(end.as_bytes());
+
+    let body_length = body.len();
+    let mut response = Response::new(Body::from(body.freeze()));
+    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary))
+            .expect("UUIDs are valid boundaries"),
+    );
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(body_length));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+
+    use super::*;
+
+    fn get(range: Option<&str>) -> Request<Body> {
+        let mut req = Request::new(Body::empty());
+        if let Some(range) = range {
+            req.headers_mut()
+                .insert(RANGE, HeaderValue::from_str(range).expect("valid"));
+        }
+        req
+    }
+
+    fn body(response: Response<Body>) -> Vec<u8> {
+        response
+            .into_body()
+            .concat2()
+            .wait()
+            .expect("in-memory bodies don't fail")
+            .to_vec()
+    }
+
+    #[test]
+    fn ranges_parse_in_every_form() {
+        assert_eq!(
+            parse_range("bytes=0-499"),
+            Some(vec![ByteRange::FromTo(0, 499)])
+        );
+        assert_eq!(parse_range("bytes=500-"), Some(vec![ByteRange::From(500)]));
+        assert_eq!(parse_range("bytes=-500"), Some(vec![ByteRange::Last(500)]));
+        assert_eq!(
+            parse_range(" BYTES=0-1, 5- ,, -2"),
+            Some(vec![
+                ByteRange::FromTo(0, 1),
+                ByteRange::From(5),
+                ByteRange::Last(2)
+            ])
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_are_ignored() {
+        assert_eq!(parse_range("bytes=5-1"), None);
+        assert_eq!(parse_range("items=0-1"), None);
+        assert_eq!(parse_range("bytes="), None);
+        assert_eq!(parse_range("bytes=-"), None);
+        assert_eq!(parse_range("bytes=a-1"), None);
+        assert_eq!(parse_range("bytes=0-1,x"), None);
+        assert_eq!(parse_range("byte"), None);
+    }
+
+    #[test]
+    fn ranges_resolve_against_the_length() {
+        assert_eq!(ByteRange::FromTo(0, 499).resolve(100), Some(0..100));
+        assert_eq!(ByteRange::FromTo(10, 19).resolve(100), Some(10..20));
+        assert_eq!(ByteRange::From(99).resolve(100), Some(99..100));
+        assert_eq!(ByteRange::From(100).resolve(100), None);
+        assert_eq!(ByteRange::Last(500).resolve(100), Some(0..100));
+        assert_eq!(ByteRange::Last(0).resolve(100), None);
+        assert_eq!(
+            ByteRange::FromTo(0, u64::max_value()).resolve(10),
+            Some(0..10)
+        );
+    }
+
+    #[test]
+    fn only_gets_with_valid_ranges_are_partial() {
+        assert_eq!(ranges(&get(None), 10), Ranges::Full);
+        assert_eq!(ranges(&get(Some("lines=1-2")), 10), Ranges::Full);
+        assert_eq!(
+            ranges(&get(Some("bytes=0-1")), 10),
+            Ranges::Partial(vec![0..2])
+        );
+        assert_eq!(
+            ranges(&get(Some("bytes=20-, 30-")), 10),
+            Ranges::Unsatisfiable
+        );
+        assert_eq!(
+            ranges(&get(Some("bytes=20-, -3")), 10),
+            Ranges::Partial(vec![7..10])
+        );
+
+        let mut post = get(Some("bytes=0-1"));
+        *post.method_mut() = Method::POST;
+        assert_eq!(ranges(&post, 10), Ranges::Full);
+    }
+
+    #[test]
+    fn content_ranges_include_the_last_byte() {
+        assert_eq!(content_range(&(0..2), 10), "bytes 0-1/10");
+        assert_eq!(unsatisfiable(10).headers()[CONTENT_RANGE], "bytes */10");
+    }
+
+    #[test]
+    fn full_responses_advertise_ranges() {
+        let response = respond(&get(None), Bytes::from("hello world"), "text/plain");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain");
+        assert_eq!(body(response), b"hello world");
+    }
+
+    #[test]
+    fn single_ranges_are_sent_as_is() {
+        let response = respond(
+            &get(Some("bytes=6-")),
+            Bytes::from("hello world"),
+            "text/plain",
+        );
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 6-10/11");
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(body(response), b"world");
+    }
+
+    #[test]
+    fn several_ranges_are_sent_as_multipart() {
+        let response = respond(
+            &get(Some("bytes=0-4, 6-")),
+            Bytes::from("hello world"),
+            "text/plain",
+        );
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .expect("ASCII")
+            .to_string();
+        assert!(content_type.starts_with("multipart/byteranges; boundary="));
+        let boundary = &content_type["multipart/byteranges; boundary=".len()..];
+
+        let body = String::from_utf8(body(response)).expect("UTF-8");
+        let expected = format!(
+            "\r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-4/11\r\n\r\nhello\
+             \r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 6-10/11\r\n\r\nworld\
+             \r\n--{0}--\r\n",
+            boundary
+        );
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_get_416() {
+        let response = respond(&get(Some("bytes=20-")), Bytes::from("hello"), "text/plain");
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes */5");
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+    }
+}
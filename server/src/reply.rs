@@ -0,0 +1,320 @@
+use std::error::Error as StdError;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+use bytes::Bytes;
+use futures::sync::mpsc;
+use futures::{Future, Stream};
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use hyper::{Body, Chunk, Response, StatusCode};
+use log::error;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::files;
+
+/// A value a handler can answer a request with, converted into a response
+/// by the dispatcher.
+pub trait IntoResponse {
+    fn into_response(self) -> Response<Body>;
+}
+
+impl IntoResponse for Response<Body> {
+    fn into_response(self) -> Response<Body> {
+        self
+    }
+}
+
+/// A `200 OK` with the string as a plain text body.
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response<Body> {
+        text(self)
+    }
+}
+
+/// A `200 OK` with the string as a plain text body.
+impl IntoResponse for String {
+    fn into_response(self) -> Response<Body> {
+        text(self)
+    }
+}
+
+/// An empty response with the status.
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response<Body> {
+        status(self)
+    }
+}
+
+/// The response for `T`, with its status replaced.
+impl<T> IntoResponse for (StatusCode, T)
+where
+    T: IntoResponse,
+{
+    fn into_response(self) -> Response<Body> {
+        let (status, value) = self;
+        let mut response = value.into_response();
+        *response.status_mut() = status;
+        response
+    }
+}
+
+/// The response for whichever of the two the result holds, so that
+/// handlers can fail with anything that can be answered with.
+impl<T, E> IntoResponse for Result<T, E>
+where
+    T: IntoResponse,
+    E: IntoResponse,
+{
+    fn into_response(self) -> Response<Body> {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// A value answered with as JSON, as [`json`] does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T> IntoResponse for Json<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response<Body> {
+        json(&self.0)
+    }
+}
+
+/// A `200 OK` response with `value` serialized as JSON, or a `500 Internal
+/// Server Error` if it can't be serialized.
+pub fn json<T>(value: &T) -> Response<Body>
+where
+    T: Serialize + ?Sized,
+{
+    match serde_json::to_vec(value) {
+        Ok(body) => with_body("application/json", body),
+        Err(err) => {
+            error!("failed to serialize response: {}", err);
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// A `200 OK` response with `text` as a UTF-8 plain text body.
+pub fn text<S>(text: S) -> Response<Body>
+where
+    S: Into<String>,
+{
+    with_body("text/plain; charset=utf-8", text.into().into_bytes())
+}
+
+/// A `200 OK` response with `html` as a UTF-8 HTML body.
+pub fn html<S>(html: S) -> Response<Body>
+where
+    S: Into<String>,
+{
+    with_body("text/html; charset=utf-8", html.into().into_bytes())
+}
+
+/// A `200 OK` response with a body of `content_type` that's sent chunk by
+/// chunk as `stream` produces them, using chunked transfer coding as its
+/// length isn't known in advance. The body ends when the stream does, and
+/// the connection is closed if the stream fails.
+///
+/// Panics if `content_type` isn't a valid header value.
+pub fn stream<S>(content_type: &str, stream: S) -> Response<Body>
+where
+    S: Stream + Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    Chunk: From<S::Item>,
+{
+    let mut response = Response::new(Body::wrap_stream(stream));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, header_value(content_type));
+    response
+}
+
+/// Like [`stream`], but follows the body with the trailers `trailers`
+/// resolves to, e.g. the `grpc-status` of a gRPC response. The future is
+/// only polled once the stream has ended, so it can wait on whatever
+/// produced the body to tell how it went. The stream is reset if it fails.
+///
+/// Trailers are only sent over HTTP/2: hyper ends chunked HTTP/1.1 bodies
+/// without them, so HTTP/1.1 clients get the body alone.
+///
+/// Panics if `content_type` isn't a valid header value.
+pub fn stream_with_trailers<S, T>(content_type: &str, stream: S, trailers: T) -> Response<Body>
+where
+    S: Stream + Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    Chunk: From<S::Item>,
+    T: Future<Item = HeaderMap> + Send + 'static,
+    T::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    let mut response = self::stream(content_type, stream);
+    let trailers: PendingTrailers = Box::new(trailers.map_err(Into::into));
+    response
+        .extensions_mut()
+        .insert(Trailers(Mutex::new(Some(trailers))));
+    response
+}
+
+pub(crate) type PendingTrailers =
+    Box<dyn Future<Item = HeaderMap, Error = Box<dyn StdError + Send + Sync>> + Send>;
+
+/// The trailers a response made by [`stream_with_trailers`] is to be
+/// followed by, kept in its extensions until the server sends it, as a
+/// `Body` has no way to carry them itself.
+pub(crate) struct Trailers(Mutex<Option<PendingTrailers>>);
+
+impl Trailers {
+    pub(crate) fn take(self) -> Option<PendingTrailers> {
+        self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Like [`stream`], but for a stream known to produce `length` bytes in
+/// all, which is sent as the `Content-Length` instead of using chunked
+/// transfer coding.
+///
+/// Panics if `content_type` isn't a valid header value.
+pub fn stream_sized<S>(content_type: &str, length: u64, stream: S) -> Response<Body>
+where
+    S: Stream + Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    Chunk: From<S::Item>,
+{
+    let mut response = self::stream(content_type, stream);
+    response
+        .headers_mut()
+        .insert(CONTENT_LENGTH, HeaderValue::from(length));
+    response
+}
+
+/// A `200 OK` response with a body of `content_type` made of the chunks
+/// sent through the returned sender. At most `buffer` chunks are held
+/// while waiting for the client to read them, after which sending waits
+/// for room. The body ends once every clone of the sender is dropped, so
+/// a handler can hold on to one and keep a response going for as long as
+/// it needs to, e.g. from a task it spawns:
+///
+/// ```ignore
+/// let (sender, response) = reply::channel("text/plain", 16);
+/// tokio::spawn(
+///     sender
+///         .send_all(log_lines().map(Bytes::from))
+///         .map(|_| ())
+///         .map_err(|_| ()),
+/// );
+/// Box::new(future::ok(response)) as ResponseFuture
+/// ```
+///
+/// Sending fails once the client has gone away.
+///
+/// Panics if `content_type` isn't a valid header value.
+pub fn channel(content_type: &str, buffer: usize) -> (mpsc::Sender<Bytes>, Response<Body>) {
+    let (sender, receiver) = mpsc::channel(buffer);
+    let chunks = receiver.map_err(|()| -> hyper::Error { unreachable!("receivers never fail") });
+    (sender, stream(content_type, chunks))
+}
+
+/// A `200 OK` response with the installed
+/// [`Templates`](crate::template::Templates) template `name` rendered with
+/// `context` as an HTML body, or a `500 Internal Server Error` if there's
+/// no such template or it fails to render.
+#[cfg(feature = "templates")]
+pub fn render<T>(name: &str, context: &T) -> Response<Body>
+where
+    T: Serialize,
+{
+    match crate::template::render(name, context) {
+        Ok(body) => html(body),
+        Err(err) => {
+            error!("failed to render template {:?}: {}", name, err);
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// A `200 OK` response streaming the file at `path`, read without holding
+/// it in memory. Its `Content-Type` is guessed from its extension, and its
+/// `Content-Length` and `Last-Modified` are taken from its metadata. Fails
+/// with a `404 Not Found` if there's no such file, which includes
+/// directories, or with a `403 Forbidden` if it can't be read.
+pub fn file<P>(path: P) -> impl Future<Item = Response<Body>, Error = Error> + Send
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref().to_path_buf();
+    files::open(path.clone())
+        .and_then(|(file, metadata)| {
+            if metadata.is_dir() {
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            } else {
+                Ok((file, metadata))
+            }
+        })
+        .map(move |(file, metadata)| files::response(&path, file, &metadata))
+        .map_err(files::error)
+}
+
+/// An empty response redirecting the client to `location` with `status`.
+///
+/// Panics if `status` isn't a redirection or `location` isn't a valid
+/// header value.
+pub fn redirect(location: &str, status: StatusCode) -> Response<Body> {
+    assert!(
+        status.is_redirection(),
+        "invalid redirect status {}",
+        status
+    );
+    let location = HeaderValue::from_str(location)
+        .unwrap_or_else(|err| panic!("invalid redirect location {:?}: {}", location, err));
+
+    let mut response = self::status(status);
+    response.headers_mut().insert(LOCATION, location);
+    response
+}
+
+/// A `308 Permanent Redirect` to `location`, which clients follow with the
+/// same method and body.
+pub fn permanent_redirect(location: &str) -> Response<Body> {
+    redirect(location, StatusCode::PERMANENT_REDIRECT)
+}
+
+/// A `307 Temporary Redirect` to `location`, which clients follow with the
+/// same method and body.
+pub fn temporary_redirect(location: &str) -> Response<Body> {
+    redirect(location, StatusCode::TEMPORARY_REDIRECT)
+}
+
+/// A `303 See Other` to `location`, which clients follow with a `GET`, e.g.
+/// to show the result of a form submission.
+pub fn see_other(location: &str) -> Response<Body> {
+    redirect(location, StatusCode::SEE_OTHER)
+}
+
+/// An empty response with `status`.
+pub fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+pub(crate) fn with_body(content_type: &'static str, body: Vec<u8>) -> Response<Body> {
+    let length = body.len();
+    let mut response = Response::new(Body::from(body));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    response
+}
+
+fn header_value(content_type: &str) -> HeaderValue {
+    HeaderValue::from_str(content_type)
+        .unwrap_or_else(|err| panic!("invalid content type {:?}: {}", content_type, err))
+}
@@ -0,0 +1,930 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::mem;
+use std::sync::Arc;
+
+use futures::{future, Future};
+use http::uri::Parts;
+use hyper::body::Payload;
+use hyper::header::{HeaderValue, ALLOW, CONTENT_LENGTH, HOST, LOCATION};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+
+use crate::handler::{CloneableService, RequestHandler, ResponseFuture};
+use crate::middleware::{set_deadline, with_timeout, Middleware};
+use crate::negotiate::Quality;
+use crate::state::State;
+
+mod normalize;
+mod path;
+mod route;
+mod shared;
+mod tree;
+
+pub use self::normalize::EncodedSlashes;
+pub use self::path::{PathParams, RequestPath};
+pub use self::route::{DuplicateRoute, Guard, MatchedRoute, Route, RouteInfo};
+pub use self::shared::{make_shared_req_dispatcher, SharedRouter};
+
+pub(crate) use self::normalize::normalize;
+
+use self::tree::RouteTree;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HttpMethod(pub Method);
+
+impl Ord for HttpMethod {
+    fn cmp(&self, other: &HttpMethod) -> Ordering {
+        self.0.as_str().cmp(other.0.as_str())
+    }
+}
+
+impl PartialOrd for HttpMethod {
+    fn partial_cmp(&self, other: &HttpMethod) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub type DispatchTable = Vec<Route>;
+
+/// Nests every route in `table` under `prefix`.
+pub fn scope(prefix: &str, table: DispatchTable) -> DispatchTable {
+    table
+        .into_iter()
+        .map(|route| route.with_prefix(prefix))
+        .collect()
+}
+
+/// Wraps the handler of every route in `table` with `middleware`, as
+/// [`Route::wrap`] does for a single route.
+pub fn wrap<M>(table: DispatchTable, middleware: M) -> DispatchTable
+where
+    M: Middleware,
+{
+    table
+        .into_iter()
+        .map(|mut route| {
+            route.handler = middleware.wrap(route.handler);
+            route
+        })
+        .collect()
+}
+
+/// A dispatch table along with any routers mounted under it.
+///
+/// Requests are first matched against the router's own routes and then
+/// handed to the mounted routers whose prefix matches the path.
+///
+/// When several routes match a request, the one with the highest
+/// [`Route::priority`] wins. Among routes of equal priority the most
+/// specific pattern wins, comparing segments from left to right: a literal
+/// segment beats a parameter, which beats a wildcard, and regex patterns
+/// lose to all of them. Mounted routers are consulted before falling back to
+/// a matching wildcard or regex route. Routes in a
+/// mounted router are written relative to the mount point, so a table can be
+/// built in one module and mounted somewhere else without knowing where.
+///
+/// A router can also serve as the fallback for a set of virtual hosts, each
+/// with its own router that takes over every request whose `Host` matches.
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: DispatchTable,
+    tree: RouteTree,
+    /// Reused between requests to collect the routes that might match.
+    candidates: Vec<usize>,
+    mounts: Vec<(String, Router)>,
+    hosts: Vec<(String, Router)>,
+    trailing_slash: TrailingSlash,
+    encoded_slashes: EncodedSlashes,
+    not_found: Option<RequestHandler>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+/// What to do with a request for `/networks/` when only `/networks` is
+/// registered, or the other way around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Paths must match exactly.
+    Strict,
+    /// Dispatch to the route as if the request had used its path.
+    Merge,
+    /// Answer with a `301 Moved Permanently` pointing at the route's path.
+    Redirect,
+}
+
+impl Default for TrailingSlash {
+    fn default() -> Self {
+        TrailingSlash::Strict
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    pub fn route<M, P>(&mut self, method: M, path: P, handler: RequestHandler) -> &mut Self
+    where
+        M: Into<Option<Method>>,
+        P: Into<RequestPath>,
+    {
+        self.add(Route::new(method, path, handler))
+    }
+
+    /// Registers `route`, panicking if it conflicts with a route that is
+    /// already registered. See [`Router::try_add`].
+    pub fn add(&mut self, route: Route) -> &mut Self {
+        self.try_add(route).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Registers `route`, failing if a route with the same method, pattern
+    /// and content types is already registered and neither of them has
+    /// guards to tell their requests apart.
+    pub fn try_add(&mut self, route: Route) -> Result<&mut Self, DuplicateRoute> {
+        if self.routes.iter().any(|other| other.conflicts_with(&route)) {
+            return Err(DuplicateRoute(RouteInfo::new(&route, None, "")));
+        }
+
+        self.tree.insert(&route.path, self.routes.len());
+        self.routes.push(route);
+        Ok(self)
+    }
+
+    /// Removes every route registered for `method` and `path`, returning
+    /// whether there were any. Routes in mounted routers aren't affected.
+    pub fn remove<M>(&mut self, method: M, path: &RequestPath) -> bool
+    where
+        M: Into<Option<Method>>,
+    {
+        let method = method.into().map(HttpMethod);
+        let count = self.routes.len();
+        self.routes
+            .retain(|route| route.method != method || route.path != *path);
+        if self.routes.len() == count {
+            return false;
+        }
+
+        self.tree = RouteTree::default();
+        for (index, route) in self.routes.iter().enumerate() {
+            self.tree.insert(&route.path, index);
+        }
+        true
+    }
+
+    /// Mounts `router` so that it serves every path under `prefix`.
+    pub fn mount<R>(&mut self, prefix: &str, router: R) -> &mut Self
+    where
+        R: Into<Router>,
+    {
+        self.mounts
+            .push((prefix.trim_end_matches('/').to_string(), router.into()));
+        self
+    }
+
+    /// Registers `router` to serve every request whose `Host` is
+    /// `hostname`, ignoring case and any port. Requests for unregistered
+    /// hosts fall back to this router's own routes.
+    pub fn host<R>(&mut self, hostname: &str, router: R) -> &mut Self
+    where
+        R: Into<Router>,
+    {
+        self.hosts
+            .push((hostname.to_ascii_lowercase(), router.into()));
+        self
+    }
+
+    /// Sets how requests whose path differs from a registered route only by
+    /// a trailing slash are handled. This only applies to the router handed
+    /// to [`make_req_dispatcher`]; mounted routers follow their parent.
+    pub fn trailing_slash(&mut self, policy: TrailingSlash) -> &mut Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Wraps every request the dispatcher handles in `middleware`, including
+    /// those that match no route. Each call wraps the middleware added
+    /// before it, so the last one added sees requests first. Like
+    /// [`Router::trailing_slash`], this only applies to the router handed to
+    /// [`make_req_dispatcher`], and only to middleware added before the
+    /// dispatcher is made.
+    pub fn wrap<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Shares `state` between every request the dispatcher handles, so that
+    /// handlers can read it as a [`State`] from the request extensions. It's
+    /// registered like middleware added with [`Router::wrap`], and is
+    /// subject to the same restrictions.
+    pub fn state<T>(&mut self, state: T) -> &mut Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.wrap(State::new(state))
+    }
+
+    /// Sets the handler for requests under this router that match no route,
+    /// in place of the default handler given to [`make_req_dispatcher`].
+    /// When several mounted routers along a request's path have one, the
+    /// most deeply mounted wins.
+    pub fn not_found(&mut self, handler: RequestHandler) -> &mut Self {
+        self.not_found = Some(handler);
+        self
+    }
+
+    /// Sets how percent-encoded slashes in request paths are treated. As
+    /// with [`Router::trailing_slash`], this only applies to the router
+    /// handed to [`make_req_dispatcher`].
+    pub fn encoded_slashes(&mut self, policy: EncodedSlashes) -> &mut Self {
+        self.encoded_slashes = policy;
+        self
+    }
+
+    /// Lists every route registered with this router, its mounted routers
+    /// and its virtual hosts, in the order they are consulted.
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let mut routes = vec![];
+        for (host, router) in &self.hosts {
+            router.collect_routes(Some(host), "", &mut routes);
+        }
+        self.collect_routes(None, "", &mut routes);
+        routes
+    }
+
+    fn collect_routes(&self, host: Option<&str>, prefix: &str, routes: &mut Vec<RouteInfo>) {
+        routes.extend(
+            self.routes
+                .iter()
+                .map(|route| RouteInfo::new(route, host, prefix)),
+        );
+
+        for (mount, router) in &self.mounts {
+            router.collect_routes(host, &format!("{}{}", prefix, mount), routes);
+        }
+    }
+
+    fn handle(
+        &mut self,
+        req: Request<Body>,
+        default_handler: &mut RequestHandler,
+    ) -> ResponseFuture {
+        let virtual_host = request_host(&req).and_then(|host| {
+            self.hosts
+                .iter()
+                .position(|(name, _)| name.eq_ignore_ascii_case(host))
+        });
+        if let Some(index) = virtual_host {
+            return self.hosts[index].1.handle(req, default_handler);
+        }
+
+        // Cloning the URI only bumps reference counts, and lets the path be
+        // borrowed while the request itself is handed around.
+        let uri = req.uri().clone();
+        let mut path = match normalize(uri.path(), self.encoded_slashes) {
+            Some(path) => path,
+            None => return status_response(StatusCode::BAD_REQUEST),
+        };
+
+        let mut req = match self.dispatch_request(&path, req) {
+            Ok(response) => return response,
+            Err(req) => req,
+        };
+
+        let mut allowed = self.allowed_methods(&path);
+
+        if allowed.is_empty() && self.trailing_slash != TrailingSlash::Strict {
+            let alternative = toggle_trailing_slash(&path)
+                .filter(|path| !self.allowed_methods(path).is_empty())
+                .and_then(|path| {
+                    let raw = toggle_trailing_slash(uri.path())?;
+                    Some((path, with_path(&uri, &raw)?))
+                });
+
+            if let Some((alternative, uri)) = alternative {
+                if self.trailing_slash == TrailingSlash::Redirect {
+                    return moved_permanently(&uri);
+                }
+
+                *req.uri_mut() = uri;
+                path = Cow::Owned(alternative);
+                req = match self.dispatch_request(&path, req) {
+                    Ok(response) => return response,
+                    Err(req) => req,
+                };
+                allowed = self.allowed_methods(&path);
+            }
+        }
+
+        if !allowed.is_empty() {
+            // A route registered for this method that was skipped because of
+            // its guards is a miss, not a method mismatch.
+            if !allowed.contains(req.method()) {
+                return allow_response(StatusCode::METHOD_NOT_ALLOWED, &allowed.methods);
+            }
+
+            if req.method() == Method::OPTIONS {
+                return allow_response(StatusCode::NO_CONTENT, &allowed.methods);
+            }
+
+            if self.is_unsupported_media_type(&path, &req) {
+                return status_response(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+
+            if self.is_not_acceptable(&path, &req) {
+                return status_response(StatusCode::NOT_ACCEPTABLE);
+            }
+        }
+
+        match self.not_found_handler(&path) {
+            Some(handler) => handler.call(req),
+            None => default_handler.call(req),
+        }
+    }
+
+    /// Returns the not found handler of the most deeply mounted router
+    /// serving `path` that has one.
+    fn not_found_handler(&mut self, path: &str) -> Option<&mut RequestHandler> {
+        for (prefix, router) in &mut self.mounts {
+            if is_mounted_under(path, prefix) {
+                if let Some(handler) = router.not_found_handler(&path[prefix.len()..]) {
+                    return Some(handler);
+                }
+            }
+        }
+        self.not_found.as_mut()
+    }
+
+    /// Dispatches `req`, answering a `HEAD` request with no route of its own
+    /// by calling the `GET` route for its path and dropping the body of the
+    /// response. The handler sees such requests as `GET` requests.
+    fn dispatch_request(
+        &mut self,
+        path: &str,
+        req: Request<Body>,
+    ) -> Result<ResponseFuture, Request<Body>> {
+        let mut req = match self.dispatch(path, 0, req) {
+            Ok(response) => return Ok(response),
+            Err(req) => req,
+        };
+        if req.method() != Method::HEAD {
+            return Err(req);
+        }
+
+        *req.method_mut() = Method::GET;
+        match self.dispatch(path, 0, req) {
+            Ok(response) => Ok(Box::new(response.map(|response| {
+                // The length of the body the GET request would have got is
+                // kept, as the client is told about it all the same.
+                let (mut parts, body) = response.into_parts();
+                if let (false, Some(length)) = (
+                    parts.headers.contains_key(CONTENT_LENGTH),
+                    body.content_length(),
+                ) {
+                    parts
+                        .headers
+                        .insert(CONTENT_LENGTH, HeaderValue::from(length));
+                }
+                Response::from_parts(parts, Body::empty())
+            }))),
+            Err(mut req) => {
+                *req.method_mut() = Method::HEAD;
+                Err(req)
+            }
+        }
+    }
+
+    /// Dispatches `req` using the part of its normalized `path` starting at
+    /// `offset`, handing the request back if no route matched.
+    fn dispatch(
+        &mut self,
+        path: &str,
+        offset: usize,
+        mut req: Request<Body>,
+    ) -> Result<ResponseFuture, Request<Body>> {
+        let matched = {
+            let req = &req;
+            let path = &path[offset..];
+            let routes = &self.routes;
+            let candidates = &mut self.candidates;
+
+            candidates.clear();
+            self.tree.lookup(path, candidates);
+            candidates
+                .iter()
+                .filter_map(|&index| {
+                    let route = &routes[index];
+                    let quality = route.matches(req, path)?;
+                    Some(Candidate {
+                        index,
+                        priority: route.priority,
+                        quality,
+                    })
+                })
+                .min_by(|a, b| Candidate::precedence(routes, a, b))
+        };
+
+        // Mounted routers get a chance at the request before routes that
+        // merely happen to match it through a wildcard or regex.
+        let matched = match matched {
+            Some(candidate) => {
+                if !self.routes[candidate.index].path.is_catch_all() {
+                    return Ok(candidate.call(&mut self.routes, path, offset, req));
+                }
+                Some(candidate)
+            }
+            None => None,
+        };
+
+        for (prefix, router) in &mut self.mounts {
+            if is_mounted_under(&path[offset..], prefix) {
+                req = match router.dispatch(path, offset + prefix.len(), req) {
+                    Ok(response) => return Ok(response),
+                    Err(req) => req,
+                };
+            }
+        }
+
+        match matched {
+            Some(candidate) => Ok(candidate.call(&mut self.routes, path, offset, req)),
+            None => Err(req),
+        }
+    }
+
+    /// Returns the methods of every route whose pattern matches `path`,
+    /// including `HEAD` wherever `GET` is registered and `OPTIONS` for any
+    /// registered path.
+    fn allowed_methods(&self, path: &str) -> AllowedMethods {
+        let mut allowed = AllowedMethods::default();
+        self.visit_routes(path, &mut |route| match &route.method {
+            Some(method) => {
+                allowed.methods.insert(method.clone());
+            }
+            None => allowed.any = true,
+        });
+
+        if allowed.methods.contains(&HttpMethod(Method::GET)) {
+            allowed.methods.insert(HttpMethod(Method::HEAD));
+        }
+        if !allowed.is_empty() {
+            allowed.methods.insert(HttpMethod(Method::OPTIONS));
+        }
+        allowed
+    }
+
+    /// Whether every route for the request's method and path only accepts
+    /// some other content type.
+    fn is_unsupported_media_type(&self, path: &str, req: &Request<Body>) -> bool {
+        let mut restricted = false;
+        let mut unrestricted = false;
+        self.visit_routes(path, &mut |route| {
+            if route.matches_method(req.method()) {
+                match route.content_type {
+                    Some(_) if !route.accepts_content_type(req) => restricted = true,
+                    _ => unrestricted = true,
+                }
+            }
+        });
+        restricted && !unrestricted
+    }
+
+    /// Whether every route for the request's method and path produces a
+    /// media type the client won't accept.
+    fn is_not_acceptable(&self, path: &str, req: &Request<Body>) -> bool {
+        let mut rejected = false;
+        let mut acceptable = false;
+        self.visit_routes(path, &mut |route| {
+            if route.matches_method(req.method()) {
+                if route.accept_quality(req) == 0 {
+                    rejected = true;
+                } else {
+                    acceptable = true;
+                }
+            }
+        });
+        rejected && !acceptable
+    }
+
+    /// Calls `visit` for every route, including those in mounted routers,
+    /// whose pattern matches `path` regardless of method or conditions.
+    fn visit_routes(&self, path: &str, visit: &mut dyn FnMut(&Route)) {
+        let mut candidates = vec![];
+        self.tree.lookup(path, &mut candidates);
+        for index in candidates {
+            let route = &self.routes[index];
+            if route.path.is_match(path) {
+                visit(route);
+            }
+        }
+
+        for (prefix, router) in &self.mounts {
+            if is_mounted_under(path, prefix) {
+                router.visit_routes(&path[prefix.len()..], visit);
+            }
+        }
+    }
+}
+
+/// The methods registered for a path.
+#[derive(Default)]
+struct AllowedMethods {
+    methods: BTreeSet<HttpMethod>,
+    /// Whether a route for the path matches any method.
+    any: bool,
+}
+
+impl AllowedMethods {
+    fn is_empty(&self) -> bool {
+        !self.any && self.methods.is_empty()
+    }
+
+    fn contains(&self, method: &Method) -> bool {
+        self.any || self.methods.contains(&HttpMethod(method.clone()))
+    }
+}
+
+/// A route that matched the request being dispatched.
+struct Candidate {
+    index: usize,
+    priority: i32,
+    quality: Quality,
+}
+
+impl Candidate {
+    /// Orders candidates so that the one to dispatch to comes first: the
+    /// highest explicit priority, then the most specific pattern, then a
+    /// route for the request's method over one for any method, then the
+    /// media type the client prefers. Remaining ties go to whichever route
+    /// was registered first.
+    fn precedence(routes: &[Route], a: &Candidate, b: &Candidate) -> Ordering {
+        let (route_a, route_b) = (&routes[a.index], &routes[b.index]);
+        b.priority
+            .cmp(&a.priority)
+            .then_with(|| route_b.path.cmp_specificity(&route_a.path))
+            .then_with(|| route_a.method.is_none().cmp(&route_b.method.is_none()))
+            .then_with(|| b.quality.cmp(&a.quality))
+    }
+
+    /// Calls the candidate's handler, capturing its parameters only now
+    /// that it's known to be the route being dispatched to. Requests whose
+    /// typed parameters don't parse are answered with a `400 Bad Request`.
+    fn call(
+        self,
+        routes: &mut [Route],
+        path: &str,
+        offset: usize,
+        mut req: Request<Body>,
+    ) -> ResponseFuture {
+        let route = &mut routes[self.index];
+        let (prefix, path) = path.split_at(offset);
+        let matched = MatchedRoute::new(route, prefix);
+
+        if let Some(params) = route.path.matches(path) {
+            if !route.path.params_are_valid(&params) {
+                return status_response(StatusCode::BAD_REQUEST);
+            }
+            if !params.is_empty() {
+                req.extensions_mut().insert(params);
+            }
+        }
+
+        req.extensions_mut().insert(matched.clone());
+        if let Some(timeout) = route.timeout {
+            set_deadline(&mut req, timeout);
+        }
+        let mut response = route.handler.call(req);
+        if let Some(timeout) = route.timeout {
+            response = with_timeout(response, timeout);
+        }
+
+        Box::new(response.map(|mut response| {
+            response.extensions_mut().insert(matched);
+            response
+        }))
+    }
+}
+
+impl From<DispatchTable> for Router {
+    fn from(routes: DispatchTable) -> Self {
+        let mut router = Router::default();
+        for route in routes {
+            router.add(route);
+        }
+        router
+    }
+}
+
+/// Parses the method of a `routes!` entry. Besides the standard methods this
+/// accepts extension methods such as `PURGE` or `REPORT`, while `ANY` stands
+/// for every method and so returns `None`.
+pub fn method(name: &str) -> Option<Method> {
+    if name == "ANY" {
+        return None;
+    }
+
+    let method = Method::from_bytes(name.as_bytes())
+        .unwrap_or_else(|err| panic!("invalid method {:?}: {}", name, err));
+    Some(method)
+}
+
+/// Creates the request handling closure for a server.
+///
+/// Request paths are percent-decoded, and `.` and `..` segments resolved,
+/// before they are matched against routes; paths that don't decode to
+/// UTF-8 are answered with a `400 Bad Request`. Captured parameters hold
+/// the decoded values.
+///
+/// Requests that match no route are answered with a `405 Method Not
+/// Allowed` if the path is registered for some other method, or a `415
+/// Unsupported Media Type` or `406 Not Acceptable` if it is only registered
+/// for other content types or for media types the client doesn't accept.
+/// They are retried according to the router's [`TrailingSlash`] policy, and
+/// are otherwise handed to the [`Router::not_found`] handler of the router
+/// serving their path, or failing that to `default_handler`.
+///
+/// `OPTIONS` requests for a registered path are answered with a `204 No
+/// Content` listing the path's methods in `Allow`, unless the path has an
+/// `OPTIONS` route of its own.
+pub fn make_req_dispatcher<R>(
+    router: R,
+    default_handler: RequestHandler,
+) -> impl FnMut(Request<Body>) -> ResponseFuture + Clone
+where
+    R: Into<Router>,
+{
+    let mut handler = dispatch_handler(router.into(), default_handler);
+    move |req: Request<Body>| handler.call(req)
+}
+
+/// The handler behind [`make_req_dispatcher`]: `router` wrapped in its
+/// middleware, falling back to `default_handler`.
+pub(crate) fn dispatch_handler(
+    mut router: Router,
+    default_handler: RequestHandler,
+) -> RequestHandler {
+    let middleware = mem::replace(&mut router.middleware, vec![]);
+    let dispatcher = Box::new(Dispatcher {
+        router,
+        default_handler,
+    }) as RequestHandler;
+
+    wrap_handler(dispatcher, &middleware)
+}
+
+/// Wraps `handler` in each of `middleware` in turn.
+fn wrap_handler(handler: RequestHandler, middleware: &[Arc<dyn Middleware>]) -> RequestHandler {
+    middleware
+        .iter()
+        .fold(handler, |handler, middleware| middleware.wrap(handler))
+}
+
+/// The router and default handler behind a dispatcher, as a handler that
+/// the router's middleware can wrap.
+#[derive(Clone)]
+struct Dispatcher {
+    router: Router,
+    default_handler: RequestHandler,
+}
+
+impl CloneableService for Dispatcher {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn call(&mut self, req: Request<Body>) -> ResponseFuture {
+        self.router.handle(req, &mut self.default_handler)
+    }
+}
+
+fn is_mounted_under(path: &str, prefix: &str) -> bool {
+    path.starts_with(prefix)
+        && (path.len() == prefix.len() || path[prefix.len()..].starts_with('/'))
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn status_response(status: StatusCode) -> ResponseFuture {
+    Box::new(future::ok(empty_response(status)))
+}
+
+/// Answers with `status` and an `Allow` header listing `allowed`.
+fn allow_response(status: StatusCode, allowed: &BTreeSet<HttpMethod>) -> ResponseFuture {
+    let allow = allowed
+        .iter()
+        .map(|method| method.0.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        ALLOW,
+        HeaderValue::from_str(&allow).expect("method names are valid header values"),
+    );
+
+    Box::new(future::ok(response))
+}
+
+/// The host a request was addressed to, without any port.
+fn request_host(req: &Request<Body>) -> Option<&str> {
+    let host = match req.uri().host() {
+        Some(host) => host,
+        None => req.headers().get(HOST)?.to_str().ok()?,
+    };
+
+    if host.starts_with('[') {
+        host.find(']').map(|end| &host[..=end])
+    } else {
+        host.split(':').next()
+    }
+}
+
+fn moved_permanently(location: &Uri) -> ResponseFuture {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::MOVED_PERMANENTLY;
+    response.headers_mut().insert(
+        LOCATION,
+        HeaderValue::from_str(&location.to_string()).expect("URIs are valid header values"),
+    );
+
+    Box::new(future::ok(response))
+}
+
+/// Returns `path` with its trailing slash removed, or with one added if it
+/// didn't have one.
+fn toggle_trailing_slash(path: &str) -> Option<String> {
+    if path == "/" {
+        None
+    } else if path.ends_with('/') {
+        Some(path.trim_end_matches('/').to_string())
+    } else {
+        Some(format!("{}/", path))
+    }
+}
+
+/// `uri` with its path replaced by `path`, keeping its query.
+pub(crate) fn with_path(uri: &Uri, path: &str) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+
+    let mut parts = Parts::from(uri.clone());
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Builds a [`DispatchTable`] from a list of routes.
+///
+/// Each entry is a method, a path pattern and a handler. The method can be
+/// any token method name, standard or not, written either bare or as a
+/// string literal for names that aren't identifiers, while `ANY` matches
+/// every method but loses to routes for the request's method. Several
+/// methods can share a handler by separating them with `|` or listing them
+/// in brackets, which adds a route for each of them. Prefixing the pattern with `regex`
+/// matches the whole request path against a regular expression instead,
+/// and `scope` nests a group of entries under a common path prefix. A scope
+/// can wrap every handler it contains in middleware by listing `wrap(..)`
+/// after its prefix, with the last one listed running first. Any of the
+/// [`Route`] builder methods can be applied to an entry by writing them
+/// between the pattern and the handler. Handlers can return anything that
+/// implements [`IntoResponseFuture`](crate::handler::IntoResponseFuture),
+/// from a boxed future to a plain `String`:
+///
+/// ```ignore
+/// routes!(
+///     GET "/networks" => on_get_networks,
+///     GET "/networks/{id}" => on_inspect_network,
+///     GET regex r"/v1\.\d+/images/.*" => on_image,
+///     GET "/version" header("x-api-version", "2") => on_version_2,
+///     GET "/version" guard(|req| req.uri().query().is_none()) => on_version,
+///     GET "/containers/json" query("all", "true") => on_list_all_containers,
+///     POST "/build" content_type("application/x-tar") => on_build_tar,
+///     POST "/build" content_type("application/json") => on_build_json,
+///     GET "/status" accept("application/json") => on_status_json,
+///     GET "/status" accept("text/plain") => on_status_text,
+///     GET "/images/*" priority(1) => on_any_image,
+///     GET "/slow" timeout(Duration::from_secs(2)) => on_slow,
+///     PURGE "/cache" => on_purge,
+///     ANY "/echo" => on_echo,
+///     GET "/old" => redirect("/new", 301),
+///     GET | POST "/ping" => on_ping,
+///     [PUT, PATCH] "/networks/{id}" => on_update_network,
+///     "M-SEARCH" "*" => on_search,
+///     DELETE "/networks/{id}" name("remove_network") => on_remove_network,
+///     scope "/api/v1" {
+///         GET "/containers" => on_list_containers,
+///         POST "/containers" => on_create_container,
+///     },
+///     scope "/admin" wrap(require_auth) {
+///         GET "/users" => on_list_users,
+///     },
+/// )
+/// ```
+#[macro_export]
+macro_rules! routes {
+    (@method $method:ident) => {
+        $crate::router::method(stringify!($method))
+    };
+    (@method $method:literal) => {
+        $crate::router::method($method)
+    };
+    (@entries $table:ident;) => {};
+    (@entries $table:ident;
+        scope $prefix:tt $(wrap ($middleware:expr))* { $($inner:tt)* }
+        $(, $($rest:tt)*)?
+    ) => {
+        let scoped = $crate::router::scope($prefix, $crate::routes!($($inner)*));
+        $(let scoped = $crate::router::wrap(scoped, $middleware);)*
+        $table.extend(scoped);
+        $crate::routes!(@entries $table; $($($rest)*)?);
+    };
+    (@entries $table:ident; [$($method:tt),+ $(,)?] $($rest:tt)*) => {
+        $crate::routes!(@route $table; [$($method)+] $($rest)*);
+    };
+    (@entries $table:ident; $($rest:tt)+) => {
+        $crate::routes!(@methods $table; [] $($rest)+);
+    };
+    (@methods $table:ident; [$($methods:tt)*] $method:tt | $($rest:tt)+) => {
+        $crate::routes!(@methods $table; [$($methods)* $method] $($rest)+);
+    };
+    (@methods $table:ident; [$($methods:tt)*] $method:tt $($rest:tt)+) => {
+        $crate::routes!(@route $table; [$($methods)* $method] $($rest)+);
+    };
+    (@route $table:ident; [$method:tt $($more:tt)*]
+        regex $pattern:tt $($modifier:ident ($($arg:tt)*))* => $handler:expr
+        $(, $($rest:tt)*)?
+    ) => {
+        $crate::routes!(@push $table; [$method $($more)*] $crate::router::Route::new(
+            $crate::routes!(@method $method),
+            $crate::router::RequestPath::regex($pattern),
+            $crate::handler::into_handler($handler),
+        )
+        $(.$modifier($($arg)*))*);
+        $crate::routes!(@entries $table; $($($rest)*)?);
+    };
+    (@route $table:ident; [$method:tt $($more:tt)*]
+        $path:tt $($modifier:ident ($($arg:tt)*))* => $handler:expr
+        $(, $($rest:tt)*)?
+    ) => {
+        $crate::routes!(@push $table; [$method $($more)*] $crate::router::Route::new(
+            $crate::routes!(@method $method),
+            $crate::router::RequestPath::from($path),
+            $crate::handler::into_handler($handler),
+        )
+        $(.$modifier($($arg)*))*);
+        $crate::routes!(@entries $table; $($($rest)*)?);
+    };
+    (@push $table:ident; [$method:tt] $route:expr) => {
+        $table.push($route);
+    };
+    (@push $table:ident; [$method:tt $($more:tt)+] $route:expr) => {
+        let index = $table.len();
+        $table.push($route);
+        $(
+            let route = $table[index].for_method($crate::routes!(@method $more));
+            $table.push(route);
+        )+
+    };
+    ($($entries:tt)+) => ({
+        let mut table = $crate::router::DispatchTable::new();
+        $crate::routes!(@entries table; $($entries)+);
+        table
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_slashes_are_toggled() {
+        assert_eq!(toggle_trailing_slash("/a"), Some("/a/".to_string()));
+        assert_eq!(toggle_trailing_slash("/a/"), Some("/a".to_string()));
+        assert_eq!(toggle_trailing_slash("/a/b//"), Some("/a/b".to_string()));
+        assert_eq!(toggle_trailing_slash("/"), None);
+    }
+
+    #[test]
+    fn paths_are_replaced_keeping_the_query() {
+        let uri: Uri = "http://example.com/a?x=1&y".parse().expect("valid");
+        assert_eq!(
+            with_path(&uri, "/a/").map(|uri| uri.to_string()),
+            Some("http://example.com/a/?x=1&y".to_string())
+        );
+
+        let uri: Uri = "/a".parse().expect("valid");
+        assert_eq!(
+            with_path(&uri, "/b").map(|uri| uri.to_string()),
+            Some("/b".to_string())
+        );
+        assert_eq!(with_path(&uri, "/b c"), None);
+    }
+}
@@ -0,0 +1,196 @@
+use std::borrow::Cow;
+
+/// What to do with a percent-encoded slash (`%2F`) in a request path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodedSlashes {
+    /// Decode it like any other character, so it separates segments.
+    Decode,
+    /// Leave it encoded, so it stays part of the segment it appears in.
+    Preserve,
+    /// Answer with a `400 Bad Request`.
+    Reject,
+}
+
+impl Default for EncodedSlashes {
+    fn default() -> Self {
+        EncodedSlashes::Decode
+    }
+}
+
+/// Percent-decodes `path` and resolves its `.` and `..` segments, returning
+/// `None` if it isn't valid UTF-8 once decoded, contains a malformed escape
+/// or an encoded slash that `encoded_slashes` rejects. Paths that need
+/// neither are borrowed as they are.
+pub(crate) fn normalize(path: &str, encoded_slashes: EncodedSlashes) -> Option<Cow<'_, str>> {
+    let decoded = if path.contains('%') {
+        Cow::Owned(decode(path, encoded_slashes)?)
+    } else {
+        Cow::Borrowed(path)
+    };
+
+    if decoded.starts_with('/')
+        && decoded
+            .split('/')
+            .any(|segment| segment == "." || segment == "..")
+    {
+        Some(Cow::Owned(remove_dot_segments(&decoded)))
+    } else {
+        Some(decoded)
+    }
+}
+
+/// Decodes a name or value from a form encoded query string, returning
+/// `None` if it's malformed.
+pub(super) fn decode_query_component(component: &str) -> Option<Cow<'_, str>> {
+    if component.contains('%') || component.contains('+') {
+        decode(&component.replace('+', " "), EncodedSlashes::Decode).map(Cow::Owned)
+    } else {
+        Some(Cow::Borrowed(component))
+    }
+}
+
+fn decode(path: &str, encoded_slashes: EncodedSlashes) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != b'%' {
+            decoded.push(bytes[index]);
+            index += 1;
+            continue;
+        }
+
+        let high = hex_value(*bytes.get(index + 1)?)?;
+        let low = hex_value(*bytes.get(index + 2)?)?;
+        let byte = (high << 4) | low;
+        if byte != b'/' {
+            decoded.push(byte);
+        } else {
+            match encoded_slashes {
+                EncodedSlashes::Decode => decoded.push(byte),
+                EncodedSlashes::Preserve => decoded.extend_from_slice(&bytes[index..index + 3]),
+                EncodedSlashes::Reject => return None,
+            }
+        }
+        index += 3;
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Resolves the `.` and `..` segments of an absolute path. A path ending
+/// in one of them keeps its trailing slash, so `/a/b/..` becomes `/a/`.
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = vec![];
+    let mut segments = path[1..].split('/').peekable();
+
+    while let Some(segment) = segments.next() {
+        match segment {
+            "." => {}
+            ".." => {
+                output.pop();
+            }
+            _ => {
+                output.push(segment);
+                continue;
+            }
+        }
+
+        if segments.peek().is_none() {
+            output.push("");
+        }
+    }
+
+    format!("/{}", output.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(path: &str) -> Option<String> {
+        normalize(path, EncodedSlashes::Decode).map(Cow::into_owned)
+    }
+
+    #[test]
+    fn plain_paths_are_borrowed() {
+        for path in &["/", "/a/b", "/a..b/", "*"] {
+            match normalize(path, EncodedSlashes::Decode) {
+                Some(Cow::Borrowed(normalized)) => assert_eq!(normalized, *path),
+                other => panic!("{:?} normalized to {:?}", path, other),
+            }
+        }
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        assert_eq!(normalized("/a%20b"), Some("/a b".to_string()));
+        assert_eq!(normalized("/caf%C3%A9"), Some("/café".to_string()));
+        assert_eq!(normalized("/caf%c3%a9"), Some("/café".to_string()));
+        assert_eq!(normalized("/100%25"), Some("/100%".to_string()));
+    }
+
+    #[test]
+    fn malformed_escapes_are_rejected() {
+        assert_eq!(normalized("/a%2"), None);
+        assert_eq!(normalized("/a%"), None);
+        assert_eq!(normalized("/a%zz"), None);
+        assert_eq!(normalized("/%ff"), None);
+    }
+
+    #[test]
+    fn encoded_slashes_follow_the_policy() {
+        let normalize =
+            |path, encoded_slashes| super::normalize(path, encoded_slashes).map(Cow::into_owned);
+        assert_eq!(
+            normalize("/a%2Fb", EncodedSlashes::Decode),
+            Some("/a/b".to_string())
+        );
+        assert_eq!(
+            normalize("/a%2Fb%20c", EncodedSlashes::Preserve),
+            Some("/a%2Fb c".to_string())
+        );
+        assert_eq!(
+            normalize("/a%2fb", EncodedSlashes::Preserve),
+            Some("/a%2fb".to_string())
+        );
+        assert_eq!(normalize("/a%2Fb", EncodedSlashes::Reject), None);
+        assert_eq!(
+            normalize("/a%20b", EncodedSlashes::Reject),
+            Some("/a b".to_string())
+        );
+    }
+
+    #[test]
+    fn dot_segments_are_resolved() {
+        assert_eq!(normalized("/a/./b/../c"), Some("/a/c".to_string()));
+        assert_eq!(normalized("/a/b/.."), Some("/a/".to_string()));
+        assert_eq!(normalized("/a/b/."), Some("/a/b/".to_string()));
+        assert_eq!(normalized("/../a"), Some("/a".to_string()));
+        assert_eq!(normalized("/.."), Some("/".to_string()));
+        assert_eq!(normalized("/a/%2e%2E/b"), Some("/b".to_string()));
+    }
+
+    #[test]
+    fn relative_paths_keep_their_dot_segments() {
+        assert_eq!(normalized("a/../b"), Some("a/../b".to_string()));
+    }
+
+    #[test]
+    fn query_components_decode_plus_as_space() {
+        let decode = |component| decode_query_component(component).map(Cow::into_owned);
+        assert_eq!(decode("a+b%21"), Some("a b!".to_string()));
+        assert_eq!(decode("a%2Fb"), Some("a/b".to_string()));
+        assert_eq!(decode("%2B"), Some("+".to_string()));
+        assert_eq!(decode("%zz"), None);
+        match decode_query_component("plain") {
+            Some(Cow::Borrowed("plain")) => {}
+            other => panic!("plain component decoded to {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use regex::Regex;
+
+/// A single `/` delimited piece of a route pattern.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub(super) enum Segment {
+    Literal(String),
+    Param(String, Option<ParamType>),
+    Wildcard(Option<String>),
+}
+
+impl Segment {
+    fn parse(segment: &str) -> Self {
+        if segment == "*" {
+            Segment::Wildcard(None)
+        } else if segment.len() > 2 && segment.starts_with('{') && segment.ends_with('}') {
+            let name = &segment[1..segment.len() - 1];
+            if name.starts_with('*') && name.len() > 1 {
+                Segment::Wildcard(Some(name[1..].to_string()))
+            } else {
+                let mut parts = name.splitn(2, ':');
+                let name = parts.next().unwrap_or_default();
+                let kind = parts.next().map(|kind| {
+                    ParamType::from_name(kind).unwrap_or_else(|| {
+                        panic!("unknown type {:?} for route parameter {:?}", kind, name)
+                    })
+                });
+                Segment::Param(name.to_string(), kind)
+            }
+        } else {
+            Segment::Literal(segment.to_string())
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 2,
+            Segment::Param(..) => 1,
+            Segment::Wildcard(_) => 0,
+        }
+    }
+}
+
+/// The type a route parameter such as `{id:u64}` has to parse as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub(super) enum ParamType {
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    Isize,
+    F32,
+    F64,
+    Bool,
+}
+
+impl ParamType {
+    fn from_name(name: &str) -> Option<Self> {
+        let kind = match name {
+            "u8" => ParamType::U8,
+            "u16" => ParamType::U16,
+            "u32" => ParamType::U32,
+            "u64" => ParamType::U64,
+            "usize" => ParamType::Usize,
+            "i8" => ParamType::I8,
+            "i16" => ParamType::I16,
+            "i32" => ParamType::I32,
+            "i64" => ParamType::I64,
+            "isize" => ParamType::Isize,
+            "f32" => ParamType::F32,
+            "f64" => ParamType::F64,
+            "bool" => ParamType::Bool,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            ParamType::U8 => value.parse::<u8>().is_ok(),
+            ParamType::U16 => value.parse::<u16>().is_ok(),
+            ParamType::U32 => value.parse::<u32>().is_ok(),
+            ParamType::U64 => value.parse::<u64>().is_ok(),
+            ParamType::Usize => value.parse::<usize>().is_ok(),
+            ParamType::I8 => value.parse::<i8>().is_ok(),
+            ParamType::I16 => value.parse::<i16>().is_ok(),
+            ParamType::I32 => value.parse::<i32>().is_ok(),
+            ParamType::I64 => value.parse::<i64>().is_ok(),
+            ParamType::Isize => value.parse::<isize>().is_ok(),
+            ParamType::F32 => value.parse::<f32>().is_ok(),
+            ParamType::F64 => value.parse::<f64>().is_ok(),
+            ParamType::Bool => value.parse::<bool>().is_ok(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Segments(Vec<Segment>),
+    Regex(Regex),
+}
+
+/// A route pattern such as `/networks`, `/networks/{id}` or `/containers/*`.
+///
+/// Segments wrapped in braces are named parameters that match exactly one
+/// non-empty path segment. A parameter can be given a type, as in
+/// `{id:u64}`, in which case requests whose segment doesn't parse as that
+/// type are answered with a `400 Bad Request` instead of reaching the
+/// handler. Integer and floating point types as well as `bool` are
+/// supported. A trailing `*` (or `{*name}` to give it a name)
+/// matches whatever is left of the path, so a single route can serve a
+/// whole subtree. The values captured for a request are handed to the
+/// handler as [`PathParams`] in the request extensions, which are left
+/// untouched for routes that capture nothing; an anonymous wildcard is
+/// captured under the name `*`.
+///
+/// Patterns built with [`RequestPath::regex`] instead match the whole path
+/// against a regular expression, with named groups captured as parameters.
+#[derive(Clone, Debug)]
+pub struct RequestPath {
+    pattern: String,
+    matcher: Matcher,
+}
+
+impl RequestPath {
+    pub fn new(pattern: &str) -> Self {
+        let segments: Vec<_> = pattern
+            .trim_start_matches('/')
+            .split('/')
+            .map(Segment::parse)
+            .collect();
+
+        if let Some(position) = segments.iter().position(|segment| match segment {
+            Segment::Wildcard(_) => true,
+            _ => false,
+        }) {
+            assert!(
+                position == segments.len() - 1,
+                "wildcard must be the last segment of route {:?}",
+                pattern
+            );
+        }
+
+        RequestPath {
+            pattern: pattern.to_string(),
+            matcher: Matcher::Segments(segments),
+        }
+    }
+
+    /// Builds a pattern that matches when the regular expression matches
+    /// the entire request path.
+    pub fn regex(pattern: &str) -> Self {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))
+            .unwrap_or_else(|err| panic!("invalid route regex {:?}: {}", pattern, err));
+
+        RequestPath {
+            pattern: pattern.to_string(),
+            matcher: Matcher::Regex(regex),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns this pattern nested under `prefix`, e.g. `/networks` under
+    /// `/api/v1` becomes `/api/v1/networks`.
+    pub fn with_prefix(&self, prefix: &str) -> RequestPath {
+        let prefix = prefix.trim_end_matches('/');
+        match self.matcher {
+            Matcher::Segments(_) => RequestPath::new(&format!("{}{}", prefix, self.pattern)),
+            Matcher::Regex(_) => {
+                RequestPath::regex(&format!("{}{}", regex::escape(prefix), self.pattern))
+            }
+        }
+    }
+
+    /// The segments of this pattern, unless it is a regex.
+    pub(super) fn segments(&self) -> Option<&[Segment]> {
+        match &self.matcher {
+            Matcher::Segments(segments) => Some(segments),
+            Matcher::Regex(_) => None,
+        }
+    }
+
+    pub fn is_regex(&self) -> bool {
+        match self.matcher {
+            Matcher::Regex(_) => true,
+            Matcher::Segments(_) => false,
+        }
+    }
+
+    /// Whether this pattern ends in a wildcard segment or is a regular
+    /// expression, either of which can match arbitrarily long paths.
+    pub fn is_catch_all(&self) -> bool {
+        match &self.matcher {
+            Matcher::Segments(segments) => match segments.last() {
+                Some(Segment::Wildcard(_)) => true,
+                _ => false,
+            },
+            Matcher::Regex(_) => true,
+        }
+    }
+
+    /// Compares how specific two patterns are, with the more specific
+    /// pattern ordered greater. Segments are compared from left to right,
+    /// with literals beating parameters and parameters beating wildcards;
+    /// regex patterns are less specific than any segment pattern.
+    pub fn cmp_specificity(&self, other: &RequestPath) -> Ordering {
+        match (&self.matcher, &other.matcher) {
+            (Matcher::Segments(lhs), Matcher::Segments(rhs)) => lhs
+                .iter()
+                .map(Segment::rank)
+                .cmp(rhs.iter().map(Segment::rank)),
+            (Matcher::Segments(_), Matcher::Regex(_)) => Ordering::Greater,
+            (Matcher::Regex(_), Matcher::Segments(_)) => Ordering::Less,
+            (Matcher::Regex(_), Matcher::Regex(_)) => Ordering::Equal,
+        }
+    }
+
+    /// Whether every typed parameter in `params`, as captured by this
+    /// pattern, parses as its declared type.
+    pub(super) fn params_are_valid(&self, params: &PathParams) -> bool {
+        let segments = match &self.matcher {
+            Matcher::Segments(segments) => segments,
+            Matcher::Regex(_) => return true,
+        };
+
+        segments.iter().all(|segment| match segment {
+            Segment::Param(name, Some(kind)) => {
+                params.get(name).map_or(false, |value| kind.accepts(value))
+            }
+            _ => true,
+        })
+    }
+
+    /// Whether `path` matches this pattern. Unlike [`RequestPath::matches`]
+    /// this doesn't allocate.
+    pub fn is_match(&self, path: &str) -> bool {
+        match &self.matcher {
+            Matcher::Segments(segments) => match_segments(segments, path, None),
+            Matcher::Regex(regex) => regex.is_match(path),
+        }
+    }
+
+    /// Matches `path` against this pattern, returning the captured
+    /// parameters on success.
+    pub fn matches(&self, path: &str) -> Option<PathParams> {
+        match &self.matcher {
+            Matcher::Segments(segments) => {
+                let mut params = PathParams::default();
+                if match_segments(segments, path, Some(&mut params)) {
+                    Some(params)
+                } else {
+                    None
+                }
+            }
+            Matcher::Regex(regex) => {
+                let captures = regex.captures(path)?;
+                let params = regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        captures
+                            .name(name)
+                            .map(|value| (name.to_string(), value.as_str().to_string()))
+                    })
+                    .collect();
+                Some(PathParams(params))
+            }
+        }
+    }
+}
+
+/// Matches `path` against `segments`, pushing the captured values onto
+/// `params` if given.
+fn match_segments(segments: &[Segment], path: &str, mut params: Option<&mut PathParams>) -> bool {
+    let mut remaining = Some(path.trim_start_matches('/'));
+
+    for segment in segments {
+        let current = match remaining {
+            Some(current) => current,
+            None => return false,
+        };
+
+        if let Segment::Wildcard(name) = segment {
+            if let Some(params) = params {
+                let name = name.as_ref().map_or("*", String::as_str);
+                params.0.push((name.to_string(), current.to_string()));
+            }
+            return true;
+        }
+
+        let part = match current.find('/') {
+            Some(index) => {
+                remaining = Some(&current[index + 1..]);
+                &current[..index]
+            }
+            None => {
+                remaining = None;
+                current
+            }
+        };
+
+        match segment {
+            Segment::Literal(literal) => {
+                if literal != part {
+                    return false;
+                }
+            }
+            Segment::Param(name, _) => {
+                if part.is_empty() {
+                    return false;
+                }
+                if let Some(params) = params.as_mut() {
+                    params.0.push((name.clone(), part.to_string()));
+                }
+            }
+            Segment::Wildcard(_) => unreachable!(),
+        }
+    }
+
+    remaining.is_none()
+}
+
+impl PartialEq for RequestPath {
+    fn eq(&self, other: &RequestPath) -> bool {
+        self.is_regex() == other.is_regex() && self.pattern == other.pattern
+    }
+}
+
+impl Eq for RequestPath {}
+
+impl Hash for RequestPath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.is_regex().hash(state);
+        self.pattern.hash(state);
+    }
+}
+
+impl Ord for RequestPath {
+    fn cmp(&self, other: &RequestPath) -> Ordering {
+        (self.is_regex(), &self.pattern).cmp(&(other.is_regex(), &other.pattern))
+    }
+}
+
+impl PartialOrd for RequestPath {
+    fn partial_cmp(&self, other: &RequestPath) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> From<&'a str> for RequestPath {
+    fn from(pattern: &'a str) -> Self {
+        RequestPath::new(pattern)
+    }
+}
+
+impl From<String> for RequestPath {
+    fn from(pattern: String) -> Self {
+        RequestPath::new(&pattern)
+    }
+}
+
+impl fmt::Display for RequestPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// Values captured by the named segments of the route that matched a
+/// request, in the order they appear in the pattern.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathParams(Vec<(String, String)>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses the parameter `name` as `T`. For a typed parameter such as
+    /// `{id:u64}` this only fails if `T` isn't the declared type, since
+    /// requests whose value doesn't parse never reach the handler.
+    pub fn parse<T>(&self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+    {
+        self.get(name)?.parse().ok()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(path: &RequestPath, request: &str) -> Option<Vec<(String, String)>> {
+        path.matches(request).map(|params| {
+            params
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        })
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn literals_match_exactly() {
+        let path = RequestPath::new("/networks");
+        assert_eq!(params(&path, "/networks"), pairs(&[]));
+        assert_eq!(params(&path, "/networks/1"), None);
+        assert_eq!(params(&path, "/network"), None);
+        assert!(path.is_match("/networks"));
+        assert!(!path.is_match("/"));
+    }
+
+    #[test]
+    fn root_matches_only_the_root() {
+        let path = RequestPath::new("/");
+        assert_eq!(params(&path, "/"), pairs(&[]));
+        assert_eq!(params(&path, "/networks"), None);
+    }
+
+    #[test]
+    fn params_capture_one_non_empty_segment() {
+        let path = RequestPath::new("/networks/{id}/containers/{name}");
+        assert_eq!(
+            params(&path, "/networks/abc/containers/web"),
+            pairs(&[("id", "abc"), ("name", "web")])
+        );
+        assert_eq!(params(&path, "/networks//containers/web"), None);
+        assert_eq!(params(&path, "/networks/abc/containers"), None);
+        assert_eq!(params(&path, "/networks/abc/containers/web/logs"), None);
+    }
+
+    #[test]
+    fn wildcards_capture_the_rest_of_the_path() {
+        let anonymous = RequestPath::new("/containers/*");
+        assert_eq!(
+            params(&anonymous, "/containers/a/b"),
+            pairs(&[("*", "a/b")])
+        );
+        assert_eq!(params(&anonymous, "/containers/"), pairs(&[("*", "")]));
+        assert_eq!(params(&anonymous, "/containers"), None);
+
+        let named = RequestPath::new("/files/{*rest}");
+        assert_eq!(
+            params(&named, "/files/a/b.txt"),
+            pairs(&[("rest", "a/b.txt")])
+        );
+        assert!(named.is_catch_all());
+        assert!(!RequestPath::new("/files/{rest}").is_catch_all());
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard must be the last segment")]
+    fn wildcards_must_come_last() {
+        RequestPath::new("/a/*/b");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown type")]
+    fn unknown_param_types_panic() {
+        RequestPath::new("/items/{id:uuid}");
+    }
+
+    #[test]
+    fn typed_params_are_checked_after_matching() {
+        let path = RequestPath::new("/items/{id:u64}/{flag:bool}");
+        let valid = path.matches("/items/7/true").expect("matches");
+        assert!(path.params_are_valid(&valid));
+        assert_eq!(valid.parse::<u64>("id"), Some(7));
+        assert_eq!(valid.parse::<bool>("flag"), Some(true));
+
+        let invalid = path.matches("/items/seven/true").expect("matches");
+        assert!(!path.params_are_valid(&invalid));
+    }
+
+    #[test]
+    fn regexes_match_the_whole_path_and_capture_named_groups() {
+        let path = RequestPath::regex("/items/(?P<id>[0-9]+)(/(details))?");
+        assert_eq!(params(&path, "/items/42"), pairs(&[("id", "42")]));
+        assert_eq!(params(&path, "/items/42/details"), pairs(&[("id", "42")]));
+        assert_eq!(params(&path, "/items/x"), None);
+        assert_eq!(params(&path, "/api/items/42"), None);
+        assert!(path.is_regex());
+        assert!(path.is_catch_all());
+    }
+
+    #[test]
+    fn prefixes_are_prepended() {
+        let path = RequestPath::new("/networks/{id}").with_prefix("/api/v1/");
+        assert_eq!(path.as_str(), "/api/v1/networks/{id}");
+        assert_eq!(params(&path, "/api/v1/networks/a"), pairs(&[("id", "a")]));
+
+        let regex = RequestPath::regex("/items/[0-9]+").with_prefix("/v1.0");
+        assert!(regex.is_match("/v1.0/items/3"));
+        assert!(!regex.is_match("/v1x0/items/3"));
+    }
+
+    #[test]
+    fn more_specific_patterns_order_greater() {
+        let literal = RequestPath::new("/a/b");
+        let param = RequestPath::new("/a/{x}");
+        let wildcard = RequestPath::new("/a/*");
+        let regex = RequestPath::regex("/a/b");
+
+        assert_eq!(literal.cmp_specificity(&param), Ordering::Greater);
+        assert_eq!(param.cmp_specificity(&wildcard), Ordering::Greater);
+        assert_eq!(wildcard.cmp_specificity(&regex), Ordering::Greater);
+        assert_eq!(regex.cmp_specificity(&literal), Ordering::Less);
+    }
+}
@@ -0,0 +1,347 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::{HeaderName, ACCEPT};
+use hyper::{Body, Method, Request};
+use typed_headers::mime::Mime;
+use typed_headers::{ContentType, HeaderMapExt};
+
+use super::normalize::decode_query_component;
+use super::{HttpMethod, RequestPath};
+use crate::handler::RequestHandler;
+use crate::middleware::Middleware;
+use crate::negotiate::{self, Quality, MAX_QUALITY};
+
+/// The quality given to routes that don't declare what they produce, so
+/// that any route the client explicitly accepts is preferred over them.
+const UNRESTRICTED_QUALITY: Quality = 1;
+
+/// A predicate a request must satisfy before it is dispatched to a route.
+pub type Guard = Arc<dyn Fn(&Request<Body>) -> bool + Send + Sync>;
+
+/// A handler registered for a method and path pattern, along with any extra
+/// conditions a request has to meet to be dispatched to it.
+///
+/// Several routes may share a method and path as long as their guards or
+/// content types tell the requests apart; the first route whose conditions
+/// all hold wins. Routes that only differ in the media type they produce are
+/// instead chosen between by the quality values of the `Accept` header.
+///
+/// A route without a method matches requests of any method, but loses to
+/// routes registered for the request's method specifically.
+#[derive(Clone)]
+pub struct Route {
+    /// `None` for routes that match any method.
+    pub(super) method: Option<HttpMethod>,
+    pub(super) path: RequestPath,
+    pub(super) guards: Vec<Guard>,
+    pub(super) content_type: Option<Mime>,
+    pub(super) accept: Option<Mime>,
+    pub(super) priority: i32,
+    pub(super) name: Option<String>,
+    pub(super) timeout: Option<Duration>,
+    pub(super) handler: RequestHandler,
+}
+
+impl Route {
+    /// Creates a route for `method`, or for every method if it's `None`.
+    pub fn new<M, P>(method: M, path: P, handler: RequestHandler) -> Self
+    where
+        M: Into<Option<Method>>,
+        P: Into<RequestPath>,
+    {
+        Route {
+            method: method.into().map(HttpMethod),
+            path: path.into(),
+            guards: vec![],
+            content_type: None,
+            accept: None,
+            priority: 0,
+            name: None,
+            timeout: None,
+            handler,
+        }
+    }
+
+    /// Creates a route that matches requests of any method.
+    pub fn any<P>(path: P, handler: RequestHandler) -> Self
+    where
+        P: Into<RequestPath>,
+    {
+        Route::new(None, path, handler)
+    }
+
+    pub fn method(&self) -> Option<&Method> {
+        self.method.as_ref().map(|method| &method.0)
+    }
+
+    pub fn path(&self) -> &RequestPath {
+        &self.path
+    }
+
+    /// A copy of this route registered for `method` instead.
+    pub fn for_method<M>(&self, method: M) -> Route
+    where
+        M: Into<Option<Method>>,
+    {
+        Route {
+            method: method.into().map(HttpMethod),
+            ..self.clone()
+        }
+    }
+
+    /// Only dispatches to this route when `guard` returns `true`.
+    pub fn guard<F>(mut self, guard: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> bool + Send + Sync + 'static,
+    {
+        self.guards.push(Arc::new(guard));
+        self
+    }
+
+    /// Only dispatches to this route when the request carries the header
+    /// `name` with exactly `value`.
+    pub fn header(self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .unwrap_or_else(|err| panic!("invalid header name {:?}: {}", name, err));
+        let value = value.to_string();
+
+        self.guard(move |req| {
+            req.headers()
+                .get_all(&name)
+                .iter()
+                .any(|header| header.as_bytes() == value.as_bytes())
+        })
+    }
+
+    /// Only dispatches to this route when the query string has the
+    /// parameter `name` set to `value`, compared after decoding.
+    pub fn query(self, name: &str, value: &str) -> Self {
+        let name = name.to_string();
+        let value = value.to_string();
+
+        self.guard(move |req| {
+            req.uri().query().map_or(false, |query| {
+                query.split('&').any(|pair| {
+                    let mut pair = pair.splitn(2, '=');
+                    let key = pair.next().and_then(decode_query_component);
+                    let actual = decode_query_component(pair.next().unwrap_or_default());
+                    match (key, actual) {
+                        (Some(key), Some(actual)) => key == name && actual == value,
+                        _ => false,
+                    }
+                })
+            })
+        })
+    }
+
+    /// Only dispatches to this route when the request body is of
+    /// `media_type`. Parameters such as `charset` are ignored when comparing.
+    pub fn content_type(mut self, media_type: &str) -> Self {
+        let media_type = media_type
+            .parse()
+            .unwrap_or_else(|err| panic!("invalid media type {:?}: {}", media_type, err));
+        self.content_type = Some(media_type);
+        self
+    }
+
+    /// Declares that this route produces `media_type`, so it's only
+    /// dispatched to when the client accepts it.
+    pub fn accept(mut self, media_type: &str) -> Self {
+        let media_type = media_type
+            .parse()
+            .unwrap_or_else(|err| panic!("invalid media type {:?}: {}", media_type, err));
+        self.accept = Some(media_type);
+        self
+    }
+
+    /// Overrides the usual precedence between routes matching the same
+    /// request: routes with a higher priority win regardless of how
+    /// specific their patterns are. Routes default to a priority of 0.
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Labels the route so it can be told apart when listing a router's
+    /// routes.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Answers with a `504 Gateway Timeout` if the handler's response isn't
+    /// ready within `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Replaces the route's handler with the one `middleware` wraps it in,
+    /// so that it can act on requests before they reach the handler and on
+    /// the responses it produces.
+    pub fn wrap<M>(mut self, middleware: M) -> Self
+    where
+        M: Middleware,
+    {
+        self.handler = middleware.wrap(self.handler);
+        self
+    }
+
+    pub(super) fn with_prefix(mut self, prefix: &str) -> Self {
+        self.path = self.path.with_prefix(prefix);
+        self
+    }
+
+    /// Matches the request against this route, using `path` in place of the
+    /// request's own path so that mounted routers can match relative paths.
+    /// Returns how much the client prefers what this route produces.
+    pub(super) fn matches(&self, req: &Request<Body>, path: &str) -> Option<Quality> {
+        if !self.matches_method(req.method()) || !self.path.is_match(path) {
+            return None;
+        }
+
+        let quality = self.accept_quality(req);
+        if quality > 0
+            && self.accepts_content_type(req)
+            && self.guards.iter().all(|guard| guard(req))
+        {
+            Some(quality)
+        } else {
+            None
+        }
+    }
+
+    /// Whether this route and `other` would be dispatched to for exactly
+    /// the same requests, so that one of them could never be reached.
+    /// Routes with guards are assumed to tell requests apart.
+    pub(super) fn conflicts_with(&self, other: &Route) -> bool {
+        self.method == other.method
+            && self.path == other.path
+            && self.guards.is_empty()
+            && other.guards.is_empty()
+            && self.content_type == other.content_type
+            && self.accept == other.accept
+    }
+
+    pub(super) fn matches_method(&self, method: &Method) -> bool {
+        self.method
+            .as_ref()
+            .map_or(true, |expected| expected.0 == *method)
+    }
+
+    pub(super) fn accepts_content_type(&self, req: &Request<Body>) -> bool {
+        let expected = match &self.content_type {
+            Some(expected) => expected,
+            None => return true,
+        };
+
+        match req.headers().typed_get::<ContentType>() {
+            Ok(Some(ContentType(actual))) => {
+                actual.type_() == expected.type_() && actual.subtype() == expected.subtype()
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn accept_quality(&self, req: &Request<Body>) -> Quality {
+        let produces = match &self.accept {
+            Some(produces) => produces,
+            None => return UNRESTRICTED_QUALITY,
+        };
+
+        match req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(accept) => negotiate::quality(&negotiate::parse_accept(accept), produces),
+            None => MAX_QUALITY,
+        }
+    }
+}
+
+/// The error returned when registering a route that conflicts with one
+/// that is already registered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateRoute(pub RouteInfo);
+
+impl fmt::Display for DuplicateRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "route {} is already registered", self.0)
+    }
+}
+
+impl Error for DuplicateRoute {}
+
+/// The route a request was dispatched to. It's inserted into the
+/// extensions of the request before the handler is called, and into those
+/// of the response the handler produces, so that middleware wrapping the
+/// dispatcher can tell which route served a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchedRoute {
+    /// `None` for routes that match any method.
+    pub method: Option<Method>,
+    /// The full pattern, including the prefixes of any routers it is
+    /// mounted under.
+    pub pattern: String,
+    pub name: Option<String>,
+}
+
+impl MatchedRoute {
+    pub(super) fn new(route: &Route, prefix: &str) -> Self {
+        MatchedRoute {
+            method: route.method().cloned(),
+            pattern: format!("{}{}", prefix, route.path),
+            name: route.name.clone(),
+        }
+    }
+}
+
+/// A description of a registered route, as listed by `Router::routes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The virtual host the route is registered for, if any.
+    pub host: Option<String>,
+    /// `None` for routes that match any method.
+    pub method: Option<Method>,
+    /// The full pattern, including the prefixes of any routers it is
+    /// mounted under.
+    pub pattern: String,
+    pub regex: bool,
+    pub name: Option<String>,
+}
+
+impl RouteInfo {
+    pub(super) fn new(route: &Route, host: Option<&str>, prefix: &str) -> Self {
+        RouteInfo {
+            host: host.map(ToString::to_string),
+            method: route.method().cloned(),
+            pattern: format!("{}{}", prefix, route.path),
+            regex: route.path.is_regex(),
+            name: route.name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(host) = &self.host {
+            write!(f, "{} ", host)?;
+        }
+        match &self.method {
+            Some(method) => write!(f, "{} ", method)?,
+            None => write!(f, "ANY ")?,
+        }
+        if self.regex {
+            write!(f, "regex ")?;
+        }
+        write!(f, "{}", self.pattern)?;
+        if let Some(name) = &self.name {
+            write!(f, " ({})", name)?;
+        }
+        Ok(())
+    }
+}
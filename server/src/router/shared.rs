@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use hyper::{Body, Method, Request};
+
+use super::{wrap_handler, DuplicateRoute, RequestPath, Route, RouteInfo, Router};
+use crate::handler::{CloneableService, RequestHandler, ResponseFuture};
+
+/// A router that can be changed while a server is dispatching to it.
+///
+/// Clones share the same routes, so a clone kept by a test can add and
+/// remove routes after another has been handed to
+/// [`make_shared_req_dispatcher`]. Every request briefly locks the router
+/// while it picks a route and calls its handler to get the response future;
+/// the future itself runs without the lock. A mutex is used rather than a
+/// read-write lock since handlers take `&mut self` and aren't `Sync`.
+#[derive(Clone, Default)]
+pub struct SharedRouter(Arc<Mutex<Router>>);
+
+impl SharedRouter {
+    pub fn new<R>(router: R) -> Self
+    where
+        R: Into<Router>,
+    {
+        SharedRouter(Arc::new(Mutex::new(router.into())))
+    }
+
+    /// Registers `route`, panicking if it conflicts with a route that is
+    /// already registered.
+    pub fn add_route(&self, route: Route) -> &Self {
+        self.lock().add(route);
+        self
+    }
+
+    /// Registers `route`, failing if it conflicts with a route that is
+    /// already registered.
+    pub fn try_add_route(&self, route: Route) -> Result<&Self, DuplicateRoute> {
+        self.lock().try_add(route)?;
+        Ok(self)
+    }
+
+    /// Removes every route registered for `method` and `path`, returning
+    /// whether there were any.
+    pub fn remove_route<M, P>(&self, method: M, path: P) -> bool
+    where
+        M: Into<Option<Method>>,
+        P: Into<RequestPath>,
+    {
+        self.lock().remove(method, &path.into())
+    }
+
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.lock().routes()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Router> {
+        // A handler that panicked while the router was locked can't have
+        // left it half updated, so there's no reason to stop serving.
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<Router> for SharedRouter {
+    fn from(router: Router) -> Self {
+        SharedRouter::new(router)
+    }
+}
+
+/// Creates the request handling closure for a server whose routes can change
+/// while it runs. Requests are handled the same way as by
+/// [`make_req_dispatcher`](super::make_req_dispatcher), including the
+/// router's middleware as of when the dispatcher is made.
+pub fn make_shared_req_dispatcher(
+    router: SharedRouter,
+    default_handler: RequestHandler,
+) -> impl FnMut(Request<Body>) -> ResponseFuture + Clone {
+    let middleware = router.lock().middleware.clone();
+    let dispatcher = Box::new(SharedDispatcher {
+        router,
+        default_handler,
+    }) as RequestHandler;
+
+    let mut handler = wrap_handler(dispatcher, &middleware);
+    move |req: Request<Body>| handler.call(req)
+}
+
+#[derive(Clone)]
+struct SharedDispatcher {
+    router: SharedRouter,
+    default_handler: RequestHandler,
+}
+
+impl CloneableService for SharedDispatcher {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn call(&mut self, req: Request<Body>) -> ResponseFuture {
+        self.router.lock().handle(req, &mut self.default_handler)
+    }
+}
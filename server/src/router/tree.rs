@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use super::path::Segment;
+use super::RequestPath;
+
+/// A prefix tree over path segments, built as routes are registered, that
+/// narrows a request path down to the routes that could match it without
+/// trying every pattern in turn.
+///
+/// Routes are identified by their index in the router's table. Regex
+/// patterns can't be placed in the tree and are returned for every path, so
+/// callers still have to match candidates against the full pattern.
+#[derive(Clone, Debug, Default)]
+pub(super) struct RouteTree {
+    root: Node,
+    regex_routes: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    literals: HashMap<String, Node>,
+    param: Option<Box<Node>>,
+    /// Routes whose pattern ends here with a wildcard.
+    wildcard: Vec<usize>,
+    /// Routes whose pattern ends exactly here.
+    routes: Vec<usize>,
+}
+
+impl RouteTree {
+    pub(super) fn insert(&mut self, path: &RequestPath, index: usize) {
+        let segments = match path.segments() {
+            Some(segments) => segments,
+            None => {
+                self.regex_routes.push(index);
+                return;
+            }
+        };
+
+        let mut node = &mut self.root;
+        for segment in segments {
+            node = match segment {
+                Segment::Literal(literal) => node
+                    .literals
+                    .entry(literal.clone())
+                    .or_insert_with(Node::default),
+                Segment::Param(..) => &mut **node.param.get_or_insert_with(Box::default),
+                Segment::Wildcard(_) => {
+                    node.wildcard.push(index);
+                    return;
+                }
+            };
+        }
+        node.routes.push(index);
+    }
+
+    /// Appends the indices of the routes that may match `path` to
+    /// `candidates`, in the order they were registered.
+    pub(super) fn lookup(&self, path: &str, candidates: &mut Vec<usize>) {
+        let start = candidates.len();
+        self.root
+            .collect(Some(path.trim_start_matches('/')), candidates);
+        candidates.extend(&self.regex_routes);
+        candidates[start..].sort_unstable();
+    }
+}
+
+impl Node {
+    fn collect(&self, remaining: Option<&str>, candidates: &mut Vec<usize>) {
+        let current = match remaining {
+            Some(current) => current,
+            None => {
+                candidates.extend(&self.routes);
+                return;
+            }
+        };
+
+        candidates.extend(&self.wildcard);
+
+        let (part, next) = match current.find('/') {
+            Some(index) => (&current[..index], Some(&current[index + 1..])),
+            None => (current, None),
+        };
+
+        if let Some(child) = self.literals.get(part) {
+            child.collect(next, candidates);
+        }
+
+        if !part.is_empty() {
+            if let Some(child) = &self.param {
+                child.collect(next, candidates);
+            }
+        }
+    }
+}
@@ -0,0 +1,1990 @@
+use std::cmp;
+use std::error::Error as StdError;
+#[cfg(windows)]
+use std::ffi::{OsStr, OsString};
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+use std::fmt;
+#[cfg(unix)]
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{self, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net as unix_net;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::task::{self, Task};
+use futures::{future, try_ready, Async, Future, Poll, Stream};
+use http::request::Parts;
+use hyper::body::Payload;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, CONNECTION, CONTENT_LENGTH, HOST, TE, TRANSFER_ENCODING,
+    UPGRADE,
+};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Chunk, Method, Request, Response, StatusCode, Version};
+use log::error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::reactor::Handle;
+use tokio::runtime::Runtime;
+use tokio::timer::{Delay, Timeout};
+#[cfg(windows)]
+use tokio_named_pipes::NamedPipe;
+
+use crate::certificate::ClientCertificates;
+use crate::handler::{self, RequestHandler, ResponseFuture};
+#[cfg(windows)]
+use crate::pipe;
+use crate::reply::{self, PendingTrailers, Trailers};
+use crate::router::{self, DispatchTable, Router};
+use crate::shutdown::{self, Drain, Shutdown, ShutdownHandle};
+#[cfg(feature = "templates")]
+use crate::template::Templates;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+use crate::tls::{Acceptor, TlsConfig, TlsSession};
+
+/// A server dispatching the requests it accepts to a [`Router`]:
+///
+/// ```ignore
+/// Server::builder()
+///     .bind(([127, 0, 0, 1], 8080))
+///     .routes(routes!(
+///         GET "/networks" => on_get_networks,
+///     ))
+///     .run()?;
+/// ```
+///
+/// A server can listen at several addresses at once, e.g. serving HTTP on
+/// one port, HTTPS on another and a unix socket besides, all dispatching to
+/// the same router and shut down together.
+pub struct Server {
+    listeners: Vec<(Bound, Transport)>,
+    handler: RequestHandler,
+    http: Http,
+    h2c: bool,
+    shutdown_handle: ShutdownHandle,
+    shutdown: Shutdown,
+    drain_timeout: Duration,
+    limits: Limits,
+    max_connections: Option<(usize, Overflow)>,
+}
+
+impl Server {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// The address the server's first TCP listener is bound to, which
+    /// tells which port the system picked when binding to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().into_iter().next()
+    }
+
+    /// The addresses all of the server's TCP listeners are bound to, in
+    /// the order they were added.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|(listener, _)| match listener {
+                Bound::Tcp(listener) => listener.local_addr().ok(),
+                #[cfg(unix)]
+                Bound::Unix(_) => None,
+                #[cfg(windows)]
+                Bound::Pipe(_) => None,
+            })
+            .collect()
+    }
+
+    /// A handle to shut the server down with once it runs.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+
+    /// Serves connections until the server is shut down or one of its
+    /// listeners fails, on the runtime the returned future is run on. The
+    /// future resolves once the connections accepted until then have
+    /// finished the requests they were handling and closed, or were closed
+    /// when the drain timeout ran out.
+    pub fn serve(self) -> impl Future<Item = (), Error = io::Error> + Send {
+        let mut handler = self.handler;
+        let dispatcher = move |req| handler.call(req);
+        let (drain, draining) = shutdown::drain();
+        let http = self.http;
+        let h2c = self.h2c;
+        let limits = self.limits;
+        let capacity = self
+            .max_connections
+            .map(|(max, overflow)| Capacity::new(max, overflow));
+        let accepts: Vec<_> = self
+            .listeners
+            .into_iter()
+            .map(|(listener, transport)| {
+                let connections = Connections {
+                    http: http.clone(),
+                    h2c,
+                    limits,
+                    drain: Some(drain.clone()),
+                    capacity: capacity.clone(),
+                };
+                listener.serve(transport, connections, dispatcher.clone())
+            })
+            .collect();
+        let accept = future::join_all(accepts).map(|_| ());
+
+        self.shutdown.serve(accept, draining, self.drain_timeout)
+    }
+
+    /// Runs the server on a new runtime, blocking the current thread until
+    /// it stops and its connections are drained.
+    pub fn run(self) {
+        let mut runtime = match Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                error!("failed to start the runtime: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = runtime.block_on(self.serve()) {
+            error!("server failed: {}", err);
+        }
+        let _ = runtime.shutdown_now().wait();
+    }
+}
+
+/// Configures and binds a [`Server`].
+pub struct Builder {
+    endpoints: Vec<Endpoint>,
+    router: Router,
+    default_handler: Option<RequestHandler>,
+    #[cfg(feature = "templates")]
+    templates: Option<Templates>,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    tls: Option<TlsConfig>,
+    http: Http,
+    h2c: bool,
+    signals: bool,
+    drain_timeout: Duration,
+    limits: Limits,
+    max_connections: Option<(usize, Overflow)>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            endpoints: vec![],
+            router: Router::default(),
+            default_handler: None,
+            #[cfg(feature = "templates")]
+            templates: None,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            tls: None,
+            http: Http::new(),
+            h2c: true,
+            signals: false,
+            drain_timeout: Duration::from_secs(30),
+            limits: Limits::default(),
+            max_connections: None,
+        }
+    }
+}
+
+impl Builder {
+    /// Adds an address to listen at. Binding to port 0 lets the system
+    /// pick a free port, which [`Server::local_addr`] then tells, so that
+    /// tests running in parallel don't race for ports.
+    pub fn bind<A>(self, addr: A) -> Self
+    where
+        A: Into<SocketAddr>,
+    {
+        self.endpoint(ListenAt::Addr(addr.into()))
+    }
+
+    /// Adds an address to serve HTTPS at with `config`, whether or not the
+    /// other listeners serve HTTPS.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn bind_tls<A>(mut self, addr: A, config: TlsConfig) -> Self
+    where
+        A: Into<SocketAddr>,
+    {
+        self.endpoints.push(Endpoint {
+            at: ListenAt::Addr(addr.into()),
+            tls: Some(config),
+        });
+        self
+    }
+
+    /// Adds a unix socket to listen at. A socket left at `path` by an
+    /// earlier run is removed first.
+    #[cfg(unix)]
+    pub fn bind_uds<P>(self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.endpoint(ListenAt::Path(path.into()))
+    }
+
+    /// Adds a named pipe to listen at, e.g. `\\.\pipe\docker_engine`.
+    #[cfg(windows)]
+    pub fn bind_pipe<S>(self, name: S) -> Self
+    where
+        S: Into<OsString>,
+    {
+        self.endpoint(ListenAt::Pipe(name.into()))
+    }
+
+    /// Adds `listener`, a socket opened beforehand, e.g. one passed on by
+    /// systemd, to the sockets to listen at.
+    pub fn listener<L>(self, listener: L) -> Self
+    where
+        L: Into<Listener>,
+    {
+        self.endpoint(ListenAt::Listener(listener.into()))
+    }
+
+    fn endpoint(mut self, at: ListenAt) -> Self {
+        self.endpoints.push(Endpoint {
+            at,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            tls: None,
+        });
+        self
+    }
+
+    /// Adds every route in `table` to the server's router.
+    pub fn routes(mut self, table: DispatchTable) -> Self {
+        for route in table {
+            self.router.add(route);
+        }
+        self
+    }
+
+    /// Replaces the server's router with `router`, for when it needs more
+    /// than a list of routes, e.g. mounted routers or middleware.
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Sets the handler for requests that match no route. Defaults to one
+    /// answering with an empty `404 Not Found`.
+    pub fn default_handler(mut self, handler: RequestHandler) -> Self {
+        self.default_handler = Some(handler);
+        self
+    }
+
+    /// Installs `templates` for [`reply::render`] when the server is
+    /// built.
+    #[cfg(feature = "templates")]
+    pub fn templates(mut self, templates: Templates) -> Self {
+        self.templates = Some(templates);
+        self
+    }
+
+    /// Serves HTTPS with `config` instead of plain HTTP at the addresses and
+    /// sockets that weren't added with a configuration of their own.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Sets whether plain HTTP connections can be served with HTTP/2. On
+    /// by default.
+    ///
+    /// Connections that start with the HTTP/2 connection preface, as those
+    /// of clients with prior knowledge of h2c do, are served with HTTP/2
+    /// from the start. A request asking to upgrade its connection to h2c is
+    /// answered with `101 Switching Protocols`, and then as the first
+    /// HTTP/2 stream, unless it has a body, in which case it's answered
+    /// over HTTP/1.1 as RFC 7540 allows. Connections served over TLS only
+    /// use HTTP/2 when they negotiate it through ALPN.
+    pub fn h2c(mut self, enabled: bool) -> Self {
+        self.http.http1_only(!enabled);
+        self.h2c = enabled;
+        self
+    }
+
+    /// Sets whether connections are kept open for further requests once a
+    /// response is sent. On by default.
+    pub fn keep_alive(mut self, enabled: bool) -> Self {
+        self.http.keep_alive(enabled);
+        self
+    }
+
+    /// Closes connections that go `timeout` without a request while no
+    /// response is being sent, counting from when the connection was
+    /// accepted or the last response was sent until the next request's
+    /// headers are received.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections once they've served `max` requests, answering
+    /// the last one with `Connection: close`. HTTP/2 connections aren't
+    /// limited.
+    pub fn max_requests_per_connection(mut self, max: usize) -> Self {
+        self.limits.max_requests = Some(max);
+        self
+    }
+
+    /// Closes connections whose client takes longer than `timeout` to send
+    /// the headers of a request once it has started sending it, or to
+    /// complete the TLS handshake.
+    pub fn header_read_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.header_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails reading the body of a request, as the handler sees it, once
+    /// the client has sent none of it for `timeout`.
+    pub fn body_read_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.body_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections whose client hasn't read any of a response for
+    /// `timeout` while the server is waiting to write more of it.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections that nothing has been read from or written to
+    /// for `timeout`, even while a request is in flight.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Keeps at most `max` connections open at once across all of the
+    /// server's listeners, handling those over the limit as `overflow`
+    /// says.
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_connections(mut self, max: usize, overflow: Overflow) -> Self {
+        assert!(max > 0, "the connection limit must be at least 1");
+        self.max_connections = Some((max, overflow));
+        self
+    }
+
+    /// Shuts the server down on `SIGINT` and `SIGTERM`, or Ctrl-C on
+    /// Windows, besides through its [`ShutdownHandle`].
+    pub fn shutdown_on_signals(mut self) -> Self {
+        self.signals = true;
+        self
+    }
+
+    /// Sets how long connections get to finish the requests they're
+    /// handling once the server shuts down before they're closed anyway.
+    /// Defaults to 30 seconds.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Binds the listeners, failing if there are none or one of them can't
+    /// be bound. Clients can connect as soon as this returns, even though
+    /// their requests aren't handled until the server runs.
+    pub fn build(self) -> io::Result<Server> {
+        if self.endpoints.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no address to bind to",
+            ));
+        }
+        #[cfg(any(feature = "rustls", feature = "native-tls"))]
+        let tls = self.tls;
+        let listeners = self
+            .endpoints
+            .into_iter()
+            .map(|endpoint| {
+                #[cfg(any(feature = "rustls", feature = "native-tls"))]
+                let transport = endpoint
+                    .tls
+                    .as_ref()
+                    .or_else(|| tls.as_ref())
+                    .map_or(Transport::Plain, Transport::tls);
+                #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+                let transport = Transport::Plain;
+                Ok((endpoint.at.bind()?, transport))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        #[cfg(feature = "templates")]
+        {
+            if let Some(templates) = self.templates {
+                templates.install();
+            }
+        }
+
+        let default_handler = self.default_handler.unwrap_or_else(|| {
+            handler::into_handler(|_: Request<Body>| reply::status(StatusCode::NOT_FOUND))
+        });
+        let (shutdown_handle, shutdown) = Shutdown::new(self.signals);
+
+        Ok(Server {
+            listeners,
+            handler: router::dispatch_handler(self.router, default_handler),
+            http: self.http,
+            h2c: self.h2c,
+            shutdown_handle,
+            shutdown,
+            drain_timeout: self.drain_timeout,
+            limits: self.limits,
+            max_connections: self.max_connections,
+        })
+    }
+
+    /// Builds the server and runs it on a new runtime, blocking the current
+    /// thread until it stops.
+    pub fn run(self) -> io::Result<()> {
+        self.build()?.run();
+        Ok(())
+    }
+}
+
+/// A listener to bind once the server is built, and how to serve it.
+struct Endpoint {
+    at: ListenAt,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    tls: Option<TlsConfig>,
+}
+
+/// Where to listen at.
+enum ListenAt {
+    Addr(SocketAddr),
+    Listener(Listener),
+    #[cfg(unix)]
+    Path(PathBuf),
+    #[cfg(windows)]
+    Pipe(OsString),
+}
+
+impl ListenAt {
+    fn bind(self) -> io::Result<Bound> {
+        match self {
+            ListenAt::Addr(addr) => Ok(Bound::Tcp(TcpListener::bind(&addr)?)),
+            ListenAt::Listener(listener) => listener.register(),
+            #[cfg(unix)]
+            ListenAt::Path(path) => {
+                remove_stale_socket(&path)?;
+                Ok(Bound::Unix(UnixListener::bind(&path)?))
+            }
+            #[cfg(windows)]
+            ListenAt::Pipe(name) => Ok(Bound::Pipe(pipe::Incoming::new(name)?)),
+        }
+    }
+}
+
+/// A listening socket opened outside the server.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(net::TcpListener),
+    #[cfg(unix)]
+    Unix(unix_net::UnixListener),
+}
+
+impl Listener {
+    /// Hands the socket over to the reactor of the runtime the server runs
+    /// on.
+    fn register(self) -> io::Result<Bound> {
+        let handle = Handle::default();
+        match self {
+            Listener::Tcp(listener) => Ok(Bound::Tcp(TcpListener::from_std(listener, &handle)?)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Bound::Unix(UnixListener::from_std(listener, &handle)?)),
+        }
+    }
+}
+
+impl From<net::TcpListener> for Listener {
+    fn from(listener: net::TcpListener) -> Self {
+        Listener::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<unix_net::UnixListener> for Listener {
+    fn from(listener: unix_net::UnixListener) -> Self {
+        Listener::Unix(listener)
+    }
+}
+
+/// A listener the server accepts connections from.
+enum Bound {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    #[cfg(windows)]
+    Pipe(pipe::Incoming),
+}
+
+impl Bound {
+    fn serve<F>(
+        self,
+        transport: Transport,
+        connections: Connections,
+        dispatcher: F,
+    ) -> Box<dyn Future<Item = (), Error = io::Error> + Send>
+    where
+        F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+    {
+        match self {
+            Bound::Tcp(listener) => Box::new(serve(
+                listener.incoming(),
+                transport,
+                connections,
+                dispatcher,
+            )),
+            #[cfg(unix)]
+            Bound::Unix(listener) => Box::new(serve(
+                listener.incoming(),
+                transport,
+                connections,
+                dispatcher,
+            )),
+            #[cfg(windows)]
+            Bound::Pipe(incoming) => Box::new(serve(incoming, transport, connections, dispatcher)),
+        }
+    }
+}
+
+/// A stream accepted by a listener.
+trait Connected {
+    /// The address of the client, if it connected over TCP.
+    fn peer(&self) -> io::Result<Option<SocketAddr>>;
+}
+
+impl Connected for TcpStream {
+    fn peer(&self) -> io::Result<Option<SocketAddr>> {
+        self.peer_addr().map(Some)
+    }
+}
+
+#[cfg(unix)]
+impl Connected for UnixStream {
+    fn peer(&self) -> io::Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+}
+
+#[cfg(windows)]
+impl Connected for NamedPipe {
+    fn peer(&self) -> io::Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+}
+
+/// The address of the client a request came from, as found in the request
+/// extensions of every request the server accepts over TCP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// The application protocol a client negotiated through ALPN when it
+/// connected over TLS, e.g. `h2`, as found in the request extensions. The
+/// HTTP version requests were actually sent with is `Request::version`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AlpnProtocol(pub Vec<u8>);
+
+/// What's known about a connection, recorded in the extensions of every
+/// request received over it.
+#[derive(Clone)]
+struct ConnectionInfo {
+    peer: Option<SocketAddr>,
+    certificates: Option<ClientCertificates>,
+    alpn_protocol: Option<AlpnProtocol>,
+}
+
+impl ConnectionInfo {
+    fn new(peer: Option<SocketAddr>) -> Self {
+        ConnectionInfo {
+            peer,
+            certificates: None,
+            alpn_protocol: None,
+        }
+    }
+
+    fn record(&self, req: &mut Request<Body>) {
+        let extensions = req.extensions_mut();
+        if let Some(peer) = self.peer {
+            extensions.insert(PeerAddr(peer));
+        }
+        if let Some(certificates) = &self.certificates {
+            extensions.insert(certificates.clone());
+        }
+        if let Some(alpn_protocol) = &self.alpn_protocol {
+            extensions.insert(alpn_protocol.clone());
+        }
+    }
+
+    /// Who's at the other end of the connection, for logging.
+    fn client(&self) -> String {
+        self.peer
+            .map_or_else(|| "a local client".to_string(), |peer| peer.to_string())
+    }
+}
+
+/// Binds to `addr` and serves every connection accepted there with a clone
+/// of `dispatcher`, such as the one returned by `make_req_dispatcher`.
+///
+/// The listener is bound before returning, so clients can connect as soon
+/// as this returns even if the server hasn't started running yet.
+///
+/// Responses are sent with a `Content-Length` whenever the length of their
+/// body is known, and with chunked transfer coding otherwise.
+pub fn run_tcp_server<F>(
+    addr: &SocketAddr,
+    dispatcher: F,
+) -> io::Result<impl Future<Item = (), Error = io::Error>>
+where
+    F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+{
+    Ok(serve(
+        TcpListener::bind(addr)?.incoming(),
+        Transport::Plain,
+        Connections::default(),
+        dispatcher,
+    ))
+}
+
+/// Like [`run_tcp_server`], but listens at the unix socket `path`, e.g. to
+/// mock the Docker engine or the IoT Edge workload and management APIs. A
+/// socket left at `path` by an earlier run is removed first. Requests
+/// received over the socket have no [`PeerAddr`].
+#[cfg(unix)]
+pub fn run_uds_server<P, F>(
+    path: P,
+    dispatcher: F,
+) -> io::Result<impl Future<Item = (), Error = io::Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+{
+    let path = path.as_ref();
+    remove_stale_socket(path)?;
+    Ok(serve(
+        UnixListener::bind(path)?.incoming(),
+        Transport::Plain,
+        Connections::default(),
+        dispatcher,
+    ))
+}
+
+/// Like [`run_tcp_server`], but listens at the named pipe `name`, e.g.
+/// `\\.\pipe\docker_engine`, as the services mocked on Windows do.
+/// Requests received over the pipe have no [`PeerAddr`].
+#[cfg(windows)]
+pub fn run_pipe_server<S, F>(
+    name: S,
+    dispatcher: F,
+) -> io::Result<impl Future<Item = (), Error = io::Error>>
+where
+    S: AsRef<OsStr>,
+    F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+{
+    Ok(serve(
+        pipe::Incoming::new(name)?,
+        Transport::Plain,
+        Connections::default(),
+        dispatcher,
+    ))
+}
+
+/// Removes the unix socket at `path` if there is one, leaving anything
+/// else there alone for binding to fail on.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Ok(()),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// What connections are served over once accepted.
+#[derive(Clone)]
+enum Transport {
+    Plain,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    Tls {
+        acceptor: Acceptor,
+        forbid_anonymous: bool,
+    },
+}
+
+impl Transport {
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn tls(config: &TlsConfig) -> Self {
+        Transport::Tls {
+            acceptor: config.acceptor(),
+            forbid_anonymous: config.forbids_anonymous(),
+        }
+    }
+}
+
+/// Serves every connection `incoming` accepts with a clone of `dispatcher`.
+fn serve<L, F>(
+    incoming: L,
+    transport: Transport,
+    connections: Connections,
+    dispatcher: F,
+) -> impl Future<Item = (), Error = io::Error> + Send
+where
+    L: Stream<Error = io::Error> + Send + 'static,
+    L::Item: Connected + AsyncRead + AsyncWrite + Send + 'static,
+    F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+{
+    let incoming = Admit {
+        incoming,
+        capacity: connections.capacity.clone(),
+        reserved: None,
+    };
+    incoming.for_each(move |(stream, slot)| {
+        // A client that's gone before it could be looked at is dropped
+        // without affecting the others.
+        let info = match stream.peer() {
+            Ok(peer) => ConnectionInfo::new(peer),
+            Err(err) => {
+                error!("accepted connection failed: {}", err);
+                return Ok(());
+            }
+        };
+        let mut dispatcher = dispatcher.clone();
+        let mut connections = connections.clone();
+
+        // Connections over the limit are told to come back later, and
+        // closed once they've been.
+        let rejected = slot.is_none();
+        if rejected {
+            connections.http.keep_alive(false);
+            connections.h2c = false;
+        }
+        let dispatcher = move |req| {
+            if rejected {
+                Box::new(future::ok(reply::status(StatusCode::SERVICE_UNAVAILABLE)))
+                    as ResponseFuture
+            } else {
+                dispatcher(req)
+            }
+        };
+
+        let connection: Box<dyn Future<Item = (), Error = ()> + Send> = match &transport {
+            Transport::Plain => Box::new(serve_connection(connections, stream, info, dispatcher)),
+            #[cfg(feature = "rustls")]
+            Transport::Tls {
+                acceptor: Acceptor::Rustls(acceptor),
+                forbid_anonymous,
+            } => {
+                let handshake = acceptor.accept(stream);
+                Box::new(serve_tls(
+                    connections,
+                    handshake,
+                    info,
+                    *forbid_anonymous,
+                    dispatcher,
+                ))
+            }
+            #[cfg(feature = "native-tls")]
+            Transport::Tls {
+                acceptor: Acceptor::Native(acceptor),
+                forbid_anonymous,
+            } => {
+                let handshake = acceptor.accept(stream);
+                Box::new(serve_tls(
+                    connections,
+                    handshake,
+                    info,
+                    *forbid_anonymous,
+                    dispatcher,
+                ))
+            }
+        };
+        tokio::spawn(connection.then(move |result| {
+            drop(slot);
+            result
+        }));
+        Ok(())
+    })
+}
+
+/// Completes a TLS handshake with a client, then serves the
+/// requests it sends over the encrypted stream, with HTTP/2 if that's what
+/// they agreed on. If `forbid_anonymous` is set and the client didn't
+/// present a certificate, they're all answered with `403 Forbidden`.
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+fn serve_tls<H, F>(
+    mut connections: Connections,
+    handshake: H,
+    mut info: ConnectionInfo,
+    forbid_anonymous: bool,
+    mut dispatcher: F,
+) -> impl Future<Item = (), Error = ()> + Send
+where
+    H: Future + Send + 'static,
+    H::Item: AsyncRead + AsyncWrite + TlsSession + Send + 'static,
+    H::Error: fmt::Display,
+    F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+{
+    // Upgrading to h2c is only for plain HTTP, TLS connections negotiate
+    // HTTP/2 through ALPN instead.
+    connections.h2c = false;
+    let client = info.client();
+    let handshake = match connections.limits.header_read_timeout {
+        Some(timeout) => future::Either::A(Timeout::new(handshake, timeout).map_err(|err| {
+            if err.is_elapsed() {
+                "timed out".to_string()
+            } else {
+                err.into_inner()
+                    .map_or_else(|| "the timer failed".to_string(), |err| err.to_string())
+            }
+        })),
+        None => future::Either::B(handshake.map_err(|err| err.to_string())),
+    };
+    handshake
+        .map_err(move |err| error!("TLS handshake with {} failed: {}", client, err))
+        .and_then(move |stream| {
+            info.certificates = stream.peer_certificates();
+            info.alpn_protocol = stream.alpn_protocol().map(AlpnProtocol);
+            if let Some(AlpnProtocol(protocol)) = &info.alpn_protocol {
+                connections.http.http2_only(&protocol[..] == b"h2");
+            }
+
+            let forbidden = forbid_anonymous && info.certificates.is_none();
+            let dispatcher = move |req| {
+                if forbidden {
+                    Box::new(future::ok(reply::status(StatusCode::FORBIDDEN))) as ResponseFuture
+                } else {
+                    dispatcher(req)
+                }
+            };
+            serve_connection(connections, stream, info, dispatcher)
+        })
+}
+
+/// Serves the requests a client sends over `stream`, until the connection
+/// is drained or outlives its limits, carrying on with HTTP/2 if a request
+/// upgrades it to h2c.
+fn serve_connection<I, F>(
+    connections: Connections,
+    stream: I,
+    info: ConnectionInfo,
+    mut dispatcher: F,
+) -> Box<dyn Future<Item = (), Error = ()> + Send>
+where
+    I: AsyncRead + AsyncWrite + Send + 'static,
+    F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+{
+    let mut upgraded_connections = connections.clone();
+    upgraded_connections.http.http2_only(true);
+    upgraded_connections.h2c = false;
+    let Connections {
+        http,
+        h2c,
+        limits,
+        mut drain,
+        ..
+    } = connections;
+    let client = info.client();
+    let activity = Arc::new(Activity::default());
+    let upgraded = Arc::new(Mutex::new(None));
+
+    let requests = activity.clone();
+    let watched = activity.clone();
+    let upgrading = upgraded.clone();
+    let service = service_fn(move |mut req: Request<Body>| {
+        info.record(&mut req);
+        if let (Some(timeout), false) = (limits.body_read_timeout, req.body().is_end_stream()) {
+            let (parts, body) = req.into_parts();
+            let body = Body::wrap_stream(Stalling {
+                body,
+                timeout,
+                delay: None,
+            });
+            req = Request::from_parts(parts, body);
+        }
+        let (served, in_flight) = requests.begin();
+        let last = limits.max_requests.map_or(false, |max| served >= max);
+        let head = req.method() == Method::HEAD;
+        let response = if h2c && !last && wants_h2c(&req) {
+            let connection = upgrade_h2c::<I, F>(
+                req,
+                upgraded_connections.clone(),
+                info.clone(),
+                dispatcher.clone(),
+            );
+            *upgrading.lock().unwrap_or_else(PoisonError::into_inner) = Some(connection);
+            future::Either::B(future::ok(switching_to_h2c()))
+        } else {
+            future::Either::A(dispatcher(req))
+        };
+        response
+            .map(move |response| frame(response, head))
+            .map(move |mut response| {
+                if last {
+                    response
+                        .headers_mut()
+                        .insert(CONNECTION, HeaderValue::from_static("close"));
+                }
+                // Responses to HEAD requests have no body for trailers to
+                // follow.
+                let trailers = response
+                    .extensions_mut()
+                    .remove::<Trailers>()
+                    .and_then(Trailers::take)
+                    .filter(|_| !head);
+                // The request counts as in flight until its response has
+                // been sent, not just until it's been produced.
+                let (parts, body) = response.into_parts();
+                let body = Outgoing {
+                    body,
+                    trailers,
+                    _in_flight: in_flight,
+                };
+                Response::from_parts(parts, body)
+            })
+    });
+
+    let stream = Watched {
+        io: stream,
+        activity: watched,
+        limits,
+        headers: None,
+        write: None,
+    };
+    let mut connection = http.serve_connection(stream, service).with_upgrades();
+    let mut draining = false;
+    let mut waiting: Option<(usize, Delay)> = None;
+    let mut idle: Option<(usize, Delay)> = None;
+    let served = future::poll_fn(move || {
+        if let Some(drain) = &mut drain {
+            if drain.is_forced() {
+                return Ok(Async::Ready(()));
+            }
+            if !draining && drain.is_draining() {
+                connection.graceful_shutdown();
+                draining = true;
+            }
+        }
+
+        let polled = connection.poll()?;
+        if let Async::NotReady = polled {
+            if let Some(timeout) = limits.keep_alive_timeout {
+                if !activity.is_idle() {
+                    waiting = None;
+                } else if stalled(&mut waiting, activity.served(), timeout) {
+                    return Ok(Async::Ready(()));
+                }
+            }
+            if let Some(timeout) = limits.idle_timeout {
+                if stalled(&mut idle, activity.transferred(), timeout) {
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+        Ok(polled)
+    })
+    .map_err(move |err: hyper::Error| error!("connection from {} failed: {}", client, err));
+
+    // A connection upgraded to h2c is carried on with once hyper has handed
+    // it over.
+    Box::new(served.and_then(move |()| {
+        let pending = upgraded
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match pending {
+            Some(upgraded) => future::Either::A(upgraded),
+            None => future::Either::B(future::ok(())),
+        }
+    }))
+}
+
+/// The header clients asking to upgrade to h2c send their HTTP/2 settings
+/// in.
+const HTTP2_SETTINGS: &str = "http2-settings";
+
+/// Whether `req` asks to upgrade its connection to h2c as RFC 7540 has it,
+/// listing `h2c` in `Upgrade` and sending a single `HTTP2-Settings` that's
+/// named in `Connection`. Requests with a body are answered over HTTP/1.1,
+/// as it would have to be read in full before the connection could be
+/// upgraded.
+fn wants_h2c(req: &Request<Body>) -> bool {
+    let headers = req.headers();
+    let mut settings = headers.get_all(HTTP2_SETTINGS).iter();
+    let settings = match (settings.next(), settings.next()) {
+        (Some(settings), None) => settings.to_str().ok().map_or(false, |settings| {
+            base64::decode_config(settings.trim_end_matches('='), base64::URL_SAFE_NO_PAD).is_ok()
+        }),
+        _ => false,
+    };
+
+    settings
+        && req.version() == Version::HTTP_11
+        && req.body().is_end_stream()
+        && has_token(headers, &UPGRADE, "h2c")
+        && has_token(headers, &CONNECTION, "upgrade")
+        && has_token(headers, &CONNECTION, HTTP2_SETTINGS)
+}
+
+/// Whether the comma separated values of the header `name` include
+/// `token`, ignoring case.
+fn has_token(headers: &HeaderMap, name: &HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+fn switching_to_h2c() -> Response<Body> {
+    let mut response = reply::status(StatusCode::SWITCHING_PROTOCOLS);
+    let headers = response.headers_mut();
+    headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(UPGRADE, HeaderValue::from_static("h2c"));
+    response
+}
+
+/// Serves the connection `req` came over with HTTP/2 once hyper has
+/// answered it with `101 Switching Protocols` and handed the connection
+/// over, answering `req` itself on the first stream as RFC 7540 has it.
+///
+/// Hyper only serves streams clients open, so the request is read as if
+/// the client had opened the first one with it right after its connection
+/// preface. The settings it sent in `HTTP2-Settings` aren't applied, as
+/// hyper only takes those in the `SETTINGS` frame of the preface.
+fn upgrade_h2c<I, F>(
+    req: Request<Body>,
+    connections: Connections,
+    info: ConnectionInfo,
+    dispatcher: F,
+) -> Box<dyn Future<Item = (), Error = ()> + Send>
+where
+    I: AsyncRead + AsyncWrite + Send + 'static,
+    F: FnMut(Request<Body>) -> ResponseFuture + Clone + Send + 'static,
+{
+    let (parts, body) = req.into_parts();
+    let first_stream = first_stream(&parts);
+    let client = info.client();
+    let upgrade = body
+        .on_upgrade()
+        .map_err(move |err| error!("upgrading the connection from {} failed: {}", client, err))
+        .and_then(move |upgraded| match upgraded.downcast::<Watched<I>>() {
+            // The connection is watched afresh for the limits of the HTTP/2
+            // connection that takes it over.
+            Ok(parts) => Ok(H2c {
+                io: Box::new(parts.io.io),
+                buffered: parts.read_buf,
+                first_stream,
+                read: 0,
+                preface: None,
+                length: [0; 3],
+            }),
+            Err(_) => {
+                error!("upgraded connection isn't the one that was served");
+                Err(())
+            }
+        })
+        .and_then(move |stream| serve_connection(connections, stream, info, dispatcher));
+    Box::new(upgrade)
+}
+
+/// The length of the string HTTP/2 connection prefaces start with.
+const PREFACE_LEN: usize = 24;
+
+/// The length of an HTTP/2 frame header.
+const FRAME_HEADER_LEN: usize = 9;
+
+/// The largest frame HTTP/2 peers have to accept before they say otherwise.
+const MAX_FRAME_SIZE: usize = 16_384;
+
+/// The `HEADERS` frame, and any `CONTINUATION` frames it takes, that open
+/// the first stream of a connection upgraded to h2c with the request
+/// `parts` describe. Its `Host` becomes its `:authority`, and the headers
+/// that only apply to the HTTP/1.1 connection are left out as HTTP/2
+/// forbids them.
+fn first_stream(parts: &Parts) -> Bytes {
+    let headers = &parts.headers;
+    let authority = headers.get(HOST).map(HeaderValue::as_bytes).or_else(|| {
+        parts
+            .uri
+            .authority_part()
+            .map(|authority| authority.as_str().as_bytes())
+    });
+    let path = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+
+    let mut block = vec![];
+    hpack_literal(&mut block, b":method", parts.method.as_str().as_bytes());
+    hpack_literal(&mut block, b":scheme", b"http");
+    if let Some(authority) = authority {
+        hpack_literal(&mut block, b":authority", authority);
+    }
+    hpack_literal(&mut block, b":path", path.as_bytes());
+    for (name, value) in headers {
+        let connection_specific = *name == HOST
+            || *name == CONNECTION
+            || *name == UPGRADE
+            || *name == TRANSFER_ENCODING
+            || *name == HTTP2_SETTINGS
+            || *name == "keep-alive"
+            || *name == "proxy-connection"
+            || (*name == TE && *value != "trailers")
+            || has_token(headers, &CONNECTION, name.as_str());
+        if !connection_specific {
+            hpack_literal(&mut block, name.as_str().as_bytes(), value.as_bytes());
+        }
+    }
+
+    let count = (block.len() + MAX_FRAME_SIZE - 1) / MAX_FRAME_SIZE;
+    let mut frames = Vec::with_capacity(block.len() + count * FRAME_HEADER_LEN);
+    for (index, fragment) in block.chunks(MAX_FRAME_SIZE).enumerate() {
+        // A HEADERS frame that ends the stream, as the request has no body,
+        // followed by CONTINUATION frames, the last of which ends the
+        // headers.
+        let (kind, mut flags) = if index == 0 { (0x1, 0x1) } else { (0x9, 0x0) };
+        if index + 1 == count {
+            flags |= 0x4;
+        }
+        let length = fragment.len();
+        frames.extend_from_slice(&[
+            (length >> 16) as u8,
+            (length >> 8) as u8,
+            length as u8,
+            kind,
+            flags,
+            0,
+            0,
+            0,
+            1,
+        ]);
+        frames.extend_from_slice(fragment);
+    }
+    Bytes::from(frames)
+}
+
+/// Appends a header field to an HPACK header block as a literal that isn't
+/// indexed, so that it leaves the decoder's dynamic table as it was.
+fn hpack_literal(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    block.push(0);
+    for string in &[name, value] {
+        // Its length is an integer with a 7 bit prefix, after a clear bit
+        // for a string that isn't Huffman coded.
+        let mut length = string.len();
+        if length < 0x7f {
+            block.push(length as u8);
+        } else {
+            block.push(0x7f);
+            length -= 0x7f;
+            while length >= 0x80 {
+                block.push((length & 0x7f) as u8 | 0x80);
+                length >>= 7;
+            }
+            block.push(length as u8);
+        }
+        block.extend_from_slice(string);
+    }
+}
+
+/// Something a connection can be served over.
+trait Io: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Io for T {}
+
+/// A connection upgraded to h2c, which reads as if the client had opened
+/// the first stream itself right after its connection preface.
+struct H2c {
+    io: Box<dyn Io>,
+    /// What hyper had read from the connection before handing it over.
+    buffered: Bytes,
+    /// The frames opening the first stream that are yet to be read.
+    first_stream: Bytes,
+    /// How much of the client's preface has been read.
+    read: usize,
+    /// The length of the client's preface, once the header of the
+    /// `SETTINGS` frame it ends with has been read.
+    preface: Option<usize>,
+    /// The length of that `SETTINGS` frame, as it's read.
+    length: [u8; 3],
+}
+
+impl H2c {
+    fn read_io(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered.is_empty() {
+            self.io.read(buf)
+        } else {
+            let read = cmp::min(buf.len(), self.buffered.len());
+            buf[..read].copy_from_slice(&self.buffered.split_to(read));
+            Ok(read)
+        }
+    }
+}
+
+impl Read for H2c {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let end = match self.preface {
+            Some(end) if self.read == end => {
+                if self.first_stream.is_empty() {
+                    return self.read_io(buf);
+                }
+                let read = cmp::min(buf.len(), self.first_stream.len());
+                buf[..read].copy_from_slice(&self.first_stream.split_to(read));
+                return Ok(read);
+            }
+            Some(end) => end,
+            None => PREFACE_LEN + FRAME_HEADER_LEN,
+        };
+
+        let limit = cmp::min(buf.len(), end - self.read);
+        let read = self.read_io(&mut buf[..limit])?;
+        for (offset, byte) in buf[..read].iter().enumerate() {
+            let at = self.read + offset;
+            if at >= PREFACE_LEN && at < PREFACE_LEN + 3 {
+                self.length[at - PREFACE_LEN] = *byte;
+            }
+        }
+        self.read += read;
+        if self.preface.is_none() && self.read == PREFACE_LEN + FRAME_HEADER_LEN {
+            let length = self
+                .length
+                .iter()
+                .fold(0, |length, byte| (length << 8) | usize::from(*byte));
+            self.preface = Some(self.read + length);
+        }
+        Ok(read)
+    }
+}
+
+impl AsyncRead for H2c {}
+
+impl Write for H2c {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsyncWrite for H2c {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+/// Whether `progress`, a count that goes up as a connection makes
+/// progress, has stayed the same for `timeout`, keeping the timer in
+/// `timer`. The timer restarts whenever the count has changed since it was
+/// set.
+fn stalled(timer: &mut Option<(usize, Delay)>, progress: usize, timeout: Duration) -> bool {
+    match timer {
+        Some((since, _)) if *since == progress => {}
+        _ => *timer = Some((progress, Delay::new(Instant::now() + timeout))),
+    }
+    match timer {
+        Some((_, delay)) => match delay.poll() {
+            Ok(Async::NotReady) => false,
+            // A failing timer can't keep time, so the connection is closed
+            // rather than left without a limit.
+            Ok(Async::Ready(())) | Err(_) => true,
+        },
+        None => false,
+    }
+}
+
+/// How connections are served, and when they're closed.
+#[derive(Clone)]
+struct Connections {
+    http: Http,
+    /// Whether requests can upgrade their connection to h2c.
+    h2c: bool,
+    limits: Limits,
+    drain: Option<Drain>,
+    capacity: Option<Capacity>,
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        Connections {
+            http: Http::new(),
+            h2c: true,
+            limits: Limits::default(),
+            drain: None,
+            capacity: None,
+        }
+    }
+}
+
+/// What a server does with the connections it accepts once it has as
+/// many open as it was limited to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stops accepting connections until one of those open closes, leaving
+    /// new clients waiting in the listen backlog.
+    Pause,
+    /// Accepts them but answers their requests with `503 Service
+    /// Unavailable` and closes them.
+    Reject,
+}
+
+/// The connections a server keeps open at once, shared by its listeners.
+#[derive(Clone)]
+struct Capacity {
+    max: usize,
+    overflow: Overflow,
+    open: Arc<Mutex<Open>>,
+}
+
+#[derive(Default)]
+struct Open {
+    count: usize,
+    /// The accept tasks paused until a connection closes.
+    waiting: Vec<Task>,
+}
+
+impl Capacity {
+    fn new(max: usize, overflow: Overflow) -> Self {
+        Capacity {
+            max,
+            overflow,
+            open: Arc::new(Mutex::new(Open::default())),
+        }
+    }
+
+    /// Takes up a place for a new connection, if there's one left. If
+    /// there isn't and `wait` is set, the current task is woken once one
+    /// is given back.
+    fn take(&self, wait: bool) -> Option<Slot> {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        if open.count < self.max {
+            open.count += 1;
+            Some(Slot(Some(self.open.clone())))
+        } else {
+            if wait {
+                open.waiting.push(task::current());
+            }
+            None
+        }
+    }
+}
+
+/// The place a connection takes up among those a server keeps open, given
+/// back once it's dropped. Servers without a limit hand out empty ones.
+struct Slot(Option<Arc<Mutex<Open>>>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(open) = &self.0 {
+            let mut open = open.lock().unwrap_or_else(PoisonError::into_inner);
+            open.count -= 1;
+            for task in open.waiting.drain(..) {
+                task.notify();
+            }
+        }
+    }
+}
+
+/// Accepts connections from `incoming` while the server's limit allows,
+/// pairing each with its slot, or with none if it's over the limit and to
+/// be rejected.
+struct Admit<L> {
+    incoming: L,
+    capacity: Option<Capacity>,
+    /// The slot taken up for the next connection while pausing, so that
+    /// it can't be taken by another listener in the meantime.
+    reserved: Option<Slot>,
+}
+
+impl<L: Stream> Stream for Admit<L> {
+    type Item = (L::Item, Option<Slot>);
+    type Error = L::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, L::Error> {
+        let capacity = match &self.capacity {
+            Some(capacity) => capacity,
+            None => {
+                let stream = try_ready!(self.incoming.poll());
+                return Ok(Async::Ready(
+                    stream.map(|stream| (stream, Some(Slot(None)))),
+                ));
+            }
+        };
+
+        if capacity.overflow == Overflow::Reject {
+            let stream = try_ready!(self.incoming.poll());
+            return Ok(Async::Ready(
+                stream.map(|stream| (stream, capacity.take(false))),
+            ));
+        }
+
+        if self.reserved.is_none() {
+            self.reserved = capacity.take(true);
+            if self.reserved.is_none() {
+                return Ok(Async::NotReady);
+            }
+        }
+        let stream = try_ready!(self.incoming.poll());
+        Ok(Async::Ready(
+            stream.map(|stream| (stream, self.reserved.take())),
+        ))
+    }
+}
+
+/// How long and for how many requests connections are kept open.
+#[derive(Clone, Copy, Debug, Default)]
+struct Limits {
+    keep_alive_timeout: Option<Duration>,
+    max_requests: Option<usize>,
+    header_read_timeout: Option<Duration>,
+    body_read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+/// What a connection is up to, shared with the requests it serves.
+#[derive(Default)]
+struct Activity {
+    served: AtomicUsize,
+    in_flight: AtomicUsize,
+    transferred: AtomicUsize,
+}
+
+impl Activity {
+    /// Counts a new request, returning how many the connection has served
+    /// including it and a guard counting it as in flight until dropped.
+    fn begin(self: &Arc<Self>) -> (usize, InFlight) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let served = self.served.fetch_add(1, Ordering::SeqCst) + 1;
+        (served, InFlight(self.clone()))
+    }
+
+    fn served(&self) -> usize {
+        self.served.load(Ordering::SeqCst)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.in_flight.load(Ordering::SeqCst) == 0
+    }
+
+    /// Counts a read from or write to the connection that got somewhere.
+    fn transfer(&self) {
+        self.transferred.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn transferred(&self) -> usize {
+        self.transferred.load(Ordering::SeqCst)
+    }
+}
+
+/// Counts a request as in flight.
+struct InFlight(Arc<Activity>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A response body as it's sent: the body of the response a handler
+/// produced followed by the trailers it was given, if any, keeping its
+/// request counted as in flight until it's been sent or dropped.
+struct Outgoing {
+    body: Body,
+    trailers: Option<PendingTrailers>,
+    _in_flight: InFlight,
+}
+
+impl Payload for Outgoing {
+    type Data = Chunk;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll_data(&mut self) -> Poll<Option<Chunk>, Self::Error> {
+        self.body.poll_data().map_err(Into::into)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        match &mut self.trailers {
+            Some(trailers) => trailers.poll().map(|trailers| trailers.map(Some)),
+            None => self.body.poll_trailers().map_err(Into::into),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers.is_none() && self.body.is_end_stream()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.body.content_length()
+    }
+}
+
+/// A request body that fails once the client has sent none of it for
+/// `timeout` while it's being read.
+struct Stalling {
+    body: Body,
+    timeout: Duration,
+    delay: Option<Delay>,
+}
+
+impl Stream for Stalling {
+    type Item = Chunk;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, io::Error> {
+        match self.body.poll() {
+            Ok(Async::NotReady) => {}
+            Ok(ready) => {
+                self.delay = None;
+                return Ok(ready);
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+        }
+
+        let timeout = self.timeout;
+        let delay = self
+            .delay
+            .get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+        match delay.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) | Err(_) => Err(timed_out("reading the request body")),
+        }
+    }
+}
+
+/// The stream of a connection, which fails reading or writing once the
+/// client stalls for longer than the limits allow.
+struct Watched<I> {
+    io: I,
+    activity: Arc<Activity>,
+    limits: Limits,
+    /// The timer for the headers the client is sending, along with how
+    /// many requests had been served when it started sending them.
+    headers: Option<(usize, Delay)>,
+    /// The timer for the write that's waiting for the client to read.
+    write: Option<Delay>,
+}
+
+impl<I> Watched<I> {
+    /// Keeps the timer for the headers of the next request up to date
+    /// after a read, failing if they've taken too long.
+    fn after_read(&mut self, result: io::Result<usize>) -> io::Result<usize> {
+        let read_any = result.as_ref().map_or(false, |read| *read > 0);
+        if read_any {
+            self.activity.transfer();
+        }
+
+        let timeout = match self.limits.header_read_timeout {
+            Some(timeout) => timeout,
+            None => return result,
+        };
+
+        let served = self.activity.served();
+        if let Some((since, _)) = &self.headers {
+            if *since != served {
+                self.headers = None;
+            }
+        }
+        if read_any && self.headers.is_none() && self.activity.is_idle() {
+            self.headers = Some((served, Delay::new(Instant::now() + timeout)));
+        }
+
+        let expired = match (&result, &mut self.headers) {
+            (Err(err), Some((_, delay))) if err.kind() == io::ErrorKind::WouldBlock => {
+                match delay.poll() {
+                    Ok(Async::NotReady) => false,
+                    Ok(Async::Ready(())) | Err(_) => true,
+                }
+            }
+            _ => false,
+        };
+        if expired {
+            Err(timed_out("reading the request headers"))
+        } else {
+            result
+        }
+    }
+
+    /// Keeps the write timer up to date after a write or flush, failing if
+    /// the client hasn't read anything for too long.
+    fn after_write<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        match &result {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if let Some(timeout) = self.limits.write_timeout {
+                    let delay = self
+                        .write
+                        .get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+                    match delay.poll() {
+                        Ok(Async::NotReady) => {}
+                        Ok(Async::Ready(())) | Err(_) => {
+                            return Err(timed_out("writing the response"))
+                        }
+                    }
+                }
+            }
+            Ok(_) => {
+                self.activity.transfer();
+                self.write = None;
+            }
+            Err(_) => {}
+        }
+        result
+    }
+}
+
+impl<I: Read> Read for Watched<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.io.read(buf);
+        self.after_read(result)
+    }
+}
+
+impl<I: AsyncRead> AsyncRead for Watched<I> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<I: Write> Write for Watched<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.io.write(buf);
+        self.after_write(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.io.flush();
+        self.after_write(result)
+    }
+}
+
+impl<I: AsyncWrite> AsyncWrite for Watched<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+fn timed_out(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", what))
+}
+
+/// Makes the framing headers of `response` agree with its body, so that it
+/// never has both a `Content-Length` and a `Transfer-Encoding`. Responses
+/// whose status doesn't allow a body get neither, those with a
+/// `Transfer-Encoding` lose any `Content-Length`, as RFC 7230 has it
+/// override the length anyway, and the rest get a `Content-Length` when
+/// the length of their body is known. Hyper sends those without one
+/// chunked.
+///
+/// Responses to `HEAD` requests and `304 Not Modified` ones describe a
+/// representation they don't carry, so their empty body says nothing about
+/// its length and they're left with whatever `Content-Length` they have.
+fn frame(mut response: Response<Body>, head: bool) -> Response<Body> {
+    let status = response.status();
+    let length = response.body().content_length();
+    let headers = response.headers_mut();
+
+    if status.is_informational() || status == StatusCode::NO_CONTENT {
+        headers.remove(CONTENT_LENGTH);
+        headers.remove(TRANSFER_ENCODING);
+    } else if headers.contains_key(TRANSFER_ENCODING) {
+        headers.remove(CONTENT_LENGTH);
+    } else if !head && status != StatusCode::NOT_MODIFIED && !headers.contains_key(CONTENT_LENGTH) {
+        if let Some(length) = length {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream as StdTcpStream;
+
+    use hyper::client::conn;
+
+    use super::*;
+    use crate::body;
+
+    fn response(status: StatusCode, body: Body) -> Response<Body> {
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        response
+    }
+
+    fn content_length(response: &Response<Body>) -> Option<&str> {
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .map(|value| value.to_str().expect("lengths are ASCII"))
+    }
+
+    #[test]
+    fn known_lengths_are_advertised() {
+        let framed = frame(response(StatusCode::OK, Body::from("hello")), false);
+        assert_eq!(content_length(&framed), Some("5"));
+
+        let framed = frame(response(StatusCode::OK, Body::empty()), false);
+        assert_eq!(content_length(&framed), Some("0"));
+    }
+
+    #[test]
+    fn unknown_lengths_are_left_to_chunking() {
+        let (_sender, body) = Body::channel();
+        let framed = frame(response(StatusCode::OK, body), false);
+        assert_eq!(content_length(&framed), None);
+    }
+
+    #[test]
+    fn existing_lengths_are_kept() {
+        let mut unframed = response(StatusCode::OK, Body::from("hello"));
+        unframed
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from_static("5"));
+        assert_eq!(content_length(&frame(unframed, false)), Some("5"));
+    }
+
+    #[test]
+    fn transfer_encoding_overrides_length() {
+        let mut unframed = response(StatusCode::OK, Body::from("hello"));
+        let headers = unframed.headers_mut();
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("5"));
+
+        let framed = frame(unframed, false);
+        assert_eq!(content_length(&framed), None);
+        assert!(framed.headers().contains_key(TRANSFER_ENCODING));
+    }
+
+    #[test]
+    fn bodiless_statuses_get_no_framing() {
+        let mut unframed = response(StatusCode::NO_CONTENT, Body::empty());
+        let headers = unframed.headers_mut();
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+
+        let framed = frame(unframed, false);
+        assert_eq!(content_length(&framed), None);
+        assert!(!framed.headers().contains_key(TRANSFER_ENCODING));
+    }
+
+    #[test]
+    fn head_responses_keep_the_length_they_describe() {
+        let framed = frame(response(StatusCode::OK, Body::empty()), true);
+        assert_eq!(content_length(&framed), None);
+
+        let mut unframed = response(StatusCode::OK, Body::empty());
+        unframed
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from_static("42"));
+        assert_eq!(content_length(&frame(unframed, true)), Some("42"));
+    }
+
+    #[test]
+    fn not_modified_responses_keep_the_length_they_describe() {
+        let framed = frame(response(StatusCode::NOT_MODIFIED, Body::empty()), false);
+        assert_eq!(content_length(&framed), None);
+    }
+
+    /// Serves `handler` at a port of its own on the returned runtime.
+    fn serve(handler: RequestHandler) -> (Runtime, SocketAddr) {
+        let server = Server::builder()
+            .bind(([127, 0, 0, 1], 0))
+            .default_handler(handler)
+            .build()
+            .expect("failed to build the server");
+        let addr = server.local_addr().expect("bound to an address");
+        let mut runtime = Runtime::new().expect("failed to start the runtime");
+        runtime.spawn(
+            server
+                .serve()
+                .map_err(|err| panic!("server failed: {}", err)),
+        );
+        (runtime, addr)
+    }
+
+    /// Sends `req` to `addr` over a new HTTP/2 connection, with prior
+    /// knowledge of h2c.
+    fn send_h2(
+        addr: SocketAddr,
+        req: Request<Body>,
+    ) -> impl Future<Item = Response<Body>, Error = String> {
+        TcpStream::connect(&addr)
+            .map_err(|err| err.to_string())
+            .and_then(|stream| {
+                conn::Builder::new()
+                    .http2_only(true)
+                    .handshake(stream)
+                    .map_err(|err| err.to_string())
+            })
+            .and_then(|(mut sender, connection)| {
+                tokio::spawn(connection.map_err(|_| ()));
+                sender.send_request(req).map_err(|err| err.to_string())
+            })
+    }
+
+    #[test]
+    fn trailers_follow_streamed_bodies_over_http2() {
+        let (mut runtime, addr) = serve(handler::into_handler(|_: Request<Body>| {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+            reply::stream_with_trailers(
+                "application/grpc",
+                futures::stream::iter_ok::<_, hyper::Error>(vec!["hello"]),
+                future::ok::<_, hyper::Error>(trailers),
+            )
+        }));
+
+        let req = Request::get(&*format!("http://{}/", addr))
+            .body(Body::empty())
+            .expect("valid request");
+        let (version, body, trailers) = runtime
+            .block_on(send_h2(addr, req).and_then(|response| {
+                let version = response.version();
+                body::to_bytes_with_trailers(response.into_body(), 1024)
+                    .map(move |(body, trailers)| (version, body, trailers))
+                    .map_err(|err| err.to_string())
+            }))
+            .expect("request failed");
+
+        assert_eq!(version, Version::HTTP_2);
+        assert_eq!(&body[..], b"hello");
+        let trailers = trailers.expect("trailers were sent");
+        assert_eq!(
+            trailers.get("grpc-status").map(HeaderValue::as_bytes),
+            Some(&b"0"[..])
+        );
+    }
+
+    #[test]
+    fn clients_with_prior_knowledge_get_http2() {
+        let (mut runtime, addr) = serve(handler::into_handler(|_: Request<Body>| "hello"));
+
+        let req = Request::get(&*format!("http://{}/", addr))
+            .body(Body::empty())
+            .expect("valid request");
+        let response = runtime
+            .block_on(send_h2(addr, req))
+            .expect("request failed");
+        assert_eq!(response.version(), Version::HTTP_2);
+    }
+
+    /// Reads an HTTP/2 frame, returning its type, flags, stream and
+    /// payload.
+    fn read_frame(stream: &mut StdTcpStream) -> (u8, u8, u32, Vec<u8>) {
+        let mut header = [0; FRAME_HEADER_LEN];
+        stream
+            .read_exact(&mut header)
+            .expect("failed to read a frame");
+        let length = header[..3]
+            .iter()
+            .fold(0, |length, byte| (length << 8) | usize::from(*byte));
+        let id = header[5..]
+            .iter()
+            .fold(0, |id, byte| (id << 8) | u32::from(*byte))
+            & 0x7fff_ffff;
+        let mut payload = vec![0; length];
+        stream
+            .read_exact(&mut payload)
+            .expect("failed to read a frame");
+        (header[3], header[4], id, payload)
+    }
+
+    #[test]
+    fn upgrading_requests_are_answered_on_the_first_stream() {
+        let (_runtime, addr) = serve(handler::into_handler(|req: Request<Body>| {
+            format!("{} {}", req.version() == Version::HTTP_2, req.uri().path())
+        }));
+
+        let mut stream = StdTcpStream::connect(addr).expect("failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("failed to set a timeout");
+        stream
+            .write_all(
+                b"GET /upgraded HTTP/1.1\r\n\
+                  Host: localhost\r\n\
+                  Connection: Upgrade, HTTP2-Settings\r\n\
+                  Upgrade: h2c\r\n\
+                  HTTP2-Settings: AAMAAABkAAQAAP__\r\n\
+                  \r\n",
+            )
+            .expect("failed to send the request");
+
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream
+                .read_exact(&mut byte)
+                .expect("failed to read the response");
+            head.push(byte[0]);
+        }
+        assert!(head.starts_with(b"HTTP/1.1 101 "));
+
+        // The client's connection preface, with an empty SETTINGS frame.
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+            .expect("failed to send the preface");
+
+        let mut headers = false;
+        let mut body = vec![];
+        loop {
+            let (kind, flags, id, payload) = read_frame(&mut stream);
+            if id != 1 {
+                continue;
+            }
+            match kind {
+                0x1 => headers = true,
+                0x0 => body.extend_from_slice(&payload),
+                _ => panic!("unexpected frame of type {} on the first stream", kind),
+            }
+            if flags & 0x1 != 0 {
+                break;
+            }
+        }
+        assert!(headers);
+        assert_eq!(&body[..], b"true /upgraded");
+    }
+
+    #[test]
+    fn upgrade_requests_need_settings() {
+        let request = |headers: &[(&'static str, &'static str)]| {
+            let mut req = Request::new(Body::empty());
+            for (name, value) in headers {
+                req.headers_mut()
+                    .insert(*name, HeaderValue::from_static(*value));
+            }
+            req
+        };
+
+        assert!(wants_h2c(&request(&[
+            ("connection", "Upgrade, HTTP2-Settings"),
+            ("upgrade", "h2c"),
+            ("http2-settings", "AAMAAABkAAQAAP__"),
+        ])));
+        assert!(!wants_h2c(&request(&[
+            ("connection", "Upgrade"),
+            ("upgrade", "h2c"),
+            ("http2-settings", "AAMAAABkAAQAAP__"),
+        ])));
+        assert!(!wants_h2c(&request(&[
+            ("connection", "Upgrade, HTTP2-Settings"),
+            ("upgrade", "h2c"),
+        ])));
+        assert!(!wants_h2c(&request(&[
+            ("connection", "Upgrade, HTTP2-Settings"),
+            ("upgrade", "websocket"),
+            ("http2-settings", "AAMAAABkAAQAAP__"),
+        ])));
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either, Shared};
+use futures::sync::{mpsc, oneshot};
+use futures::{Async, Future, Poll, Stream};
+use tokio::timer::Delay;
+
+/// Stops a running server from accepting connections, letting those it
+/// accepted finish the requests they're handling. Clones stop the same
+/// server.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    drained: Shared<oneshot::Receiver<()>>,
+}
+
+impl ShutdownHandle {
+    /// Shuts the server down. Does nothing if it's already been shut down
+    /// or has stopped.
+    pub fn shutdown(&self) {
+        let sender = self
+            .sender
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(sender) = sender {
+            // The server having stopped already is fine.
+            let _ = sender.send(());
+        }
+    }
+
+    /// A future resolving once the server has shut down and every
+    /// connection it accepted is closed, or once it's gone.
+    pub fn drained(&self) -> Drained {
+        Drained(self.drained.clone())
+    }
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownHandle").finish()
+    }
+}
+
+/// The future returned by [`ShutdownHandle::drained`].
+#[must_use = "futures do nothing unless polled"]
+pub struct Drained(Shared<oneshot::Receiver<()>>);
+
+impl Future for Drained {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // The server going away without draining leaves nothing to
+            // wait for either.
+            Ok(Async::Ready(_)) | Err(_) => Ok(Async::Ready(())),
+        }
+    }
+}
+
+/// What a server stops on, whether a [`ShutdownHandle`] or a signal.
+pub(crate) struct Shutdown {
+    receiver: oneshot::Receiver<()>,
+    signals: bool,
+    drained: oneshot::Sender<()>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(signals: bool) -> (ShutdownHandle, Self) {
+        let (sender, receiver) = oneshot::channel();
+        let (drained, drained_receiver) = oneshot::channel();
+        let handle = ShutdownHandle {
+            sender: Arc::new(Mutex::new(Some(sender))),
+            drained: drained_receiver.shared(),
+        };
+        let shutdown = Shutdown {
+            receiver,
+            signals,
+            drained,
+        };
+        (handle, shutdown)
+    }
+
+    /// Runs `accept` until the server is to stop, then drains the
+    /// connections it accepted, giving them `timeout` to finish.
+    pub(crate) fn serve<A>(
+        self,
+        accept: A,
+        draining: Draining,
+        timeout: Duration,
+    ) -> impl Future<Item = (), Error = io::Error> + Send
+    where
+        A: Future<Item = (), Error = io::Error> + Send + 'static,
+    {
+        let drained = self.drained;
+        accept
+            .select(requested(self.receiver, self.signals))
+            .then(move |result| {
+                // Dropping what's left of accepting closes the listener.
+                let result = result.map(|_| ()).map_err(|(err, _)| err);
+                draining.drain(timeout).then(move |_| {
+                    let _ = drained.send(());
+                    result
+                })
+            })
+    }
+}
+
+/// Resolves once the server is to stop, which is never if every handle is
+/// dropped without being used and signals aren't handled.
+fn requested(
+    receiver: oneshot::Receiver<()>,
+    signals: bool,
+) -> impl Future<Item = (), Error = io::Error> + Send {
+    let requested = receiver.or_else(|_| future::empty());
+    if signals {
+        Either::A(
+            requested
+                .select(signal())
+                .map(|_| ())
+                .map_err(|(err, _)| err),
+        )
+    } else {
+        Either::B(requested)
+    }
+}
+
+/// Resolves on the first `SIGINT` or `SIGTERM`, or Ctrl-C on Windows.
+#[cfg(unix)]
+fn signal() -> impl Future<Item = (), Error = io::Error> + Send {
+    use tokio_signal::unix::{Signal, SIGTERM};
+
+    let terminate = Signal::new(SIGTERM)
+        .flatten_stream()
+        .into_future()
+        .map(|_| ())
+        .map_err(|(err, _)| err);
+    ctrl_c()
+        .select(terminate)
+        .map(|_| ())
+        .map_err(|(err, _)| err)
+}
+
+/// Resolves on the first `SIGINT` or `SIGTERM`, or Ctrl-C on Windows.
+#[cfg(not(unix))]
+fn signal() -> impl Future<Item = (), Error = io::Error> + Send {
+    ctrl_c()
+}
+
+fn ctrl_c() -> impl Future<Item = (), Error = io::Error> + Send {
+    tokio_signal::ctrl_c()
+        .flatten_stream()
+        .into_future()
+        .map(|_| ())
+        .map_err(|(err, _)| err)
+}
+
+/// Tells a connection when to close, held by every connection a server
+/// accepts until it's closed.
+#[derive(Clone)]
+pub(crate) struct Drain {
+    graceful: Shared<oneshot::Receiver<()>>,
+    force: Shared<oneshot::Receiver<()>>,
+    _tracker: mpsc::Sender<()>,
+}
+
+impl Drain {
+    /// Whether the connection is to close once it's done with the request
+    /// it's handling.
+    pub(crate) fn is_draining(&mut self) -> bool {
+        fired(&mut self.graceful)
+    }
+
+    /// Whether the connection is to close right away.
+    pub(crate) fn is_forced(&mut self) -> bool {
+        fired(&mut self.force)
+    }
+}
+
+/// Whether `signal` was sent, or can't be anymore as its sender is gone.
+fn fired(signal: &mut Shared<oneshot::Receiver<()>>) -> bool {
+    match signal.poll() {
+        Ok(Async::NotReady) => false,
+        Ok(Async::Ready(_)) | Err(_) => true,
+    }
+}
+
+/// Closes the connections holding a [`Drain`].
+pub(crate) struct Draining {
+    graceful: oneshot::Sender<()>,
+    force: oneshot::Sender<()>,
+    tracker: mpsc::Receiver<()>,
+}
+
+impl Draining {
+    /// Tells every connection to close once it's done with the request it's
+    /// handling, resolving once they all have, or after `timeout` having
+    /// closed the rest.
+    fn drain(self, timeout: Duration) -> impl Future<Item = (), Error = ()> + Send {
+        let Draining {
+            graceful,
+            force,
+            tracker,
+        } = self;
+        let _ = graceful.send(());
+
+        // The tracker ends once every connection dropped its drain.
+        let closed = tracker.for_each(|()| Ok(()));
+        let deadline = Delay::new(Instant::now() + timeout).then(|_| Ok(()));
+        closed.select(deadline).then(move |_| {
+            let _ = force.send(());
+            Ok(())
+        })
+    }
+}
+
+/// Creates the [`Drain`] to hand to connections, and what closes them.
+pub(crate) fn drain() -> (Drain, Draining) {
+    let (graceful, graceful_receiver) = oneshot::channel();
+    let (force, force_receiver) = oneshot::channel();
+    let (tracker, tracker_receiver) = mpsc::channel(0);
+    let drain = Drain {
+        graceful: graceful_receiver.shared(),
+        force: force_receiver.shared(),
+        _tracker: tracker,
+    };
+    let draining = Draining {
+        graceful,
+        force,
+        tracker: tracker_receiver,
+    };
+    (drain, draining)
+}
@@ -0,0 +1,275 @@
+use std::error::Error as StdError;
+use std::fmt::{self, Write};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::sync::mpsc;
+use futures::{Async, Future, Poll, Sink, Stream};
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Request, Response};
+use tokio::timer::Delay;
+
+/// One server-sent event. Line breaks in its id and event name are
+/// removed, as the format has no way to represent them, while those in its
+/// data are sent as several `data` lines and rejoined by the client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    /// An event carrying `data`.
+    pub fn new<S>(data: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Event {
+            data: Some(data.into()),
+            ..Event::default()
+        }
+    }
+
+    /// A comment, which clients ignore but which keeps the connection
+    /// from going idle.
+    pub fn comment<S>(comment: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Event {
+            comment: Some(comment.into()),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the id the client reports in `Last-Event-ID` when reconnecting.
+    pub fn id<S>(mut self, id: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// Sets the type of the event, which clients dispatch it by.
+    pub fn event<S>(mut self, event: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// Tells the client how long to wait before reconnecting if the
+    /// connection is lost.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(comment) = &self.comment {
+            for line in comment.lines() {
+                writeln!(f, ": {}", line)?;
+            }
+        }
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", id)?;
+        }
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", event)?;
+        }
+        if let Some(retry) = self.retry {
+            let millis = retry.as_secs() * 1000 + u64::from(retry.subsec_millis());
+            writeln!(f, "retry: {}", millis)?;
+        }
+        if let Some(data) = &self.data {
+            for line in data.lines() {
+                writeln!(f, "data: {}", line)?;
+            }
+            if data.is_empty() || data.ends_with('\n') {
+                writeln!(f, "data:")?;
+            }
+        }
+        f.write_char('\n')
+    }
+}
+
+fn single_line(value: String) -> String {
+    value.replace(|c: char| c == '\r' || c == '\n', "")
+}
+
+/// The id of the last event a reconnecting client received, if it sent
+/// one.
+pub fn last_event_id(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Builds `text/event-stream` responses.
+///
+/// By default a comment is sent after every 15 seconds without an event,
+/// so that proxies and clients don't give up on a quiet connection.
+#[derive(Clone, Copy, Debug)]
+pub struct Sse {
+    keep_alive: Option<Duration>,
+    buffer: usize,
+}
+
+impl Sse {
+    pub fn new() -> Self {
+        Sse::default()
+    }
+
+    /// Sends a keep-alive comment after `interval` without an event.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    /// Doesn't send keep-alive comments.
+    pub fn no_keep_alive(mut self) -> Self {
+        self.keep_alive = None;
+        self
+    }
+
+    /// Sets how many events [`Sse::channel`] holds while waiting for the
+    /// client to read them before sending waits for room. Defaults to 16.
+    pub fn buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// A response sending the events `events` produces, ending when the
+    /// stream does.
+    pub fn stream<S>(self, events: S) -> Response<Body>
+    where
+        S: Stream<Item = Event> + Send + 'static,
+        S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let events = KeepAlive {
+            events,
+            interval: self.keep_alive,
+            delay: self
+                .keep_alive
+                .map(|interval| Delay::new(Instant::now() + interval)),
+        };
+
+        let mut response = Response::new(Body::wrap_stream(events));
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+
+    /// A response sending the events pushed through the returned sender,
+    /// which a handler can hold on to, e.g. in a task it spawns. The
+    /// response ends once every clone of the sender is dropped.
+    pub fn channel(self) -> (EventSender, Response<Body>) {
+        let (sender, receiver) = mpsc::channel(self.buffer);
+        let events =
+            receiver.map_err(|()| -> hyper::Error { unreachable!("receivers never fail") });
+        (EventSender(sender), self.stream(events))
+    }
+}
+
+impl Default for Sse {
+    fn default() -> Self {
+        Sse {
+            keep_alive: Some(Duration::from_secs(15)),
+            buffer: 16,
+        }
+    }
+}
+
+/// Pushes events to the client of a response made by [`Sse::channel`].
+#[derive(Clone, Debug)]
+pub struct EventSender(mpsc::Sender<Event>);
+
+impl EventSender {
+    /// Sends `event`, waiting for room if the buffer is full, and resolves
+    /// to the sender so that more events can be sent. Fails once the
+    /// client has gone away.
+    pub fn send(self, event: Event) -> impl Future<Item = Self, Error = Disconnected> + Send {
+        self.0
+            .send(event)
+            .map(EventSender)
+            .map_err(|_| Disconnected)
+    }
+
+    /// Sends `event` if there's room for it, handing it back otherwise or
+    /// if the client has gone away.
+    pub fn try_send(&mut self, event: Event) -> Result<(), mpsc::TrySendError<Event>> {
+        self.0.try_send(event)
+    }
+
+    /// Whether the client has gone away.
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+/// The error sending an event fails with once the client has gone away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the client has disconnected")
+    }
+}
+
+impl StdError for Disconnected {}
+
+/// Formats the events of a stream, sending a comment whenever none has
+/// been sent for the keep-alive interval.
+struct KeepAlive<S> {
+    events: S,
+    interval: Option<Duration>,
+    delay: Option<Delay>,
+}
+
+impl<S> KeepAlive<S> {
+    fn reset(&mut self) {
+        if let (Some(interval), Some(delay)) = (self.interval, &mut self.delay) {
+            delay.reset(Instant::now() + interval);
+        }
+    }
+}
+
+impl<S> Stream for KeepAlive<S>
+where
+    S: Stream<Item = Event>,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Item = Bytes;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Self::Error> {
+        match self.events.poll().map_err(Into::into)? {
+            Async::Ready(Some(event)) => {
+                self.reset();
+                return Ok(Async::Ready(Some(Bytes::from(event.to_string()))));
+            }
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => {}
+        }
+
+        let elapsed = match &mut self.delay {
+            Some(delay) => delay.poll()?.is_ready(),
+            None => false,
+        };
+        if elapsed {
+            self.reset();
+            Ok(Async::Ready(Some(Bytes::from_static(b":\n\n"))))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+
+use crate::handler::RequestHandler;
+use crate::middleware::{from_fn, Middleware};
+
+/// State shared between every request a dispatcher handles, such as
+/// counters or fixtures, as found in the request extensions once it's
+/// registered with `Router::state`.
+pub struct State<T>(Arc<T>);
+
+impl<T> State<T> {
+    pub fn new(state: T) -> Self {
+        State(Arc::new(state))
+    }
+}
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Inserts a clone of the [`State`] into every request.
+impl<T> Middleware for State<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn wrap(&self, handler: RequestHandler) -> RequestHandler {
+        let state = self.clone();
+        from_fn(
+            move |mut req: Request<Body>, handler: &mut RequestHandler| {
+                req.extensions_mut().insert(state.clone());
+                handler.call(req)
+            },
+        )
+        .wrap(handler)
+    }
+}
@@ -0,0 +1,75 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::process;
+
+use crate::server::Listener;
+
+/// The first file descriptor systemd passes on, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listening sockets systemd passed on to the process when it
+/// activated it, along with the names they were given with
+/// `FileDescriptorName=`, or `unknown` for those without one. Any of them
+/// can be handed to `Builder::listener`.
+///
+/// Returns no sockets if the process wasn't socket activated. The
+/// `LISTEN_*` variables are removed from the environment, so neither a
+/// second call nor a child process takes the same sockets again.
+pub fn listeners() -> io::Result<Vec<(String, Listener)>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // The sockets are meant for another process if the PID doesn't match,
+    // e.g. for the parent of this one.
+    if pid.and_then(|pid| pid.parse().ok()) != Some(process::id()) {
+        return Ok(vec![]);
+    }
+    let count: RawFd = match fds.and_then(|fds| fds.parse().ok()) {
+        Some(count) => count,
+        None => return Ok(vec![]),
+    };
+    let mut names = names
+        .as_ref()
+        .map(String::as_str)
+        .unwrap_or_default()
+        .split(':')
+        .filter(|name| !name.is_empty());
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            let name = names.next().unwrap_or("unknown").to_string();
+            Ok((name, listener(fd)?))
+        })
+        .collect()
+}
+
+/// Takes ownership of the listening socket `fd`, working out whether it's
+/// a TCP or a unix socket from its local address.
+fn listener(fd: RawFd) -> io::Result<Listener> {
+    // systemd hands each descriptor to this process alone, and it's taken
+    // exactly once as `listeners` clears the environment.
+    let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+    if tcp.local_addr().is_ok() {
+        return Ok(Listener::Tcp(tcp));
+    }
+
+    let unix = unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    if unix.local_addr().is_ok() {
+        return Ok(Listener::Unix(unix));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "file descriptor {} passed by systemd isn't a TCP or unix socket",
+            fd
+        ),
+    ))
+}
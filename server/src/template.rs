@@ -0,0 +1,71 @@
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use handlebars::{Handlebars, RenderError, TemplateError, TemplateFileError};
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+lazy_static! {
+    static ref REGISTRY: RwLock<Arc<Handlebars>> = RwLock::new(Arc::new(Handlebars::new()));
+}
+
+/// A set of named handlebars templates, rendered by
+/// [`reply::render`](crate::reply::render) once installed:
+///
+/// ```ignore
+/// Templates::new()
+///     .template("greeting", "<p>Hello, {{name}}!</p>")?
+///     .file("network", "templates/network.hbs")?
+///     .install();
+/// ```
+pub struct Templates(Handlebars);
+
+impl Templates {
+    /// An empty set, in strict mode so that templates using a field the
+    /// context doesn't have fail to render rather than leaving it out.
+    pub fn new() -> Self {
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+        Templates(registry)
+    }
+
+    /// Adds the template `source` under `name`.
+    pub fn template(mut self, name: &str, source: &str) -> Result<Self, TemplateError> {
+        self.0.register_template_string(name, source)?;
+        Ok(self)
+    }
+
+    /// Adds the template in the file at `path` under `name`.
+    pub fn file<P>(mut self, name: &str, path: P) -> Result<Self, TemplateFileError>
+    where
+        P: AsRef<Path>,
+    {
+        self.0.register_template_file(name, path)?;
+        Ok(self)
+    }
+
+    /// Makes these the templates [`render`] draws from, replacing any that
+    /// were installed before.
+    pub fn install(self) {
+        let mut registry = REGISTRY.write().unwrap_or_else(PoisonError::into_inner);
+        *registry = Arc::new(self.0);
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Templates::new()
+    }
+}
+
+/// Renders the installed template `name` with `context`.
+pub fn render<T>(name: &str, context: &T) -> Result<String, RenderError>
+where
+    T: Serialize,
+{
+    let registry = REGISTRY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    registry.render(name, context)
+}
@@ -0,0 +1,349 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(feature = "native-tls")]
+use std::io::{Read, Write};
+use std::path::Path;
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+
+#[cfg(feature = "native-tls")]
+use native_tls_crate as native_tls;
+#[cfg(feature = "rustls")]
+use tokio_rustls::rustls::internal::pemfile;
+#[cfg(feature = "rustls")]
+use tokio_rustls::rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey,
+    RootCertStore, ServerConfig, Session, TLSError,
+};
+
+use crate::certificate::{Certificate, ClientCertificates};
+
+/// The certificate and key a server uses to serve HTTPS, along with the
+/// rest of its TLS settings.
+///
+/// Either rustls or the platform's TLS library (SChannel, Secure Transport
+/// or OpenSSL) does the work, depending on the constructor, which are
+/// behind the `rustls` and `native-tls` features respectively. Only rustls
+/// can ask clients for certificates.
+#[derive(Clone)]
+pub struct TlsConfig {
+    backend: Backend,
+    #[cfg(feature = "rustls")]
+    client_auth: ClientAuth,
+    #[cfg(feature = "rustls")]
+    roots: RootCertStore,
+    #[cfg(feature = "rustls")]
+    rejection: Rejection,
+}
+
+#[derive(Clone)]
+enum Backend {
+    #[cfg(feature = "rustls")]
+    Rustls(ServerConfig),
+    #[cfg(feature = "native-tls")]
+    Native(native_tls::TlsAcceptor),
+}
+
+/// The TLS backend a [`TlsConfig`] uses, ready to accept connections.
+#[derive(Clone)]
+pub(crate) enum Acceptor {
+    #[cfg(feature = "rustls")]
+    Rustls(tokio_rustls::TlsAcceptor),
+    #[cfg(feature = "native-tls")]
+    Native(tokio_tls::TlsAcceptor),
+}
+
+/// Whether clients are asked to authenticate with a certificate.
+#[cfg(feature = "rustls")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Clients aren't asked for a certificate.
+    None,
+    /// Clients are asked for a certificate but may go without one.
+    Optional,
+    /// Clients have to present a certificate.
+    Required,
+}
+
+/// What happens to clients that don't present a certificate when one is
+/// required.
+#[cfg(feature = "rustls")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The handshake fails.
+    Handshake,
+    /// The handshake succeeds, but every request is answered with `403
+    /// Forbidden`, which is easier to tell apart from other failures.
+    Forbidden,
+}
+
+impl TlsConfig {
+    /// Builds a rustls configuration from a PEM encoded certificate chain,
+    /// leaf first, and the PEM encoded private key of the leaf, either in
+    /// PKCS #8 or in PKCS #1 form. HTTP/2 is offered to clients through
+    /// ALPN along with HTTP/1.1.
+    #[cfg(feature = "rustls")]
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> Result<Self, TlsError> {
+        let cert_chain = pemfile::certs(&mut &*cert_chain)
+            .ok()
+            .filter(|certs| !certs.is_empty())
+            .ok_or(TlsError::InvalidCertificate)?;
+        let key = private_key(key).ok_or(TlsError::InvalidKey)?;
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(cert_chain, key)
+            .map_err(TlsError::Rustls)?;
+        config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        Ok(TlsConfig::from_rustls(config))
+    }
+
+    /// Like [`TlsConfig::from_pem`], but reads the certificate chain and
+    /// key from the files at the given paths.
+    #[cfg(feature = "rustls")]
+    pub fn from_pem_files<P, Q>(cert_chain: P, key: Q) -> Result<Self, TlsError>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let cert_chain = fs::read(cert_chain).map_err(TlsError::Io)?;
+        let key = fs::read(key).map_err(TlsError::Io)?;
+        TlsConfig::from_pem(&cert_chain, &key)
+    }
+
+    /// Uses a rustls configuration built by hand, for settings the other
+    /// constructors don't cover. Its client certificate verifier is
+    /// replaced unless client authentication is left off, and connections
+    /// are served with HTTP/2 if it negotiates `h2` through ALPN.
+    #[cfg(feature = "rustls")]
+    pub fn from_rustls(config: ServerConfig) -> Self {
+        TlsConfig::new(Backend::Rustls(config))
+    }
+
+    /// Builds a native-tls configuration from a DER encoded PKCS #12
+    /// archive holding the certificate chain and private key, decrypted
+    /// with `password`. Native-tls can't negotiate protocols, so only
+    /// HTTP/1.1 is served over it.
+    #[cfg(feature = "native-tls")]
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Result<Self, TlsError> {
+        let identity =
+            native_tls::Identity::from_pkcs12(der, password).map_err(TlsError::NativeTls)?;
+        let acceptor = native_tls::TlsAcceptor::new(identity).map_err(TlsError::NativeTls)?;
+        Ok(TlsConfig::from_native_tls(acceptor))
+    }
+
+    /// Like [`TlsConfig::from_pkcs12`], but reads the archive from the file
+    /// at `path`.
+    #[cfg(feature = "native-tls")]
+    pub fn from_pkcs12_file<P>(path: P, password: &str) -> Result<Self, TlsError>
+    where
+        P: AsRef<Path>,
+    {
+        let der = fs::read(path).map_err(TlsError::Io)?;
+        TlsConfig::from_pkcs12(&der, password)
+    }
+
+    /// Uses a native-tls acceptor built by hand, e.g. to restrict the
+    /// protocol versions it allows.
+    #[cfg(feature = "native-tls")]
+    pub fn from_native_tls(acceptor: native_tls::TlsAcceptor) -> Self {
+        TlsConfig::new(Backend::Native(acceptor))
+    }
+
+    fn new(backend: Backend) -> Self {
+        TlsConfig {
+            backend,
+            #[cfg(feature = "rustls")]
+            client_auth: ClientAuth::None,
+            #[cfg(feature = "rustls")]
+            roots: RootCertStore::empty(),
+            #[cfg(feature = "rustls")]
+            rejection: Rejection::Handshake,
+        }
+    }
+
+    /// Asks clients for certificates issued by one of the CAs in the PEM
+    /// encoded `ca_bundle`, as `mode` says. The certificates clients
+    /// authenticate with are found in the request extensions as
+    /// [`ClientCertificates`].
+    ///
+    /// Clients presenting a certificate no CA in the bundle issued always
+    /// fail the handshake. Has no effect on native-tls configurations.
+    #[cfg(feature = "rustls")]
+    pub fn client_auth(mut self, mode: ClientAuth, ca_bundle: &[u8]) -> Result<Self, TlsError> {
+        let mut roots = RootCertStore::empty();
+        if mode != ClientAuth::None {
+            match roots.add_pem_file(&mut &*ca_bundle) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => return Err(TlsError::InvalidCertificate),
+            }
+        }
+
+        self.client_auth = mode;
+        self.roots = roots;
+        Ok(self)
+    }
+
+    /// Like [`TlsConfig::client_auth`], but reads the CA bundle from the
+    /// file at `path`.
+    #[cfg(feature = "rustls")]
+    pub fn client_auth_file<P>(self, mode: ClientAuth, ca_bundle: P) -> Result<Self, TlsError>
+    where
+        P: AsRef<Path>,
+    {
+        let ca_bundle = fs::read(ca_bundle).map_err(TlsError::Io)?;
+        self.client_auth(mode, &ca_bundle)
+    }
+
+    /// Sets what happens to clients that don't present a certificate when
+    /// one is required. They fail the handshake by default.
+    #[cfg(feature = "rustls")]
+    pub fn reject_with(mut self, rejection: Rejection) -> Self {
+        self.rejection = rejection;
+        self
+    }
+
+    /// Whether requests from clients that didn't present a certificate are
+    /// to be answered with `403 Forbidden`.
+    pub(crate) fn forbids_anonymous(&self) -> bool {
+        match self.backend {
+            #[cfg(feature = "rustls")]
+            Backend::Rustls(_) => {
+                self.client_auth == ClientAuth::Required && self.rejection == Rejection::Forbidden
+            }
+            #[cfg(feature = "native-tls")]
+            Backend::Native(_) => false,
+        }
+    }
+
+    pub(crate) fn acceptor(&self) -> Acceptor {
+        match &self.backend {
+            #[cfg(feature = "rustls")]
+            Backend::Rustls(config) => {
+                let mut config = config.clone();
+                let roots = self.roots.clone();
+                match self.client_auth {
+                    ClientAuth::None => {}
+                    ClientAuth::Required if self.rejection == Rejection::Handshake => {
+                        config.set_client_certificate_verifier(AllowAnyAuthenticatedClient::new(
+                            roots,
+                        ));
+                    }
+                    ClientAuth::Optional | ClientAuth::Required => {
+                        config.set_client_certificate_verifier(
+                            AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+                        );
+                    }
+                }
+                Acceptor::Rustls(Arc::new(config).into())
+            }
+            #[cfg(feature = "native-tls")]
+            Backend::Native(acceptor) => Acceptor::Native(acceptor.clone().into()),
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+fn private_key(pem: &[u8]) -> Option<PrivateKey> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut &*pem).unwrap_or_default();
+    if let Some(key) = pkcs8.into_iter().next() {
+        return Some(key);
+    }
+    pemfile::rsa_private_keys(&mut &*pem)
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+}
+
+/// A TLS stream that can tell what was agreed on during the handshake.
+pub(crate) trait TlsSession {
+    /// The certificates the client authenticated with.
+    fn peer_certificates(&self) -> Option<ClientCertificates>;
+
+    /// The application protocol negotiated through ALPN.
+    fn alpn_protocol(&self) -> Option<Vec<u8>>;
+}
+
+#[cfg(feature = "rustls")]
+impl<S> TlsSession for tokio_rustls::server::TlsStream<S> {
+    fn peer_certificates(&self) -> Option<ClientCertificates> {
+        let ders = self
+            .get_ref()
+            .1
+            .get_peer_certificates()?
+            .into_iter()
+            .map(|certificate| certificate.0);
+        client_certificates(ders)
+    }
+
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.get_ref().1.get_alpn_protocol().map(<[u8]>::to_vec)
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S> TlsSession for tokio_tls::TlsStream<S>
+where
+    S: Read + Write,
+{
+    fn peer_certificates(&self) -> Option<ClientCertificates> {
+        let certificate = self.get_ref().peer_certificate().ok()??;
+        client_certificates(certificate.to_der().ok())
+    }
+
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Reads a certificate chain, returning `None` if it's empty or one of the
+/// certificates is malformed.
+fn client_certificates<I>(ders: I) -> Option<ClientCertificates>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let certificates = ders
+        .into_iter()
+        .map(Certificate::from_der)
+        .collect::<Option<Vec<_>>>()?;
+    if certificates.is_empty() {
+        None
+    } else {
+        Some(ClientCertificates(certificates))
+    }
+}
+
+/// The error building a [`TlsConfig`] fails with.
+#[derive(Debug)]
+pub enum TlsError {
+    /// A file couldn't be read.
+    Io(io::Error),
+    /// There's no valid certificate in the certificate chain or CA bundle.
+    InvalidCertificate,
+    /// There's no valid private key.
+    InvalidKey,
+    /// Rustls rejected the certificate or key.
+    #[cfg(feature = "rustls")]
+    Rustls(TLSError),
+    /// The platform's TLS library rejected the certificate or key.
+    #[cfg(feature = "native-tls")]
+    NativeTls(native_tls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io(err) => write!(f, "failed to read file: {}", err),
+            TlsError::InvalidCertificate => write!(f, "no valid PEM certificate found"),
+            TlsError::InvalidKey => write!(f, "no valid PEM private key found"),
+            #[cfg(feature = "rustls")]
+            TlsError::Rustls(err) => write!(f, "invalid certificate or key: {}", err),
+            #[cfg(feature = "native-tls")]
+            TlsError::NativeTls(err) => write!(f, "invalid certificate or key: {}", err),
+        }
+    }
+}
+
+impl Error for TlsError {}